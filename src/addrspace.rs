@@ -0,0 +1,185 @@
+use core::fmt::{self, Display};
+
+use crate::getenv;
+
+/// Virtual address space reserved for each linear memory slot by default,
+/// the static memory reservation of Wasmtime on 64-bit hosts
+const MEMORY_RESERVATION: u64 = 4 << 30;
+
+/// Guard region following each linear memory slot by default
+const MEMORY_GUARD_SIZE: u64 = 2 << 30;
+
+/// Default size of async stacks
+const ASYNC_STACK_SIZE: u64 = 2 << 20;
+
+const PAGE_SIZE: u64 = 4 << 10;
+
+/// Approximate footprint of the pooling allocator with `slots` component instance slots,
+/// counting linear memory and stack slots, which dominate it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Footprint {
+    /// Virtual address space reserved in a single contiguous mapping, in bytes
+    memories: u64,
+    /// Virtual address space of stacks, in bytes, committed on creation
+    stacks: u64,
+    /// Memory mappings once all slots are in use, split by guard pages
+    maps: u64,
+}
+
+impl Footprint {
+    /// Estimates the footprint of [`crate::new_pooling_config`] with `slots`
+    /// and `max_memory_size`, honoring the same environment overrides
+    fn new(slots: u32, max_memory_size: usize) -> Self {
+        let memories = u64::from(getenv("WASMTIME_POOLING_TOTAL_MEMORIES").unwrap_or(slots));
+        let stacks = u64::from(getenv("WASMTIME_POOLING_TOTAL_STACKS").unwrap_or(slots));
+        let memory_slot = u64::try_from(max_memory_size)
+            .unwrap_or(u64::MAX)
+            .max(MEMORY_RESERVATION)
+            .saturating_add(MEMORY_GUARD_SIZE);
+        let stack_slot = getenv::<u64>("WASMTIME_ASYNC_STACK_SIZE")
+            .unwrap_or(ASYNC_STACK_SIZE)
+            .next_multiple_of(PAGE_SIZE)
+            .saturating_add(PAGE_SIZE);
+        Self {
+            memories: memories.saturating_mul(memory_slot),
+            stacks: stacks.saturating_mul(stack_slot),
+            // accessible and guard regions of each slot
+            maps: memories.saturating_add(stacks).saturating_mul(2),
+        }
+    }
+}
+
+/// Address space limit of the process the pooling allocator reservations must fit in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Limit {
+    /// Largest free range between existing mappings, where ASLR placed them
+    Gap(u64),
+    /// Remainder of the `RLIMIT_AS` soft limit
+    RlimitAs(u64),
+    /// Remainder of `vm.max_map_count`
+    MaxMapCount(u64),
+    /// Remainder of `CommitLimit` with `vm.overcommit_memory` set to 2
+    CommitLimit(u64),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gap(n) => write!(f, "the largest free address range of {n} bytes"),
+            Self::RlimitAs(n) => write!(f, "the remaining {n} bytes of `RLIMIT_AS`"),
+            Self::MaxMapCount(n) => write!(f, "the remaining {n} mappings of `vm.max_map_count`"),
+            Self::CommitLimit(n) => {
+                write!(
+                    f,
+                    "the remaining {n} bytes of strict overcommit `CommitLimit`"
+                )
+            }
+        }
+    }
+}
+
+impl Limit {
+    fn fits(self, footprint: &Footprint) -> bool {
+        match self {
+            Self::Gap(n) => footprint.memories <= n,
+            Self::RlimitAs(n) => footprint.memories.saturating_add(footprint.stacks) <= n,
+            Self::MaxMapCount(n) => footprint.maps <= n,
+            Self::CommitLimit(n) => footprint.stacks <= n,
+        }
+    }
+}
+
+/// Returns the address ranges of all mappings of the process, sorted by start address
+fn mappings() -> Vec<(u64, u64)> {
+    let Ok(maps) = std::fs::read_to_string("/proc/self/maps") else {
+        return Vec::new();
+    };
+    let mut ranges: Vec<_> = maps
+        .lines()
+        .filter_map(|line| {
+            let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+            let start = u64::from_str_radix(start, 16).ok()?;
+            let end = u64::from_str_radix(end, 16).ok()?;
+            Some((start, end))
+        })
+        .collect();
+    ranges.sort_unstable();
+    ranges
+}
+
+fn read_sysctl(path: &str) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn read_meminfo(key: &str) -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kib = meminfo.lines().find_map(|line| line.strip_prefix(key))?;
+    let kib = kib.strip_prefix(':')?.trim().trim_end_matches("kB").trim();
+    kib.parse::<u64>().ok().map(|kib| kib.saturating_mul(1024))
+}
+
+/// Address space available to the process, read once before the engine is constructed
+#[derive(Clone, Debug, Default)]
+pub struct Budget(Vec<Limit>);
+
+impl Budget {
+    /// Reads the mappings, limits and overcommit settings of the process,
+    /// skipping those which cannot be read
+    pub fn read() -> Self {
+        let mut limits = Vec::new();
+        let ranges = mappings();
+        // the stack is the highest mapping of the user address space, ignoring `[vsyscall]`
+        let top = ranges
+            .iter()
+            .map(|(_, end)| *end)
+            .filter(|end| *end <= 1 << 56)
+            .max();
+        if let Some(top) = top {
+            let mut gap = 0;
+            let mut prev = read_sysctl("/proc/sys/vm/mmap_min_addr").unwrap_or(PAGE_SIZE);
+            for (start, end) in ranges.iter().filter(|(_, end)| *end <= top) {
+                gap = gap.max(start.saturating_sub(prev));
+                prev = prev.max(*end);
+            }
+            limits.push(Limit::Gap(gap));
+        }
+        let size: u64 = ranges.iter().map(|(start, end)| end - start).sum();
+        if let Ok(limit) = rlimit::Resource::AS.get_soft() {
+            if limit != rlimit::INFINITY {
+                limits.push(Limit::RlimitAs(limit.saturating_sub(size)));
+            }
+        }
+        if let Some(max) = read_sysctl("/proc/sys/vm/max_map_count") {
+            limits.push(Limit::MaxMapCount(max.saturating_sub(ranges.len() as u64)));
+        }
+        if read_sysctl("/proc/sys/vm/overcommit_memory") == Some(2) {
+            if let (Some(limit), Some(committed)) =
+                (read_meminfo("CommitLimit"), read_meminfo("Committed_AS"))
+            {
+                limits.push(Limit::CommitLimit(limit.saturating_sub(committed)));
+            }
+        }
+        Self(limits)
+    }
+
+    /// Returns the first limit the footprint of `slots` with `max_memory_size` exceeds, if any
+    pub fn exceeded(&self, slots: u32, max_memory_size: usize) -> Option<impl Display> {
+        let footprint = Footprint::new(slots, max_memory_size);
+        self.0.iter().copied().find(|limit| !limit.fits(&footprint))
+    }
+
+    /// Returns the largest number of slots, at most `slots`, whose footprint fits,
+    /// or 0 if not even a single one does
+    pub fn fit(&self, slots: u32, max_memory_size: usize) -> u32 {
+        let (mut lo, mut hi) = (0, slots);
+        while lo < hi {
+            let mid = lo + (hi - lo).div_ceil(2);
+            if self.exceeded(mid, max_memory_size).is_none() {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        lo
+    }
+}
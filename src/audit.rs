@@ -0,0 +1,457 @@
+use core::future::Future;
+use core::mem;
+use core::net::SocketAddr;
+
+use std::fs::File;
+use std::io::{LineWriter, Write as _};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use serde::Serialize;
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::bindings::filesystem::types::{
+    self, Advice, Descriptor, DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry,
+    DirectoryEntryStream, ErrorCode, Filesize, HostDescriptor, HostDirectoryEntryStream,
+    MetadataHashValue, NewTimestamp, OpenFlags, PathFlags,
+};
+use wasmtime_wasi::bindings::io::streams::{InputStream, OutputStream};
+use wasmtime_wasi::pipe::{ClosedInputStream, ClosedOutputStream};
+use wasmtime_wasi::{FsError, FsResult, SocketAddrUse, WasiImpl, WasiView as _};
+
+use crate::chaos::{Chaos, FaultyStream, Target};
+use crate::Ctx;
+
+/// Security-relevant host operation performed on behalf of a guest
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// `wasi:filesystem` open of `path`, relative to a guest descriptor
+    Open {
+        path: &'a str,
+        write: bool,
+        create: bool,
+        truncate: bool,
+        ok: bool,
+    },
+    /// `wasi:sockets` connect or datagram destination check
+    Connect {
+        addr: SocketAddr,
+        protocol: &'static str,
+        allowed: bool,
+    },
+    /// Outgoing `wasi:http` request
+    Http {
+        method: &'a str,
+        uri: &'a str,
+        allowed: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Host wall clock time in milliseconds since UNIX epoch
+    time_ms: u128,
+    instance: usize,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Audit log file shared by all instances, one JSON object per line
+pub struct Log(Mutex<LineWriter<File>>);
+
+impl Log {
+    /// Creates or truncates the log file at `path`
+    pub fn create(path: &Path) -> anyhow::Result<Arc<Self>> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create `{}`", path.display()))?;
+        Ok(Arc::new(Self(Mutex::new(LineWriter::new(file)))))
+    }
+}
+
+/// Audit log of a single instance
+#[derive(Clone)]
+pub struct Audit {
+    log: Arc<Log>,
+    index: usize,
+}
+
+impl Audit {
+    pub fn new(log: Arc<Log>, index: usize) -> Self {
+        Self { log, index }
+    }
+
+    /// Appends `event` to the log, failures are reported but do not affect the guest
+    pub fn record(&self, event: Event<'_>) {
+        let record = Record {
+            time_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            instance: self.index,
+            event,
+        };
+        let mut buf = match serde_json::to_vec(&record) {
+            Ok(buf) => buf,
+            Err(err) => {
+                eprintln!("failed to encode audit record: {err}");
+                return;
+            }
+        };
+        buf.push(b'\n');
+        let mut file = self.log.0.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = file.write_all(&buf) {
+            eprintln!("failed to write audit record: {err}");
+        }
+    }
+
+    /// Records a socket address check of `usage`
+    pub fn connect(&self, addr: SocketAddr, usage: SocketAddrUse, allowed: bool) {
+        let protocol = match usage {
+            SocketAddrUse::TcpBind | SocketAddrUse::UdpBind => return,
+            SocketAddrUse::TcpConnect => "tcp",
+            SocketAddrUse::UdpConnect | SocketAddrUse::UdpOutgoingDatagram => "udp",
+        };
+        self.record(Event::Connect {
+            addr,
+            protocol,
+            allowed,
+        });
+    }
+}
+
+/// `wasi:filesystem/types` implementation recording opens to the audit log
+/// and injecting `--chaos` faults, if enabled
+pub struct Filesystem<'a> {
+    fs: WasiImpl<&'a mut Ctx>,
+    audit: Option<Audit>,
+    chaos: Option<Arc<Chaos>>,
+}
+
+impl<'a> Filesystem<'a> {
+    pub fn new(ctx: &'a mut Ctx) -> Self {
+        let audit = ctx.audit.clone();
+        let chaos = ctx.chaos.clone();
+        Self {
+            fs: WasiImpl(ctx),
+            audit,
+            chaos,
+        }
+    }
+
+    /// Delays the operation and fails it with an I/O error, if drawn by the fault injector.
+    ///
+    /// The future does not borrow `self`, which is not `Sync`
+    fn fault(&self) -> impl Future<Output = FsResult<()>> + Send + 'static {
+        let chaos = self.chaos.clone();
+        async move {
+            match chaos {
+                Some(chaos) if chaos.inject(Target::Fs).await => Err(ErrorCode::Io.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Wraps `stream` to inject faults into its reads, if enabled
+    fn faulty_input(&mut self, stream: Resource<InputStream>) -> FsResult<Resource<InputStream>> {
+        if let Some(chaos) = &self.chaos {
+            let inner = self.fs.table().get_mut(&stream)?;
+            let faulty = FaultyStream::new(
+                mem::replace(inner, Box::new(ClosedInputStream)),
+                Arc::clone(chaos),
+            );
+            *inner = Box::new(faulty);
+        }
+        Ok(stream)
+    }
+
+    /// Wraps `stream` to inject faults into its writes, if enabled
+    fn faulty_output(
+        &mut self,
+        stream: Resource<OutputStream>,
+    ) -> FsResult<Resource<OutputStream>> {
+        if let Some(chaos) = &self.chaos {
+            let inner = self.fs.table().get_mut(&stream)?;
+            let faulty = FaultyStream::new(
+                mem::replace(inner, Box::new(ClosedOutputStream)),
+                Arc::clone(chaos),
+            );
+            *inner = Box::new(faulty);
+        }
+        Ok(stream)
+    }
+}
+
+/// Replaces `wasi:filesystem/types` in the linker with one recording opens
+pub fn add_to_linker(
+    linker: &mut Linker<Ctx>,
+    f: impl Fn(&mut Ctx) -> Filesystem<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.allow_shadowing(true);
+    let res = types::add_to_linker_get_host(linker, f);
+    linker.allow_shadowing(false);
+    res
+}
+
+#[async_trait::async_trait]
+impl types::Host for Filesystem<'_> {
+    fn convert_error_code(&mut self, err: FsError) -> anyhow::Result<ErrorCode> {
+        self.fs.convert_error_code(err)
+    }
+
+    fn filesystem_error_code(
+        &mut self,
+        err: Resource<anyhow::Error>,
+    ) -> anyhow::Result<Option<ErrorCode>> {
+        self.fs.filesystem_error_code(err)
+    }
+}
+
+#[async_trait::async_trait]
+impl HostDescriptor for Filesystem<'_> {
+    async fn open_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        path_flags: PathFlags,
+        path: String,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Resource<Descriptor>> {
+        self.fault().await?;
+        let Some(audit) = self.audit.clone() else {
+            return self.fs.open_at(fd, path_flags, path, oflags, flags).await;
+        };
+        let event = |ok| Event::Open {
+            path: &path,
+            write: flags.contains(DescriptorFlags::WRITE),
+            create: oflags.contains(OpenFlags::CREATE),
+            truncate: oflags.contains(OpenFlags::TRUNCATE),
+            ok,
+        };
+        let res = self
+            .fs
+            .open_at(fd, path_flags, path.clone(), oflags, flags)
+            .await;
+        audit.record(event(res.is_ok()));
+        res
+    }
+
+    async fn advise(
+        &mut self,
+        fd: Resource<Descriptor>,
+        offset: Filesize,
+        len: Filesize,
+        advice: Advice,
+    ) -> FsResult<()> {
+        self.fs.advise(fd, offset, len, advice).await
+    }
+
+    async fn sync_data(&mut self, fd: Resource<Descriptor>) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.sync_data(fd).await
+    }
+
+    async fn get_flags(&mut self, fd: Resource<Descriptor>) -> FsResult<DescriptorFlags> {
+        self.fs.get_flags(fd).await
+    }
+
+    async fn get_type(&mut self, fd: Resource<Descriptor>) -> FsResult<DescriptorType> {
+        self.fs.get_type(fd).await
+    }
+
+    async fn set_size(&mut self, fd: Resource<Descriptor>, size: Filesize) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.set_size(fd, size).await
+    }
+
+    async fn set_times(
+        &mut self,
+        fd: Resource<Descriptor>,
+        atim: NewTimestamp,
+        mtim: NewTimestamp,
+    ) -> FsResult<()> {
+        self.fs.set_times(fd, atim, mtim).await
+    }
+
+    async fn read(
+        &mut self,
+        fd: Resource<Descriptor>,
+        len: Filesize,
+        offset: Filesize,
+    ) -> FsResult<(Vec<u8>, bool)> {
+        self.fault().await?;
+        self.fs.read(fd, len, offset).await
+    }
+
+    async fn write(
+        &mut self,
+        fd: Resource<Descriptor>,
+        buf: Vec<u8>,
+        offset: Filesize,
+    ) -> FsResult<Filesize> {
+        self.fault().await?;
+        self.fs.write(fd, buf, offset).await
+    }
+
+    async fn read_directory(
+        &mut self,
+        fd: Resource<Descriptor>,
+    ) -> FsResult<Resource<DirectoryEntryStream>> {
+        self.fault().await?;
+        self.fs.read_directory(fd).await
+    }
+
+    async fn sync(&mut self, fd: Resource<Descriptor>) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.sync(fd).await
+    }
+
+    async fn create_directory_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        path: String,
+    ) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.create_directory_at(fd, path).await
+    }
+
+    async fn stat(&mut self, fd: Resource<Descriptor>) -> FsResult<DescriptorStat> {
+        self.fault().await?;
+        self.fs.stat(fd).await
+    }
+
+    async fn stat_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        path_flags: PathFlags,
+        path: String,
+    ) -> FsResult<DescriptorStat> {
+        self.fault().await?;
+        self.fs.stat_at(fd, path_flags, path).await
+    }
+
+    async fn set_times_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        path_flags: PathFlags,
+        path: String,
+        atim: NewTimestamp,
+        mtim: NewTimestamp,
+    ) -> FsResult<()> {
+        self.fs.set_times_at(fd, path_flags, path, atim, mtim).await
+    }
+
+    async fn link_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        old_path_flags: PathFlags,
+        old_path: String,
+        new_descriptor: Resource<Descriptor>,
+        new_path: String,
+    ) -> FsResult<()> {
+        self.fs
+            .link_at(fd, old_path_flags, old_path, new_descriptor, new_path)
+            .await
+    }
+
+    fn drop(&mut self, fd: Resource<Descriptor>) -> anyhow::Result<()> {
+        HostDescriptor::drop(&mut self.fs, fd)
+    }
+
+    async fn readlink_at(&mut self, fd: Resource<Descriptor>, path: String) -> FsResult<String> {
+        self.fs.readlink_at(fd, path).await
+    }
+
+    async fn remove_directory_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        path: String,
+    ) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.remove_directory_at(fd, path).await
+    }
+
+    async fn rename_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        old_path: String,
+        new_fd: Resource<Descriptor>,
+        new_path: String,
+    ) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.rename_at(fd, old_path, new_fd, new_path).await
+    }
+
+    async fn symlink_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        src_path: String,
+        dest_path: String,
+    ) -> FsResult<()> {
+        self.fs.symlink_at(fd, src_path, dest_path).await
+    }
+
+    async fn unlink_file_at(&mut self, fd: Resource<Descriptor>, path: String) -> FsResult<()> {
+        self.fault().await?;
+        self.fs.unlink_file_at(fd, path).await
+    }
+
+    fn read_via_stream(
+        &mut self,
+        fd: Resource<Descriptor>,
+        offset: Filesize,
+    ) -> FsResult<Resource<InputStream>> {
+        let stream = self.fs.read_via_stream(fd, offset)?;
+        self.faulty_input(stream)
+    }
+
+    fn write_via_stream(
+        &mut self,
+        fd: Resource<Descriptor>,
+        offset: Filesize,
+    ) -> FsResult<Resource<OutputStream>> {
+        let stream = self.fs.write_via_stream(fd, offset)?;
+        self.faulty_output(stream)
+    }
+
+    fn append_via_stream(&mut self, fd: Resource<Descriptor>) -> FsResult<Resource<OutputStream>> {
+        let stream = self.fs.append_via_stream(fd)?;
+        self.faulty_output(stream)
+    }
+
+    async fn is_same_object(
+        &mut self,
+        a: Resource<Descriptor>,
+        b: Resource<Descriptor>,
+    ) -> anyhow::Result<bool> {
+        self.fs.is_same_object(a, b).await
+    }
+
+    async fn metadata_hash(&mut self, fd: Resource<Descriptor>) -> FsResult<MetadataHashValue> {
+        self.fs.metadata_hash(fd).await
+    }
+
+    async fn metadata_hash_at(
+        &mut self,
+        fd: Resource<Descriptor>,
+        path_flags: PathFlags,
+        path: String,
+    ) -> FsResult<MetadataHashValue> {
+        self.fs.metadata_hash_at(fd, path_flags, path).await
+    }
+}
+
+#[async_trait::async_trait]
+impl HostDirectoryEntryStream for Filesystem<'_> {
+    async fn read_directory_entry(
+        &mut self,
+        stream: Resource<DirectoryEntryStream>,
+    ) -> FsResult<Option<DirectoryEntry>> {
+        self.fs.read_directory_entry(stream).await
+    }
+
+    fn drop(&mut self, stream: Resource<DirectoryEntryStream>) -> anyhow::Result<()> {
+        HostDirectoryEntryStream::drop(&mut self.fs, stream)
+    }
+}
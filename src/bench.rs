@@ -0,0 +1,230 @@
+use core::num::NonZeroUsize;
+use core::time::Duration;
+
+use std::process::ExitCode;
+use std::time::Instant;
+
+use anyhow::{ensure, Context as _};
+use serde::Serialize;
+
+use crate::report::{ProcessMemory, Stats};
+use crate::Outcome;
+
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+pub struct Args {
+    /// Number of times to run the component in the pool, summarizing all instances of all runs
+    #[clap(long, default_value = "1")]
+    repeat: NonZeroUsize,
+
+    /// Print the summary as JSON
+    #[clap(long)]
+    json: bool,
+
+    #[command(flatten)]
+    run: crate::Args,
+}
+
+/// Distribution of a metric across instances
+#[derive(Debug, Default, Serialize)]
+struct Summary {
+    samples: usize,
+    min: u64,
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    max: u64,
+    mean: u64,
+}
+
+impl Summary {
+    fn new(mut samples: Vec<u64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+        // nearest-rank percentile
+        let percentile = |p: usize| samples[(samples.len() * p).div_ceil(100).max(1) - 1];
+        let sum: u128 = samples.iter().copied().map(u128::from).sum();
+        Self {
+            samples: samples.len(),
+            min: samples[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: samples[samples.len() - 1],
+            mean: (sum / samples.len() as u128).try_into().unwrap_or(u64::MAX),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    runs: usize,
+    instances: usize,
+    succeeded: usize,
+    wall_usec: u64,
+    peak_rss_bytes: Option<u64>,
+    instantiate_usec: Summary,
+    run_usec: Summary,
+    cpu_usage_usec: Summary,
+    cpu_user_usec: Summary,
+    cpu_system_usec: Summary,
+    memory_peak_bytes: Summary,
+    /// Memory usage of the whole process, shared by all instances
+    process_memory: Option<ProcessMemory>,
+    store_memory_bytes: Summary,
+    store_table_elements: Summary,
+}
+
+fn usec(d: Duration) -> u64 {
+    d.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// Returns the peak resident set size of the process, shared by all sandboxes
+fn peak_rss() -> anyhow::Result<u64> {
+    let status = std::fs::read_to_string("/proc/self/status")
+        .context("failed to read `/proc/self/status`")?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .context("`VmHWM` missing in `/proc/self/status`")?;
+    let kib = kib
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .context("failed to parse `VmHWM`")?;
+    Ok(kib.saturating_mul(1024))
+}
+
+fn cpu_stat(instances: &[(Outcome, Stats)], key: &str) -> Summary {
+    Summary::new(
+        instances
+            .iter()
+            .filter_map(|(_, stats)| stats.cpu_stat.as_ref()?.get(key).copied())
+            .collect(),
+    )
+}
+
+/// Runs the component in sandboxes `repeat` times and prints a summary of per-instance
+/// latencies and resource usage
+pub fn run(Args { repeat, json, run }: Args) -> anyhow::Result<ExitCode> {
+    ensure!(
+        repeat.get() == 1 || !(run.wasm.is_stdin() || run.invoke_stdin),
+        "`--repeat` cannot be used when reading from stdin"
+    );
+    let _otlp = crate::setup(&run)?;
+    let start = Instant::now();
+    let mut instances = Vec::new();
+    for i in 1..=repeat.get() {
+        if repeat.get() > 1 {
+            eprintln!("run {i}/{repeat}");
+        }
+        instances.extend(crate::execute(run.clone())?);
+    }
+    let wall = start.elapsed();
+    let peak_rss_bytes = match peak_rss() {
+        Ok(rss) => Some(rss),
+        Err(err) => {
+            eprintln!("failed to get peak RSS: {err:#}");
+            None
+        }
+    };
+    let succeeded = instances
+        .iter()
+        .filter(|(outcome, _)| outcome.is_success())
+        .count();
+    let report = Report {
+        runs: repeat.get(),
+        instances: instances.len(),
+        succeeded,
+        wall_usec: usec(wall),
+        peak_rss_bytes,
+        instantiate_usec: Summary::new(
+            instances
+                .iter()
+                .filter_map(|(_, stats)| stats.instantiate.map(usec))
+                .collect(),
+        ),
+        run_usec: Summary::new(
+            instances
+                .iter()
+                .filter_map(|(_, stats)| stats.run.map(usec))
+                .collect(),
+        ),
+        cpu_usage_usec: cpu_stat(&instances, "usage_usec"),
+        cpu_user_usec: cpu_stat(&instances, "user_usec"),
+        cpu_system_usec: cpu_stat(&instances, "system_usec"),
+        memory_peak_bytes: Summary::new(
+            instances
+                .iter()
+                .filter_map(|(_, stats)| stats.memory_peak)
+                .collect(),
+        ),
+        process_memory: ProcessMemory::max(&instances),
+        store_memory_bytes: Summary::new(
+            instances
+                .iter()
+                .filter_map(|(_, stats)| Some(stats.store?.memory))
+                .collect(),
+        ),
+        store_table_elements: Summary::new(
+            instances
+                .iter()
+                .filter_map(|(_, stats)| Some(stats.store?.table_elements))
+                .collect(),
+        ),
+    };
+    if json {
+        let buf = serde_json::to_string_pretty(&report).context("failed to encode summary")?;
+        println!("{buf}");
+    } else {
+        println!("runs: {}", report.runs);
+        println!("instances: {}", report.instances);
+        println!("succeeded: {}", report.succeeded);
+        println!("wall_usec: {}", report.wall_usec);
+        if let Some(rss) = report.peak_rss_bytes {
+            println!("peak_rss_bytes: {rss}");
+        }
+        if let Some(ProcessMemory {
+            shared, private, ..
+        }) = report.process_memory
+        {
+            println!("process_shared_bytes: {shared}");
+            println!("process_private_bytes: {private}");
+        }
+        println!(
+            "{:<24}{:>10}{:>12}{:>12}{:>12}{:>12}{:>12}{:>12}",
+            "METRIC", "SAMPLES", "MIN", "P50", "P90", "P99", "MAX", "MEAN"
+        );
+        for (name, summary) in [
+            ("instantiate_usec", &report.instantiate_usec),
+            ("run_usec", &report.run_usec),
+            ("cpu_usage_usec", &report.cpu_usage_usec),
+            ("cpu_user_usec", &report.cpu_user_usec),
+            ("cpu_system_usec", &report.cpu_system_usec),
+            ("memory_peak_bytes", &report.memory_peak_bytes),
+            ("store_memory_bytes", &report.store_memory_bytes),
+            ("store_table_elements", &report.store_table_elements),
+        ] {
+            let Summary {
+                samples,
+                min,
+                p50,
+                p90,
+                p99,
+                max,
+                mean,
+            } = summary;
+            println!(
+                "{name:<24}{samples:>10}{min:>12}{p50:>12}{p90:>12}{p99:>12}{max:>12}{mean:>12}"
+            );
+        }
+    }
+    if succeeded == report.instances {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
@@ -0,0 +1,599 @@
+use core::fmt::{self, Display};
+use core::future::Future;
+use core::str::FromStr;
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _};
+use bytes::Bytes;
+use futures::{StreamExt as _, TryStreamExt as _};
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore, PutPayload};
+use wasmtime::component::{Linker, Resource, ResourceTable};
+use wasmtime_wasi::pipe::{MemoryInputPipe, MemoryOutputPipe};
+use wasmtime_wasi::{InputStream, OutputStream};
+
+use bindings::wasi::blobstore::types::{ContainerMetadata, Error, ObjectId, ObjectMetadata};
+use bindings::wasi::blobstore::{blobstore, container, types};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "wasi:blobstore/imports",
+        trappable_imports: true,
+        async: true,
+        with: {
+            "wasi:io": wasmtime_wasi::bindings::io,
+            "wasi:blobstore/container/container": super::Container,
+            "wasi:blobstore/container/stream-object-names": super::StreamObjectNames,
+            "wasi:blobstore/types/incoming-value": super::IncomingValue,
+            "wasi:blobstore/types/outgoing-value": super::OutgoingValue,
+        },
+    });
+}
+
+/// Object marking the existence of a container, hidden from guests
+const MARKER: &str = ".cgwasm-container";
+
+/// `wasi:blobstore` backend selection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendConfig {
+    /// Containers are subdirectories of the directory at the path
+    Fs(PathBuf),
+    /// Containers are top-level prefixes of the S3 bucket, configured by `AWS_*` variables
+    S3(String),
+}
+
+impl FromStr for BackendConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("fs", path)) if !path.is_empty() => Ok(Self::Fs(path.into())),
+            Some(("s3", bucket)) if !bucket.is_empty() => Ok(Self::S3(bucket.into())),
+            _ => bail!("invalid blobstore backend `{s}`, expected `fs:PATH` or `s3:BUCKET`"),
+        }
+    }
+}
+
+impl Display for BackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fs(path) => write!(f, "fs:{}", path.display()),
+            Self::S3(bucket) => write!(f, "s3:{bucket}"),
+        }
+    }
+}
+
+/// Object store shared by all instances.
+///
+/// Requests are driven by the runtime the store was created on, so sandboxes do not need
+/// network or filesystem access of their own
+pub struct Store {
+    store: Arc<dyn ObjectStore>,
+    rt: tokio::runtime::Handle,
+}
+
+impl Store {
+    /// Creates the store, must be called from within the root runtime
+    pub fn new(config: &BackendConfig) -> anyhow::Result<Self> {
+        let store: Arc<dyn ObjectStore> = match config {
+            BackendConfig::Fs(path) => {
+                let fs = LocalFileSystem::new_with_prefix(path).with_context(|| {
+                    format!("failed to open blobstore directory `{}`", path.display())
+                })?;
+                Arc::new(fs)
+            }
+            BackendConfig::S3(bucket) => {
+                let s3 = AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()
+                    .with_context(|| format!("failed to configure S3 bucket `{bucket}`"))?;
+                Arc::new(s3)
+            }
+        };
+        Ok(Self {
+            store,
+            rt: tokio::runtime::Handle::current(),
+        })
+    }
+
+    async fn run<T, F>(&self, f: impl FnOnce(Arc<dyn ObjectStore>) -> F) -> object_store::Result<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = object_store::Result<T>> + Send + 'static,
+    {
+        match self.rt.spawn(f(Arc::clone(&self.store))).await {
+            Ok(res) => res,
+            Err(source) => Err(object_store::Error::JoinError { source }),
+        }
+    }
+
+    async fn head(&self, path: Path) -> object_store::Result<Option<ObjectMeta>> {
+        match self
+            .run(|store| async move { store.head(&path).await })
+            .await
+        {
+            Ok(meta) => Ok(Some(meta)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn list(&self, prefix: Path) -> object_store::Result<Vec<ObjectMeta>> {
+        self.run(|store| async move { store.list(Some(&prefix)).try_collect().await })
+            .await
+    }
+
+    async fn delete(&self, paths: Vec<Path>) -> object_store::Result<()> {
+        self.run(|store| async move {
+            let paths = futures::stream::iter(paths.into_iter().map(Ok)).boxed();
+            store.delete_stream(paths).try_collect::<Vec<_>>().await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns metadata of the container marker, if the container exists
+    async fn container(&self, name: &str) -> Result<Option<ObjectMeta>, Error> {
+        let marker = marker(name)?;
+        self.head(marker).await.map_err(other)
+    }
+
+    /// Deletes objects of the container, including the marker if `marker` is set
+    async fn clear(&self, name: &str, marker: bool) -> Result<(), Error> {
+        let objects = self.list(prefix(name)?).await.map_err(other)?;
+        let paths = objects
+            .into_iter()
+            .map(|ObjectMeta { location, .. }| location)
+            .filter(|location| marker || location.filename() != Some(MARKER))
+            .collect();
+        self.delete(paths).await.map_err(other)
+    }
+}
+
+fn other(err: impl Display) -> Error {
+    err.to_string()
+}
+
+fn timestamp(meta: &ObjectMeta) -> u64 {
+    meta.last_modified
+        .timestamp()
+        .try_into()
+        .unwrap_or_default()
+}
+
+fn prefix(container: &str) -> Result<Path, Error> {
+    if container.is_empty() || container.contains('/') {
+        return Err(format!("invalid container name `{container}`"));
+    }
+    Path::parse(container).map_err(other)
+}
+
+fn path(container: &str, object: &str) -> Result<Path, Error> {
+    let path = Path::parse(format!("{}/{object}", prefix(container)?)).map_err(other)?;
+    if object.is_empty() || path.filename() == Some(MARKER) {
+        return Err(format!("invalid object name `{object}`"));
+    }
+    Ok(path)
+}
+
+fn marker(container: &str) -> Result<Path, Error> {
+    Ok(prefix(container)?.child(MARKER))
+}
+
+/// `wasi:blobstore` container resource
+pub struct Container {
+    name: String,
+    created_at: u64,
+}
+
+/// `wasi:blobstore` object name stream resource
+pub struct StreamObjectNames(VecDeque<String>);
+
+/// `wasi:blobstore` incoming value resource
+pub struct IncomingValue(Bytes);
+
+/// `wasi:blobstore` outgoing value resource, buffering the written data in memory
+pub struct OutgoingValue {
+    pipe: MemoryOutputPipe,
+    body: bool,
+}
+
+/// Per-instance `wasi:blobstore` state
+#[derive(Clone)]
+pub struct BlobstoreCtx {
+    store: Option<Arc<Store>>,
+    /// Maximum size of outgoing values in bytes, writes past it fail
+    max_object_size: usize,
+}
+
+impl BlobstoreCtx {
+    pub fn new(store: Option<Arc<Store>>, max_object_size: usize) -> Self {
+        Self {
+            store,
+            max_object_size,
+        }
+    }
+}
+
+/// A view into the `wasi:blobstore` state of an instance
+pub struct Blobstore<'a> {
+    ctx: &'a BlobstoreCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> Blobstore<'a> {
+    pub fn new(ctx: &'a BlobstoreCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+
+    fn store(&self) -> Result<Arc<Store>, Error> {
+        self.ctx
+            .store
+            .clone()
+            .ok_or_else(|| "no blobstore backend configured".to_string())
+    }
+
+    fn container_name(&self, container: &Resource<Container>) -> wasmtime::Result<String> {
+        Ok(self.table.get(container)?.name.clone())
+    }
+}
+
+/// Evaluates to the value of `Ok`, returning `Ok(Err(..))` from the host function otherwise
+macro_rules! guest_try {
+    ($e:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(err) => return Ok(Err(err)),
+        }
+    };
+}
+
+#[async_trait::async_trait]
+impl types::HostOutgoingValue for Blobstore<'_> {
+    async fn new_outgoing_value(&mut self) -> wasmtime::Result<Resource<OutgoingValue>> {
+        let value = OutgoingValue {
+            pipe: MemoryOutputPipe::new(self.ctx.max_object_size),
+            body: false,
+        };
+        Ok(self.table.push(value)?)
+    }
+
+    async fn outgoing_value_write_body(
+        &mut self,
+        value: Resource<OutgoingValue>,
+    ) -> wasmtime::Result<Result<Resource<OutputStream>, ()>> {
+        let value = self.table.get_mut(&value)?;
+        if value.body {
+            return Ok(Err(()));
+        }
+        value.body = true;
+        let body: OutputStream = Box::new(value.pipe.clone());
+        Ok(Ok(self.table.push(body)?))
+    }
+
+    async fn finish(
+        &mut self,
+        value: Resource<OutgoingValue>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        self.table.delete(value)?;
+        Ok(Ok(()))
+    }
+
+    async fn drop(&mut self, value: Resource<OutgoingValue>) -> wasmtime::Result<()> {
+        self.table.delete(value)?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl types::HostIncomingValue for Blobstore<'_> {
+    async fn incoming_value_consume_sync(
+        &mut self,
+        value: Resource<IncomingValue>,
+    ) -> wasmtime::Result<Result<Vec<u8>, Error>> {
+        let IncomingValue(data) = self.table.delete(value)?;
+        Ok(Ok(data.into()))
+    }
+
+    async fn incoming_value_consume_async(
+        &mut self,
+        value: Resource<IncomingValue>,
+    ) -> wasmtime::Result<Result<Resource<InputStream>, Error>> {
+        let IncomingValue(data) = self.table.delete(value)?;
+        let body: InputStream = Box::new(MemoryInputPipe::new(data));
+        Ok(Ok(self.table.push(body)?))
+    }
+
+    async fn size(&mut self, value: Resource<IncomingValue>) -> wasmtime::Result<u64> {
+        let IncomingValue(data) = self.table.get(&value)?;
+        Ok(data.len().try_into()?)
+    }
+
+    async fn drop(&mut self, value: Resource<IncomingValue>) -> wasmtime::Result<()> {
+        self.table.delete(value)?;
+        Ok(())
+    }
+}
+
+impl types::Host for Blobstore<'_> {}
+
+#[async_trait::async_trait]
+impl container::HostContainer for Blobstore<'_> {
+    async fn name(
+        &mut self,
+        container: Resource<Container>,
+    ) -> wasmtime::Result<Result<String, Error>> {
+        Ok(Ok(self.container_name(&container)?))
+    }
+
+    async fn info(
+        &mut self,
+        container: Resource<Container>,
+    ) -> wasmtime::Result<Result<ContainerMetadata, Error>> {
+        let Container { name, created_at } = self.table.get(&container)?;
+        Ok(Ok(ContainerMetadata {
+            name: name.clone(),
+            created_at: *created_at,
+        }))
+    }
+
+    async fn get_data(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+        start: u64,
+        end: u64,
+    ) -> wasmtime::Result<Result<Resource<IncomingValue>, Error>> {
+        let store = guest_try!(self.store());
+        let path = guest_try!(path(&self.container_name(&container)?, &name));
+        let Some(meta) = guest_try!(store.head(path.clone()).await.map_err(other)) else {
+            return Ok(Err(format!("object `{name}` does not exist")));
+        };
+        let start = usize::try_from(start).unwrap_or(usize::MAX).min(meta.size);
+        let end = usize::try_from(end)
+            .unwrap_or(usize::MAX)
+            .saturating_add(1)
+            .clamp(start, meta.size);
+        let data = guest_try!(store
+            .run(|store| async move { store.get_range(&path, start..end).await })
+            .await
+            .map_err(other));
+        Ok(Ok(self.table.push(IncomingValue(data))?))
+    }
+
+    async fn write_data(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+        data: Resource<OutgoingValue>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let store = guest_try!(self.store());
+        let path = guest_try!(path(&self.container_name(&container)?, &name));
+        let payload = PutPayload::from_bytes(self.table.get(&data)?.pipe.contents());
+        let res = store
+            .run(|store| async move { store.put(&path, payload).await })
+            .await;
+        Ok(res.map(|_| ()).map_err(other))
+    }
+
+    async fn list_objects(
+        &mut self,
+        container: Resource<Container>,
+    ) -> wasmtime::Result<Result<Resource<StreamObjectNames>, Error>> {
+        let store = guest_try!(self.store());
+        let prefix = guest_try!(prefix(&self.container_name(&container)?));
+        let objects = guest_try!(store.list(prefix.clone()).await.map_err(other));
+        let names = objects
+            .into_iter()
+            .filter(|ObjectMeta { location, .. }| location.filename() != Some(MARKER))
+            .filter_map(|ObjectMeta { location, .. }| {
+                let parts = location.prefix_match(&prefix)?;
+                Some(
+                    parts
+                        .map(|part| part.as_ref().to_string())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                )
+            })
+            .collect();
+        Ok(Ok(self.table.push(StreamObjectNames(names))?))
+    }
+
+    async fn delete_object(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        self.delete_objects(container, vec![name]).await
+    }
+
+    async fn delete_objects(
+        &mut self,
+        container: Resource<Container>,
+        names: Vec<String>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let store = guest_try!(self.store());
+        let container = self.container_name(&container)?;
+        let paths = guest_try!(names
+            .iter()
+            .map(|name| path(&container, name))
+            .collect::<Result<_, _>>());
+        Ok(store.delete(paths).await.map_err(other))
+    }
+
+    async fn has_object(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+    ) -> wasmtime::Result<Result<bool, Error>> {
+        let store = guest_try!(self.store());
+        let path = guest_try!(path(&self.container_name(&container)?, &name));
+        Ok(store
+            .head(path)
+            .await
+            .map(|meta| meta.is_some())
+            .map_err(other))
+    }
+
+    async fn object_info(
+        &mut self,
+        container: Resource<Container>,
+        name: String,
+    ) -> wasmtime::Result<Result<ObjectMetadata, Error>> {
+        let store = guest_try!(self.store());
+        let container = self.container_name(&container)?;
+        let path = guest_try!(path(&container, &name));
+        let Some(meta) = guest_try!(store.head(path).await.map_err(other)) else {
+            return Ok(Err(format!("object `{name}` does not exist")));
+        };
+        Ok(Ok(ObjectMetadata {
+            created_at: timestamp(&meta),
+            size: meta.size.try_into()?,
+            name,
+            container,
+        }))
+    }
+
+    async fn clear(
+        &mut self,
+        container: Resource<Container>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let store = guest_try!(self.store());
+        let container = self.container_name(&container)?;
+        Ok(store.clear(&container, false).await)
+    }
+
+    async fn drop(&mut self, container: Resource<Container>) -> wasmtime::Result<()> {
+        self.table.delete(container)?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl container::HostStreamObjectNames for Blobstore<'_> {
+    async fn read_stream_object_names(
+        &mut self,
+        names: Resource<StreamObjectNames>,
+        len: u64,
+    ) -> wasmtime::Result<Result<(Vec<String>, bool), Error>> {
+        let StreamObjectNames(names) = self.table.get_mut(&names)?;
+        let len = usize::try_from(len).unwrap_or(usize::MAX).min(names.len());
+        let read = names.drain(..len).collect();
+        Ok(Ok((read, names.is_empty())))
+    }
+
+    async fn skip_stream_object_names(
+        &mut self,
+        names: Resource<StreamObjectNames>,
+        num: u64,
+    ) -> wasmtime::Result<Result<(u64, bool), Error>> {
+        let StreamObjectNames(names) = self.table.get_mut(&names)?;
+        let num = usize::try_from(num).unwrap_or(usize::MAX).min(names.len());
+        names.drain(..num);
+        Ok(Ok((num.try_into()?, names.is_empty())))
+    }
+
+    async fn drop(&mut self, names: Resource<StreamObjectNames>) -> wasmtime::Result<()> {
+        self.table.delete(names)?;
+        Ok(())
+    }
+}
+
+impl container::Host for Blobstore<'_> {}
+
+#[async_trait::async_trait]
+impl blobstore::Host for Blobstore<'_> {
+    async fn create_container(
+        &mut self,
+        name: String,
+    ) -> wasmtime::Result<Result<Resource<Container>, Error>> {
+        let store = guest_try!(self.store());
+        if guest_try!(store.container(&name).await).is_some() {
+            return Ok(Err(format!("container `{name}` already exists")));
+        }
+        let marker = guest_try!(marker(&name));
+        let res = store
+            .run(|store| async move { store.put(&marker, PutPayload::new()).await })
+            .await;
+        guest_try!(res.map_err(other));
+        // the backend may not report the creation time, so only fall back to ours
+        let created_at = match guest_try!(store.container(&name).await) {
+            Some(meta) => timestamp(&meta),
+            None => std::time::SystemTime::UNIX_EPOCH
+                .elapsed()
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+        Ok(Ok(self.table.push(Container { name, created_at })?))
+    }
+
+    async fn get_container(
+        &mut self,
+        name: String,
+    ) -> wasmtime::Result<Result<Resource<Container>, Error>> {
+        let store = guest_try!(self.store());
+        let Some(meta) = guest_try!(store.container(&name).await) else {
+            return Ok(Err(format!("container `{name}` does not exist")));
+        };
+        let created_at = timestamp(&meta);
+        Ok(Ok(self.table.push(Container { name, created_at })?))
+    }
+
+    async fn delete_container(&mut self, name: String) -> wasmtime::Result<Result<(), Error>> {
+        let store = guest_try!(self.store());
+        if guest_try!(store.container(&name).await).is_none() {
+            return Ok(Err(format!("container `{name}` does not exist")));
+        }
+        Ok(store.clear(&name, true).await)
+    }
+
+    async fn container_exists(&mut self, name: String) -> wasmtime::Result<Result<bool, Error>> {
+        let store = guest_try!(self.store());
+        Ok(store.container(&name).await.map(|meta| meta.is_some()))
+    }
+
+    async fn copy_object(
+        &mut self,
+        src: ObjectId,
+        dest: ObjectId,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let store = guest_try!(self.store());
+        let from = guest_try!(path(&src.container, &src.object));
+        let to = guest_try!(path(&dest.container, &dest.object));
+        let res = store
+            .run(|store| async move { store.copy(&from, &to).await })
+            .await;
+        Ok(res.map_err(other))
+    }
+
+    async fn move_object(
+        &mut self,
+        src: ObjectId,
+        dest: ObjectId,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let store = guest_try!(self.store());
+        let from = guest_try!(path(&src.container, &src.object));
+        let to = guest_try!(path(&dest.container, &dest.object));
+        let res = store
+            .run(|store| async move { store.rename(&from, &to).await })
+            .await;
+        Ok(res.map_err(other))
+    }
+}
+
+/// Adds `wasi:blobstore` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> Blobstore<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    types::add_to_linker_get_host(linker, f)?;
+    container::add_to_linker_get_host(linker, f)?;
+    blobstore::add_to_linker_get_host(linker, f)?;
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use anyhow::Context as _;
+use tokio::sync::watch;
+
+/// Cancellation and restart handles of all sandbox instances.
+///
+/// Cancellation and restarts interrupt running guests by incrementing the engine epoch,
+/// on which each store checks its own handles, so other instances keep running
+pub struct Cancel {
+    engine: wasmtime::Engine,
+    instances: Box<[watch::Sender<bool>]>,
+    /// Restart requests of each instance, counted
+    restarts: Box<[watch::Sender<u64>]>,
+}
+
+impl Cancel {
+    pub fn new(engine: wasmtime::Engine, count: usize) -> Self {
+        Self {
+            engine,
+            instances: (0..count).map(|_| watch::channel(false).0).collect(),
+            restarts: (0..count).map(|_| watch::channel(0).0).collect(),
+        }
+    }
+
+    /// Returns the cancellation handle of instance at `index`
+    pub fn subscribe(&self, index: usize) -> watch::Receiver<bool> {
+        self.instances
+            .get(index)
+            .map(watch::Sender::subscribe)
+            .unwrap_or_else(|| watch::channel(false).1)
+    }
+
+    /// Returns the restart handle of instance at `index`
+    pub fn subscribe_restart(&self, index: usize) -> watch::Receiver<u64> {
+        self.restarts
+            .get(index)
+            .map(watch::Sender::subscribe)
+            .unwrap_or_else(|| watch::channel(0).1)
+    }
+
+    /// Requests instance at `index` to be interrupted and instantiated anew within its sandbox,
+    /// returns `false` if its sandbox is gone
+    pub fn restart(&self, index: usize) -> anyhow::Result<bool> {
+        let tx = self
+            .restarts
+            .get(index)
+            .with_context(|| format!("instance {index} does not exist"))?;
+        tx.send_modify(|n| *n = n.wrapping_add(1));
+        self.engine.increment_epoch();
+        Ok(tx.receiver_count() > 0)
+    }
+
+    /// Cancels instance at `index`, returns `false` if it was already cancelled
+    pub fn cancel(&self, index: usize) -> anyhow::Result<bool> {
+        let tx = self
+            .instances
+            .get(index)
+            .with_context(|| format!("instance {index} does not exist"))?;
+        let cancelled = !tx.send_replace(true);
+        if cancelled {
+            self.engine.increment_epoch();
+        }
+        Ok(cancelled)
+    }
+
+    /// Cancels all instances, returns `false` if all of them were already cancelled
+    pub fn cancel_all(&self) -> bool {
+        let mut cancelled = false;
+        for tx in &self.instances {
+            cancelled |= !tx.send_replace(true);
+        }
+        if cancelled {
+            self.engine.increment_epoch();
+        }
+        cancelled
+    }
+}
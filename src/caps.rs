@@ -0,0 +1,150 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use anyhow::{bail, Context as _};
+
+/// Capability names, indexed by capability number
+const NAMES: &[&str] = &[
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Linux capability, like `CAP_NET_BIND_SERVICE` or `net_bind_service`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cap(u8);
+
+impl FromStr for Cap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        let name = name.strip_prefix("cap_").unwrap_or(&name);
+        let Some(cap) = NAMES.iter().position(|n| *n == name) else {
+            bail!("unknown capability `{s}`");
+        };
+        Ok(Self(
+            cap.try_into().context("capability number out of range")?,
+        ))
+    }
+}
+
+impl Display for Cap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = NAMES[usize::from(self.0)].to_ascii_uppercase();
+        write!(f, "CAP_{name}")
+    }
+}
+
+/// Sets `no_new_privs` and drops all capabilities except for `keep` of the current thread
+/// and threads subsequently spawned by it
+pub fn drop_privileges(keep: &[Cap]) -> anyhow::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to set `no_new_privs`");
+    }
+    if unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error())
+            .context("failed to clear ambient capabilities");
+    }
+    let mut mask = 0u64;
+    for cap in keep {
+        mask |= 1 << cap.0;
+    }
+    for cap in 0..NAMES.len() {
+        if mask & (1 << cap) != 0 {
+            continue;
+        }
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0 {
+            let err = std::io::Error::last_os_error();
+            // capabilities unknown to the running kernel
+            if err.raw_os_error() == Some(libc::EINVAL) {
+                continue;
+            }
+            return Err(err)
+                .with_context(|| format!("failed to drop `{}` from bounding set", Cap(cap as u8)));
+        }
+    }
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [
+        CapUserData {
+            effective: mask as u32,
+            permitted: mask as u32,
+            inheritable: 0,
+        },
+        CapUserData {
+            effective: (mask >> 32) as u32,
+            permitted: (mask >> 32) as u32,
+            inheritable: 0,
+        },
+    ];
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to set capabilities");
+    }
+    Ok(())
+}
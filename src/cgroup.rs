@@ -0,0 +1,744 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _};
+use nix::sys::stat::{major, minor};
+use serde::Serialize;
+
+use crate::numa::Numa;
+
+/// Whether sandboxes run in cgroups of their own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// Create a threaded cgroup per sandbox
+    #[default]
+    On,
+    /// Run all sandboxes in the cgroup of the process
+    Off,
+}
+
+/// cgroup hierarchies mounted in the mount namespace of the process
+#[derive(Clone, Debug, Default)]
+pub struct Mounts {
+    /// Mount point of the cgroup v2 hierarchy
+    pub unified: Option<PathBuf>,
+    /// Mount points of legacy cgroup v1 hierarchies with controllers bound to them
+    pub legacy: Vec<(PathBuf, String)>,
+}
+
+impl Mounts {
+    /// Parses `/proc/self/mountinfo`
+    pub fn read() -> io::Result<Self> {
+        let s = std::fs::read_to_string("/proc/self/mountinfo")?;
+        let mut mounts = Self::default();
+        for line in s.lines() {
+            let Some((fields, fs)) = line.split_once(" - ") else {
+                continue;
+            };
+            let Some(mount_point) = fields.split(' ').nth(4) else {
+                continue;
+            };
+            let mut fs = fs.split(' ');
+            match (fs.next(), fs.nth(1)) {
+                (Some("cgroup2"), _) if mounts.unified.is_none() => {
+                    mounts.unified = Some(mount_point.into());
+                }
+                (Some("cgroup"), Some(options)) => {
+                    let controllers = options
+                        .split(',')
+                        .filter(|opt| {
+                            !opt.contains('=')
+                                && !matches!(
+                                    *opt,
+                                    "rw" | "ro" | "clone_children" | "noprefix" | "xattr"
+                                )
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    if !controllers.is_empty() {
+                        mounts.legacy.push((mount_point.into(), controllers));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(mounts)
+    }
+}
+
+/// Parses a flat keyed cgroup file, like `cpu.stat` or `memory.events`
+pub fn read_flat_keyed(path: impl AsRef<Path>) -> io::Result<BTreeMap<String, u64>> {
+    let s = std::fs::read_to_string(path)?;
+    Ok(s.lines()
+        .filter_map(|line| {
+            let (k, v) = line.split_once(' ')?;
+            let v = v.trim().parse().ok()?;
+            Some((k.to_string(), v))
+        })
+        .collect())
+}
+
+/// Reads a single-value cgroup file, like `memory.current`
+pub fn read_u64(path: impl AsRef<Path>) -> io::Result<u64> {
+    let s = std::fs::read_to_string(path)?;
+    s.trim()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Returns the lowest `memory.max` of the cgroup at `path` and its ancestors.
+///
+/// `memory.high` only throttles and reclaims, so it is not a limit allocations fail at.
+/// Threaded cgroups have no memory controller, so their limit is that of the closest
+/// domain ancestor. Cgroups without the file, like the root or ones that do not exist,
+/// are skipped
+pub fn memory_limit(path: &Path) -> Option<u64> {
+    path.ancestors()
+        .filter_map(|dir| read_u64(dir.join("memory.max")).ok())
+        .min()
+}
+
+/// Returns the memory budget of the cgroup at `path`, the lowest `memory.high` or `memory.max`
+/// of it and its ancestors.
+///
+/// `memory.high` is preferred where it is lower, since the kernel throttles and reclaims
+/// memory of the cgroup beyond it
+pub fn memory_budget(path: &Path) -> Option<u64> {
+    path.ancestors()
+        .flat_map(|dir| [dir.join("memory.high"), dir.join("memory.max")])
+        .filter_map(|path| read_u64(path).ok())
+        .min()
+}
+
+/// Returns `memory.current` of the closest cgroup with the `memory` controller among the cgroup
+/// at `path` and its ancestors, which threaded cgroups share with all their siblings
+pub fn memory_current(path: &Path) -> Option<u64> {
+    path.ancestors()
+        .find_map(|dir| read_u64(dir.join("memory.current")).ok())
+}
+
+/// Returns the CPUs in a cpuset list, like `0-3,8`, also used for lists of NUMA nodes
+pub fn parse_cpus(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<usize>().ok()?..=end.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+/// Returns the number of CPUs in a cpuset list, like `0-3,8`
+fn count_cpus(list: &str) -> Option<usize> {
+    parse_cpus(list).map(|cpus| cpus.len())
+}
+
+/// Returns the number of CPUs available to the cgroup at `path`, the lower of
+/// the closest `cpuset.cpus.effective` and the lowest `cpu.max` quota, rounded up,
+/// of the cgroup and its ancestors.
+///
+/// Cgroups without the files, like ones that do not exist yet, are skipped
+pub fn cpu_limit(path: &Path) -> Option<usize> {
+    let cpuset = path
+        .ancestors()
+        .find_map(|dir| std::fs::read_to_string(dir.join("cpuset.cpus.effective")).ok())
+        .and_then(|list| count_cpus(&list))
+        .filter(|n| *n > 0);
+    let quota = path
+        .ancestors()
+        .filter_map(|dir| {
+            let max = std::fs::read_to_string(dir.join("cpu.max")).ok()?;
+            let (quota, period) = max.trim().split_once(' ')?;
+            let quota: u64 = quota.parse().ok()?;
+            let period: u64 = period.parse().ok().filter(|period| *period > 0)?;
+            usize::try_from(quota.div_ceil(period).max(1)).ok()
+        })
+        .min();
+    match (cpuset, quota) {
+        (Some(cpuset), Some(quota)) => Some(cpuset.min(quota)),
+        (cpuset, quota) => cpuset.or(quota),
+    }
+}
+
+/// Withholds the `n` highest-numbered CPUs of the cgroup at `path` from it and its descendants
+/// by writing the remaining ones to its `cpuset.cpus`, which are returned
+pub fn reserve_cpus(path: &Path, n: usize) -> anyhow::Result<String> {
+    let effective_path = path.join("cpuset.cpus.effective");
+    let effective = std::fs::read_to_string(&effective_path)
+        .with_context(|| format!("failed to read `{}`", effective_path.display()))?;
+    let cpus = parse_cpus(&effective)
+        .with_context(|| format!("failed to parse `{}` contents", effective_path.display()))?;
+    ensure!(
+        n < cpus.len(),
+        "cannot reserve {n} of {} CPUs available to `{}`",
+        cpus.len(),
+        path.display()
+    );
+    let list = cpus[..cpus.len() - n]
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let cpus_path = path.join("cpuset.cpus");
+    std::fs::write(&cpus_path, &list)
+        .with_context(|| format!("failed to write `{list}` to `{}`", cpus_path.display()))?;
+    Ok(list)
+}
+
+/// Returns `MemTotal` of `/proc/meminfo` in bytes
+fn mem_total() -> anyhow::Result<u64> {
+    let meminfo =
+        std::fs::read_to_string("/proc/meminfo").context("failed to read `/proc/meminfo`")?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|kib| kib.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kib| kib.saturating_mul(1024))
+        .context("`MemTotal` missing in `/proc/meminfo`")
+}
+
+/// Withholds `bytes` of the memory available to the cgroup at `path`, the lower of `MemTotal`
+/// and its [`memory_limit`], by lowering its `memory.max`, which is returned, restoring
+/// the previous value via `restore`
+pub fn reserve_memory(path: &Path, bytes: u64, restore: &mut Restore) -> anyhow::Result<u64> {
+    let total = mem_total()?;
+    let total = memory_limit(path).map_or(total, |limit| total.min(limit));
+    ensure!(
+        bytes < total,
+        "cannot reserve {bytes} of {total} bytes of memory available to `{}`",
+        path.display()
+    );
+    let max = total - bytes;
+    let max_path = path.join("memory.max");
+    let prev = std::fs::read_to_string(&max_path)
+        .with_context(|| format!("failed to read `{}`", max_path.display()))?;
+    restore.write(max_path, &max.to_string(), prev.trim().to_string())?;
+    Ok(max)
+}
+
+/// Files of cgroups not created by us with their previous contents, written back on drop,
+/// so limits lowered for the run are restored on both success and failure
+#[derive(Debug, Default)]
+pub struct Restore(Vec<(PathBuf, String)>);
+
+impl Restore {
+    /// Writes `value` to the file at `path`, to be overwritten by `prev` on drop
+    pub fn write(&mut self, path: PathBuf, value: &str, prev: String) -> anyhow::Result<()> {
+        std::fs::write(&path, value)
+            .with_context(|| format!("failed to write `{value}` to `{}`", path.display()))?;
+        self.0.push((path, prev));
+        Ok(())
+    }
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        for (path, prev) in self.0.drain(..).rev() {
+            if let Err(err) = std::fs::write(&path, &prev) {
+                eprintln!("failed to restore `{prev}` of `{}`: {err}", path.display());
+            }
+        }
+    }
+}
+
+/// Applies `limits` to `io.max` of the cgroup at `path`, restoring the previous limits
+/// of the devices via `restore`.
+///
+/// `io` is not a threaded controller, so the limits apply to the domain cgroup of the process,
+/// shared by all sandboxes, rather than to each sandbox cgroup
+pub fn apply_io_max(path: &Path, limits: &[IoMax], restore: &mut Restore) -> anyhow::Result<()> {
+    let path = path.join("io.max");
+    let prev = match std::fs::read_to_string(&path) {
+        Ok(prev) => prev,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => bail!(
+            "`{}` does not exist, `io` controller is not enabled for the cgroup",
+            path.display()
+        ),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read `{}`", path.display()))
+        }
+    };
+    for limit in limits {
+        let dev = format!("{}:{}", limit.major, limit.minor);
+        let prev = prev
+            .lines()
+            .find(|line| line.split_whitespace().next() == Some(&dev))
+            .map_or_else(
+                || format!("{dev} riops=max wiops=max rbps=max wbps=max"),
+                str::to_string,
+            );
+        restore.write(path.clone(), &limit.to_string(), prev)?;
+    }
+    Ok(())
+}
+
+/// `io.max` limit of the form `DEV=RIOPS:WIOPS:RBPS:WBPS`.
+///
+/// `DEV` is either a `MAJOR:MINOR` device number or a path to a block device,
+/// each limit is either a number or `max`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoMax {
+    major: u64,
+    minor: u64,
+    riops: Option<u64>,
+    wiops: Option<u64>,
+    rbps: Option<u64>,
+    wbps: Option<u64>,
+}
+
+impl FromStr for IoMax {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((dev, limits)) = s.rsplit_once('=') else {
+            bail!("`{s}` is not a valid `DEV=RIOPS:WIOPS:RBPS:WBPS` limit");
+        };
+        let (major, minor) = if dev.starts_with('/') {
+            let meta = std::fs::metadata(dev).with_context(|| format!("failed to stat `{dev}`"))?;
+            (major(meta.rdev()), minor(meta.rdev()))
+        } else {
+            let (major, minor) = dev
+                .split_once(':')
+                .with_context(|| format!("invalid device `{dev}` in `{s}`"))?;
+            let major = major
+                .parse()
+                .with_context(|| format!("invalid major number `{major}` in `{s}`"))?;
+            let minor = minor
+                .parse()
+                .with_context(|| format!("invalid minor number `{minor}` in `{s}`"))?;
+            (major, minor)
+        };
+        let limits = limits
+            .split(':')
+            .map(|limit| match limit {
+                "max" => Ok(None),
+                limit => limit
+                    .parse()
+                    .map(Some)
+                    .with_context(|| format!("invalid limit `{limit}` in `{s}`")),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let [riops, wiops, rbps, wbps] = limits[..] else {
+            bail!("`{s}` must specify exactly 4 limits");
+        };
+        Ok(Self {
+            major,
+            minor,
+            riops,
+            wiops,
+            rbps,
+            wbps,
+        })
+    }
+}
+
+impl Display for IoMax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)?;
+        for (key, limit) in [
+            ("riops", self.riops),
+            ("wiops", self.wiops),
+            ("rbps", self.rbps),
+            ("wbps", self.wbps),
+        ] {
+            if let Some(limit) = limit {
+                write!(f, " {key}={limit}")?;
+            } else {
+                write!(f, " {key}=max")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// CPU priority tier of the form `NAME=WEIGHT`, with `WEIGHT` in `1..=10000`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuTier {
+    pub name: String,
+    pub weight: u16,
+}
+
+impl FromStr for CpuTier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, weight)) = s.split_once('=') else {
+            bail!("`{s}` is not a valid `NAME=WEIGHT` tier");
+        };
+        if name.is_empty() {
+            bail!("empty tier name in `{s}`");
+        }
+        let weight = weight
+            .parse()
+            .ok()
+            .filter(|weight| (1..=10000).contains(weight))
+            .with_context(|| format!("invalid weight `{weight}` in `{s}`, expected 1-10000"))?;
+        Ok(Self {
+            name: name.to_string(),
+            weight,
+        })
+    }
+}
+
+/// Policy assigning instances to CPU tiers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TierPolicy {
+    /// Instance `INDEX` gets tier `INDEX % TIERS`
+    #[default]
+    RoundRobin,
+    /// Instances are split into contiguous blocks of equal size, one per tier in order
+    Blocks,
+}
+
+/// CPU tiers of all instances
+#[derive(Clone, Debug, Default)]
+pub struct CpuTiers {
+    pub tiers: Vec<CpuTier>,
+    pub policy: TierPolicy,
+    /// Total number of instances
+    pub count: usize,
+}
+
+impl CpuTiers {
+    /// Returns the tier of the instance at `index`, if any tiers are configured
+    pub fn get(&self, index: usize) -> Option<&CpuTier> {
+        if self.tiers.is_empty() {
+            return None;
+        }
+        let i = match self.policy {
+            TierPolicy::RoundRobin => index % self.tiers.len(),
+            TierPolicy::Blocks => {
+                let size = self.count.div_ceil(self.tiers.len()).max(1);
+                (index / size).min(self.tiers.len() - 1)
+            }
+        };
+        self.tiers.get(i)
+    }
+}
+
+/// Resource limits applied to each sandbox cgroup
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub cpu_tiers: CpuTiers,
+    pub numa: Option<Numa>,
+}
+
+impl Limits {
+    /// Writes the limits of the instance at `index` into the cgroup at `path`,
+    /// called on the sandbox thread
+    pub fn apply(&self, index: usize, path: &Path) -> anyhow::Result<()> {
+        if let Some(CpuTier { name, weight }) = self.cpu_tiers.get(index) {
+            let path = path.join("cpu.weight");
+            if !path.exists() {
+                bail!(
+                    "`{}` does not exist, `cpu` controller is not enabled for the cgroup",
+                    path.display()
+                );
+            }
+            std::fs::write(&path, weight.to_string()).with_context(|| {
+                format!(
+                    "failed to write `{name}` tier weight `{weight}` to `{}`",
+                    path.display()
+                )
+            })?;
+        }
+        if let Some(numa) = &self.numa {
+            numa.apply(index, path)
+                .context("failed to apply NUMA placement")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of CPUs the cpuset applied to instance at `index` restricts it to,
+    /// if any, known before the limits are applied
+    pub fn cpus(&self, index: usize) -> Option<usize> {
+        self.numa.as_ref()?.cpus(index)
+    }
+}
+
+/// Averages and total stall time of a single PSI line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct PressureStall {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total: u64,
+}
+
+/// Pressure stall information, like `cpu.pressure` or `memory.pressure`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Pressure {
+    pub some: PressureStall,
+    pub full: Option<PressureStall>,
+}
+
+/// Parses a PSI cgroup file, like `cpu.pressure` or `memory.pressure`
+pub fn read_pressure(path: impl AsRef<Path>) -> io::Result<Pressure> {
+    let s = std::fs::read_to_string(path)?;
+    let mut pressure = Pressure::default();
+    for line in s.lines() {
+        let Some((kind, fields)) = line.split_once(' ') else {
+            continue;
+        };
+        let mut stall = PressureStall::default();
+        for field in fields.split_whitespace() {
+            let parsed = match field.split_once('=') {
+                Some(("avg10", v)) => v.parse().map(|v| stall.avg10 = v).is_ok(),
+                Some(("avg60", v)) => v.parse().map(|v| stall.avg60 = v).is_ok(),
+                Some(("avg300", v)) => v.parse().map(|v| stall.avg300 = v).is_ok(),
+                Some(("total", v)) => v.parse().map(|v| stall.total = v).is_ok(),
+                _ => true,
+            };
+            if !parsed {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid PSI field `{field}`"),
+                ));
+            }
+        }
+        match kind {
+            "some" => pressure.some = stall,
+            "full" => pressure.full = Some(stall),
+            _ => {}
+        }
+    }
+    Ok(pressure)
+}
+
+/// Template of a cgroup path relative to its parent cgroup.
+///
+/// `{component}` is substituted by the component file stem and `{index}` by the instance index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl FromStr for NameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            bail!("cgroup name template must not be empty");
+        }
+        for segment in s.split('/') {
+            if matches!(segment, "" | "." | "..") {
+                bail!("invalid path segment `{segment}` in cgroup name template `{s}`");
+            }
+        }
+        let mut rest = s;
+        while let Some((_, tail)) = rest.split_once('{') {
+            let (key, tail) = tail
+                .split_once('}')
+                .with_context(|| format!("unterminated placeholder in `{s}`"))?;
+            if !matches!(key, "component" | "index") {
+                bail!("unknown placeholder `{{{key}}}` in `{s}`");
+            }
+            rest = tail;
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl NameTemplate {
+    /// Whether the template produces a distinct name for each instance
+    pub fn is_per_instance(&self) -> bool {
+        self.0.contains("{index}")
+    }
+
+    pub fn render(&self, component: &str, index: usize) -> String {
+        self.0
+            .replace("{component}", component)
+            .replace("{index}", &index.to_string())
+    }
+}
+
+/// Creates threaded cgroups for each segment of `name` within `base`, enabling `controllers`
+/// in each one, and returns the path of the last one
+pub fn create_threaded(base: &Path, name: &str, controllers: &str) -> anyhow::Result<PathBuf> {
+    let mut path = base.to_path_buf();
+    for segment in name.split('/') {
+        path.push(segment);
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("failed to create `{}` cgroup", path.display()))?;
+        let type_path = path.join("cgroup.type");
+        std::fs::write(&type_path, b"threaded")
+            .with_context(|| format!("failed to write `threaded` to `{}`", type_path.display()))?;
+        let subtree_path = path.join("cgroup.subtree_control");
+        std::fs::write(&subtree_path, controllers).with_context(|| {
+            format!(
+                "failed to write `{controllers}` to `{}`",
+                subtree_path.display()
+            )
+        })?;
+    }
+    Ok(path)
+}
+
+/// Name of the cgroup within the prefix cgroup holding the supervisor threads
+pub const CONTROL: &str = "cgwasm_control";
+
+/// Moves all threads of the process into a threaded [`CONTROL`] cgroup within `cg`
+/// with `cpu_weight`, so that the supervisor competes with sandboxes as a single, weighted group.
+///
+/// Threads spawned afterwards start in the cgroup of the spawning thread,
+/// sandbox threads move into their own cgroup once started
+pub fn isolate_control(cg: &Path, cpu_weight: u16) -> anyhow::Result<PathBuf> {
+    let path = cg.join(CONTROL);
+    std::fs::create_dir_all(&path)
+        .with_context(|| format!("failed to create `{}` cgroup", path.display()))?;
+    let type_path = path.join("cgroup.type");
+    std::fs::write(&type_path, b"threaded")
+        .with_context(|| format!("failed to write `threaded` to `{}`", type_path.display()))?;
+    let weight_path = path.join("cpu.weight");
+    if weight_path.exists() {
+        std::fs::write(&weight_path, cpu_weight.to_string()).with_context(|| {
+            format!(
+                "failed to write `{cpu_weight}` to `{}`",
+                weight_path.display()
+            )
+        })?;
+    } else {
+        eprintln!("`cpu` controller is not enabled, control cgroup CPU weight is not applied");
+    }
+    let threads_path = path.join("cgroup.threads");
+    for entry in std::fs::read_dir("/proc/self/task").context("failed to list threads")? {
+        let entry = entry.context("failed to read thread entry")?;
+        let tid = entry.file_name();
+        match std::fs::write(&threads_path, tid.as_encoded_bytes()) {
+            Ok(()) => {}
+            // thread exited in the meantime
+            Err(err) if err.raw_os_error() == Some(libc::ESRCH) => {}
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!(
+                        "failed to move thread `{}` to `{}`",
+                        tid.to_string_lossy(),
+                        threads_path.display()
+                    )
+                })
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Returns cgroups within `cg` without any threads in their subtree, left over by previous runs,
+/// descendants first
+pub fn find_stale(cg: &Path) -> io::Result<Vec<PathBuf>> {
+    fn visit(path: &Path, stale: &mut Vec<PathBuf>) -> io::Result<bool> {
+        let mut is_stale = true;
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                is_stale &= visit(&entry.path(), stale)?;
+            }
+        }
+        let threads = std::fs::read_to_string(path.join("cgroup.threads"))?;
+        is_stale &= threads.trim().is_empty();
+        if is_stale {
+            stale.push(path.to_path_buf());
+        }
+        Ok(is_stale)
+    }
+
+    let mut stale = Vec::new();
+    for entry in std::fs::read_dir(cg)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            visit(&entry.path(), &mut stale)?;
+        }
+    }
+    Ok(stale)
+}
+
+/// Removes empty cgroups at `names` relative to `base` and their ancestors within `base`,
+/// logging failures
+pub fn remove_tree(base: &Path, names: impl IntoIterator<Item = impl AsRef<Path>>) {
+    let mut paths = BTreeSet::new();
+    for name in names {
+        let mut name = name.as_ref();
+        while !name.as_os_str().is_empty() {
+            paths.insert(name.to_path_buf());
+            name = name.parent().unwrap_or(Path::new(""));
+        }
+    }
+    let mut paths: Vec<_> = paths.into_iter().collect();
+    paths.sort_by_key(|path| core::cmp::Reverse(path.components().count()));
+    for path in paths {
+        remove(&base.join(path));
+    }
+}
+
+/// Removes an empty cgroup at `path`, logging failures
+pub fn remove(path: &Path) {
+    match std::fs::remove_dir(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => eprintln!("failed to remove `{}` cgroup: {err}", path.display()),
+    }
+}
+
+/// Disables `controllers`, in `+controller` form, in `subtree_control` of the cgroup at `path`,
+/// logging failures
+pub fn disable_controllers(path: &Path, controllers: &str) {
+    let controllers = controllers
+        .split_whitespace()
+        .filter_map(|c| c.strip_prefix('+'))
+        .map(|c| format!("-{c}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if controllers.is_empty() {
+        return;
+    }
+    let path = path.join("cgroup.subtree_control");
+    if let Err(err) = std::fs::write(&path, &controllers) {
+        eprintln!(
+            "failed to write `{controllers}` to `{}`: {err}",
+            path.display()
+        );
+    }
+}
+
+/// cgroup setup of the process, undone on drop: the process is moved back to `parent`,
+/// the prefix cgroup and the cgroups of instances within it are removed,
+/// controllers enabled by us are disabled and limits of `parent` restored
+pub struct Setup {
+    /// cgroup the process was moved from
+    pub parent: PathBuf,
+    /// Name of the prefix cgroup within `parent`
+    pub prefix: String,
+    pub pid: u32,
+    /// Controllers enabled in `parent` by us, in `+controller` form
+    pub enabled: String,
+    /// Names of the cgroups of instances within the prefix cgroup
+    pub names: Vec<String>,
+    /// Limits of `parent` to restore, after the prefix cgroup is removed
+    pub restore: Restore,
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let procs = self.parent.join("cgroup.procs");
+        if let Err(err) = std::fs::write(&procs, self.pid.to_string()) {
+            eprintln!(
+                "failed to move PID back to `{}`: {err}",
+                self.parent.display()
+            );
+        }
+        let cg = self.parent.join(&self.prefix);
+        remove_tree(&cg, &self.names);
+        remove(&cg.join(CONTROL));
+        remove_tree(&self.parent, [&self.prefix]);
+        disable_controllers(&self.parent, &self.enabled);
+    }
+}
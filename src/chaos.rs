@@ -0,0 +1,283 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use core::time::Duration;
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, ensure, Context as _};
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use wasmtime_wasi::{
+    HostInputStream, HostOutputStream, InputStream, OutputStream, StreamError, StreamResult,
+    Subscribe,
+};
+
+/// Class of guest host calls faults are injected into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// `wasi:filesystem` descriptor and stream operations
+    Fs,
+    /// `wasi:sockets` connects and datagrams
+    Net,
+    /// Outgoing `wasi:http` requests
+    Http,
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fs => write!(f, "fs"),
+            Self::Net => write!(f, "net"),
+            Self::Http => write!(f, "http"),
+        }
+    }
+}
+
+/// Fault injection rule of the form `TARGET:fail=P[,delay=P@DURATION]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    target: Target,
+    /// Probability of a call failing
+    fail: f64,
+    /// Probability of a call being delayed
+    delay: f64,
+    delay_by: Duration,
+}
+
+fn parse_probability(s: &str) -> anyhow::Result<f64> {
+    let p = s
+        .parse::<f64>()
+        .with_context(|| format!("invalid probability `{s}`"))?;
+    ensure!(
+        (0. ..=1.).contains(&p),
+        "probability `{s}` must be within 0 and 1"
+    );
+    Ok(p)
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((target, faults)) = s.split_once(':') else {
+            bail!("invalid chaos rule `{s}`, expected `TARGET:fail=P[,delay=P@DURATION]`")
+        };
+        let target = match target {
+            "fs" => Target::Fs,
+            "net" => Target::Net,
+            "http" => Target::Http,
+            _ => bail!("invalid chaos target `{target}`, expected `fs`, `net` or `http`"),
+        };
+        let mut rule = Self {
+            target,
+            fail: 0.,
+            delay: 0.,
+            delay_by: Duration::ZERO,
+        };
+        for fault in faults.split(',') {
+            match fault.split_once('=') {
+                Some(("fail", p)) => rule.fail = parse_probability(p)?,
+                Some(("delay", delay)) => {
+                    let (p, by) = delay.split_once('@').with_context(|| {
+                        format!("invalid delay `{delay}`, expected `P@DURATION`")
+                    })?;
+                    rule.delay = parse_probability(p)?;
+                    rule.delay_by = humantime::parse_duration(by)
+                        .with_context(|| format!("invalid delay duration `{by}`"))?;
+                }
+                _ => bail!("invalid fault `{fault}`, expected `fail=P` or `delay=P@DURATION`"),
+            }
+        }
+        Ok(rule)
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:fail={},delay={}@{}",
+            self.target,
+            self.fail,
+            self.delay,
+            humantime::format_duration(self.delay_by)
+        )
+    }
+}
+
+/// Fault injector of a single instance
+pub struct Chaos {
+    rules: Arc<[Rule]>,
+    rng: Mutex<StdRng>,
+    index: usize,
+}
+
+impl Chaos {
+    /// Creates an injector of instance at `index` applying `rules`, drawing from a generator
+    /// seeded by `seed`, if set, or host entropy
+    pub fn new(rules: Arc<[Rule]>, seed: Option<u64>, index: usize) -> Self {
+        let rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+        Self {
+            rules,
+            rng: Mutex::new(rng),
+            index,
+        }
+    }
+
+    /// Returns the delay of a call of `target` and whether it must fail, as drawn by the rules
+    fn draw(&self, target: Target) -> (Duration, bool) {
+        let (delay, fail) = {
+            let mut rng = self.rng.lock().unwrap_or_else(|err| err.into_inner());
+            let mut delay = Duration::ZERO;
+            let mut fail = false;
+            for rule in self.rules.iter().filter(|rule| rule.target == target) {
+                if rng.gen_bool(rule.delay) {
+                    delay += rule.delay_by;
+                }
+                fail |= rng.gen_bool(rule.fail);
+            }
+            (delay, fail)
+        };
+        if fail {
+            eprintln!("instance {} injected `{target}` fault", self.index);
+        }
+        (delay, fail)
+    }
+
+    /// Delays a call of `target` and returns whether it must fail, as drawn by the rules
+    pub async fn inject(&self, target: Target) -> bool {
+        let (delay, fail) = self.draw(target);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        fail
+    }
+}
+
+fn stream_fault() -> StreamError {
+    StreamError::LastOperationFailed(anyhow!("injected `{}` fault", Target::Fs))
+}
+
+/// `wasi:filesystem` stream injecting [`Target::Fs`] faults into operations of the wrapped one.
+///
+/// Blocking operations are delayed in place, delays of non-blocking ones are deferred
+/// to the next readiness check, which guests wait for before continuing
+pub struct FaultyStream<S> {
+    inner: S,
+    chaos: Arc<Chaos>,
+    /// Deferred delay of non-blocking operations
+    delay: Duration,
+}
+
+impl<S> FaultyStream<S> {
+    pub fn new(inner: S, chaos: Arc<Chaos>) -> Self {
+        Self {
+            inner,
+            chaos,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Draws a fault of a non-blocking operation, deferring its delay
+    fn draw(&mut self) -> StreamResult<()> {
+        let (delay, fail) = self.chaos.draw(Target::Fs);
+        self.delay += delay;
+        if fail {
+            return Err(stream_fault());
+        }
+        Ok(())
+    }
+
+    /// Delays a blocking operation, including deferred delays, and fails it, if drawn
+    async fn inject(&mut self) -> StreamResult<()> {
+        self.delay().await;
+        if self.chaos.inject(Target::Fs).await {
+            return Err(stream_fault());
+        }
+        Ok(())
+    }
+
+    /// Applies deferred delays
+    async fn delay(&mut self) {
+        let delay = core::mem::take(&mut self.delay);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Subscribe> Subscribe for FaultyStream<S> {
+    async fn ready(&mut self) {
+        self.delay().await;
+        self.inner.ready().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl HostInputStream for FaultyStream<InputStream> {
+    fn read(&mut self, size: usize) -> StreamResult<Bytes> {
+        self.draw()?;
+        self.inner.read(size)
+    }
+
+    async fn blocking_read(&mut self, size: usize) -> StreamResult<Bytes> {
+        self.inject().await?;
+        self.inner.blocking_read(size).await
+    }
+
+    fn skip(&mut self, nelem: usize) -> StreamResult<usize> {
+        self.draw()?;
+        self.inner.skip(nelem)
+    }
+
+    async fn blocking_skip(&mut self, nelem: usize) -> StreamResult<usize> {
+        self.inject().await?;
+        self.inner.blocking_skip(nelem).await
+    }
+
+    async fn cancel(&mut self) {
+        self.inner.cancel().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl HostOutputStream for FaultyStream<OutputStream> {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.draw()?;
+        self.inner.write(bytes)
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        self.inner.flush()
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        self.inner.check_write()
+    }
+
+    async fn blocking_write_and_flush(&mut self, bytes: Bytes) -> StreamResult<()> {
+        self.inject().await?;
+        self.inner.blocking_write_and_flush(bytes).await
+    }
+
+    fn write_zeroes(&mut self, nelem: usize) -> StreamResult<()> {
+        self.draw()?;
+        self.inner.write_zeroes(nelem)
+    }
+
+    async fn blocking_write_zeroes_and_flush(&mut self, nelem: usize) -> StreamResult<()> {
+        self.inject().await?;
+        self.inner.blocking_write_zeroes_and_flush(nelem).await
+    }
+
+    async fn write_ready(&mut self) -> StreamResult<usize> {
+        self.delay().await;
+        self.inner.write_ready().await
+    }
+
+    async fn cancel(&mut self) {
+        self.inner.cancel().await;
+    }
+}
@@ -0,0 +1,169 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use std::time::{Instant, SystemTime};
+
+use anyhow::{bail, ensure, Context as _};
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+/// Guest clock behavior
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClockConfig {
+    /// Pass through host clocks
+    #[default]
+    Host,
+    /// Clocks never advance
+    Frozen,
+    /// Clocks advance `factor` times as fast as host clocks
+    Scaled(f64),
+    /// Clocks advance by a fixed step on every read, independently of host time
+    Step(Duration),
+}
+
+impl FromStr for ClockConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "host" => Ok(Self::Host),
+            None if s == "frozen" => Ok(Self::Frozen),
+            Some(("scaled", factor)) => {
+                let factor = factor
+                    .parse::<f64>()
+                    .with_context(|| format!("invalid scaling factor `{factor}`"))?;
+                ensure!(
+                    factor.is_finite() && factor > 0.,
+                    "scaling factor must be positive"
+                );
+                Ok(Self::Scaled(factor))
+            }
+            Some(("step", step)) => {
+                let step = humantime::parse_duration(step)
+                    .with_context(|| format!("invalid step duration `{step}`"))?;
+                Ok(Self::Step(step))
+            }
+            _ => bail!(
+                "invalid clock `{s}`, expected `host`, `frozen`, `scaled:FACTOR` or `step:DURATION`"
+            ),
+        }
+    }
+}
+
+impl Display for ClockConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Host => write!(f, "host"),
+            Self::Frozen => write!(f, "frozen"),
+            Self::Scaled(factor) => write!(f, "scaled:{factor}"),
+            Self::Step(step) => write!(f, "step:{}", humantime::format_duration(*step)),
+        }
+    }
+}
+
+impl ClockConfig {
+    /// Whether guest-visible time is independent of host time
+    pub fn is_deterministic(self) -> bool {
+        matches!(self, Self::Frozen | Self::Step(..))
+    }
+
+    /// Configures virtual clocks starting at `epoch`, time since UNIX epoch, on the builder.
+    ///
+    /// Host clocks are left untouched for [`ClockConfig::Host`]
+    pub fn configure(self, epoch: Duration, builder: &mut WasiCtxBuilder) {
+        if self == Self::Host {
+            return;
+        }
+        builder.wall_clock(self.wall_clock(epoch));
+        builder.monotonic_clock(self.monotonic_clock());
+    }
+
+    /// Returns a virtual wall clock starting at `epoch`, time since UNIX epoch,
+    /// which follows host time for [`ClockConfig::Host`]
+    pub fn wall_clock(self, epoch: Duration) -> impl HostWallClock + 'static {
+        WallClock {
+            epoch,
+            clock: VirtualClock::new(self),
+        }
+    }
+
+    /// Returns a virtual monotonic clock starting at zero
+    pub fn monotonic_clock(self) -> impl HostMonotonicClock + 'static {
+        MonotonicClock(VirtualClock::new(self))
+    }
+}
+
+/// Returns current host time since UNIX epoch
+pub fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+struct VirtualClock {
+    config: ClockConfig,
+    start: Instant,
+    reads: AtomicU64,
+}
+
+impl VirtualClock {
+    fn new(config: ClockConfig) -> Self {
+        Self {
+            config,
+            start: Instant::now(),
+            reads: AtomicU64::default(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.config {
+            ClockConfig::Host => self.start.elapsed(),
+            ClockConfig::Frozen => Duration::ZERO,
+            ClockConfig::Scaled(factor) => self.start.elapsed().mul_f64(factor),
+            ClockConfig::Step(step) => {
+                let n = self.reads.fetch_add(1, Ordering::Relaxed);
+                step.checked_mul(n.try_into().unwrap_or(u32::MAX))
+                    .unwrap_or(Duration::MAX)
+            }
+        }
+    }
+
+    fn resolution(&self) -> Duration {
+        match self.config {
+            ClockConfig::Step(step) if !step.is_zero() => step,
+            _ => Duration::from_nanos(1),
+        }
+    }
+}
+
+struct WallClock {
+    epoch: Duration,
+    clock: VirtualClock,
+}
+
+impl HostWallClock for WallClock {
+    fn resolution(&self) -> Duration {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> Duration {
+        self.epoch.saturating_add(self.clock.elapsed())
+    }
+}
+
+struct MonotonicClock(VirtualClock);
+
+impl HostMonotonicClock for MonotonicClock {
+    fn resolution(&self) -> u64 {
+        self.0
+            .resolution()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    fn now(&self) -> u64 {
+        self.0.elapsed().as_nanos().try_into().unwrap_or(u64::MAX)
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _};
+use tokio::fs;
+use wac_graph::types::Package;
+use wac_graph::{CompositionGraph, EncodeOptions};
+
+use crate::provenance;
+use crate::signature::{self, Verifier};
+
+/// Plugs exports of components at `plugs` into matching imports of the `socket` component
+/// and returns the encoded composition with digests of the plugs by path.
+///
+/// Plugs are applied in order, so later plugs may satisfy imports of earlier ones.
+/// If `verifier` is set, each plug must be signed by `PATH.sig`
+pub async fn plug(
+    socket: Vec<u8>,
+    plugs: &[PathBuf],
+    verifier: Option<&Verifier>,
+) -> anyhow::Result<(Vec<u8>, BTreeMap<String, String>)> {
+    let mut graph = CompositionGraph::new();
+    let socket = Package::from_bytes("socket", None, socket, graph.types_mut())
+        .context("failed to parse socket component")?;
+    let socket = graph
+        .register_package(socket)
+        .context("failed to register socket component")?;
+    let mut ids = Vec::with_capacity(plugs.len());
+    let mut digests = BTreeMap::new();
+    for (i, path) in plugs.iter().enumerate() {
+        let wasm = fs::read(path)
+            .await
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        if let Some(verifier) = verifier {
+            verifier
+                .verify(&wasm, &signature::default_path(path))
+                .await
+                .with_context(|| format!("failed to verify `{}`", path.display()))?;
+        }
+        digests.insert(path.display().to_string(), provenance::digest(&wasm));
+        let plug = Package::from_bytes(&format!("plug{i}"), None, wasm, graph.types_mut())
+            .with_context(|| format!("failed to parse `{}`", path.display()))?;
+        let plug = graph
+            .register_package(plug)
+            .with_context(|| format!("failed to register `{}`", path.display()))?;
+        ids.push(plug);
+    }
+    wac_graph::plug(&mut graph, ids, socket).context("failed to plug components")?;
+    let wasm = graph
+        .encode(EncodeOptions::default())
+        .context("failed to encode composition")?;
+    Ok((wasm, digests))
+}
+
+/// Name of the package the command component is available as to `--compose-config` documents
+pub const MAIN_PACKAGE: &str = "cgwasm:main";
+
+/// Composes the `main` component with packages referenced by the WAC document at `path`
+/// and returns the encoded composition with digests of the packages by name.
+///
+/// The component is available to the document as [`MAIN_PACKAGE`], other packages `NS:NAME`
+/// are read from `deps/NS/NAME.wasm` next to the document, like `wac compose` does,
+/// ignoring versions. If `verifier` is set, each of them must be signed by `PATH.sig`
+pub async fn compose(
+    main: Vec<u8>,
+    path: &Path,
+    verifier: Option<&Verifier>,
+) -> anyhow::Result<(Vec<u8>, BTreeMap<String, String>)> {
+    let source = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    let document = wac_parser::Document::parse(&source)
+        .with_context(|| format!("failed to parse `{}`", path.display()))?;
+    let keys = wac_resolver::packages(&document)
+        .with_context(|| format!("failed to find packages of `{}`", path.display()))?;
+    let deps = path.parent().unwrap_or(Path::new(".")).join("deps");
+    let mut main = Some(main);
+    let mut packages = Vec::with_capacity(keys.len());
+    let mut digests = BTreeMap::new();
+    for key in keys.into_keys() {
+        if key.name == MAIN_PACKAGE {
+            let wasm = main
+                .take()
+                .with_context(|| format!("`{MAIN_PACKAGE}` referenced with multiple versions"))?;
+            packages.push((key, wasm));
+            continue;
+        }
+        let (ns, name) = key
+            .name
+            .split_once(':')
+            .with_context(|| format!("invalid package name `{key}`"))?;
+        let path = deps.join(ns).join(format!("{name}.wasm"));
+        let wasm = fs::read(&path)
+            .await
+            .with_context(|| format!("failed to read package `{key}` at `{}`", path.display()))?;
+        if let Some(verifier) = verifier {
+            verifier
+                .verify(&wasm, &signature::default_path(&path))
+                .await
+                .with_context(|| format!("failed to verify `{}`", path.display()))?;
+        }
+        digests.insert(key.to_string(), provenance::digest(&wasm));
+        packages.push((key, wasm));
+    }
+    if main.is_some() {
+        bail!("`{}` does not reference `{MAIN_PACKAGE}`", path.display());
+    }
+    let resolution = document
+        .resolve(packages.into_iter().collect())
+        .with_context(|| format!("failed to resolve `{}`", path.display()))?;
+    let wasm = resolution
+        .encode(EncodeOptions::default())
+        .context("failed to encode composition")?;
+    Ok((wasm, digests))
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _};
+use tokio::fs;
+use wasmtime::component::Linker;
+
+use bindings::wasi::config::store;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "wasi:config/imports",
+        trappable_imports: true,
+    });
+}
+
+/// Parses a `KEY=VALUE` pair
+pub fn parse_key_value(s: &str) -> anyhow::Result<(String, String)> {
+    let Some((k, v)) = s.split_once('=') else {
+        bail!("`{s}` is not a valid `KEY=VALUE` pair");
+    };
+    Ok((k.to_string(), v.to_string()))
+}
+
+/// Loads guest configuration from the `[config]` table of a TOML file at `path`
+pub async fn load(path: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    let buf = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    let mut file: toml::Table = buf
+        .parse()
+        .with_context(|| format!("failed to parse `{}`", path.display()))?;
+    let Some(config) = file.remove("config") else {
+        return Ok(BTreeMap::default());
+    };
+    let toml::Value::Table(config) = config else {
+        bail!("`config` in `{}` is not a table", path.display());
+    };
+    config
+        .into_iter()
+        .map(|(k, v)| match v {
+            toml::Value::String(v) => Ok((k, v)),
+            toml::Value::Integer(..)
+            | toml::Value::Float(..)
+            | toml::Value::Boolean(..)
+            | toml::Value::Datetime(..) => Ok((k, v.to_string())),
+            toml::Value::Array(..) | toml::Value::Table(..) => {
+                bail!("`config.{k}` in `{}` is not a scalar value", path.display())
+            }
+        })
+        .collect()
+}
+
+/// `wasi:config` state shared by all instances
+#[derive(Clone, Default)]
+pub struct ConfigCtx {
+    values: Arc<BTreeMap<String, String>>,
+}
+
+impl ConfigCtx {
+    pub fn new(values: BTreeMap<String, String>) -> Self {
+        Self {
+            values: Arc::new(values),
+        }
+    }
+}
+
+/// A view into the `wasi:config` state of an instance
+pub struct Config<'a> {
+    ctx: &'a ConfigCtx,
+}
+
+impl<'a> Config<'a> {
+    pub fn new(ctx: &'a ConfigCtx) -> Self {
+        Self { ctx }
+    }
+}
+
+impl store::Host for Config<'_> {
+    fn get(&mut self, key: String) -> wasmtime::Result<Result<Option<String>, store::Error>> {
+        Ok(Ok(self.ctx.values.get(&key).cloned()))
+    }
+
+    fn get_all(&mut self) -> wasmtime::Result<Result<Vec<(String, String)>, store::Error>> {
+        let values = &self.ctx.values;
+        Ok(Ok(values
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()))
+    }
+}
+
+/// Adds `wasi:config` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> Config<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    store::add_to_linker_get_host(linker, f)
+}
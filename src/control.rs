@@ -0,0 +1,157 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::cancel::Cancel;
+
+/// Delay before accepting connections again after a failure to accept one
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Control server handling line-based commands on a Unix socket or read from a file on `SIGUSR2`.
+///
+/// Supported commands are:
+/// - `freeze INDEX` - pauses the instance by freezing its cgroup
+/// - `thaw INDEX` - resumes a frozen instance
+/// - `cancel INDEX` - interrupts the instance, leaving other instances running
+/// - `restart INDEX` - interrupts the instance and instantiates it anew within its sandbox
+pub struct Control {
+    cg: Arc<Path>,
+    names: Arc<[String]>,
+    cancel: Arc<Cancel>,
+}
+
+impl Control {
+    pub fn new(cg: Arc<Path>, names: Vec<String>, cancel: Arc<Cancel>) -> Self {
+        Self {
+            cg,
+            names: names.into(),
+            cancel,
+        }
+    }
+
+    /// Binds a Unix socket at `path`
+    pub fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+        UnixListener::bind(path)
+            .with_context(|| format!("failed to bind control socket at `{}`", path.display()))
+    }
+
+    /// Accepts connections on `listener` until the task is aborted
+    pub async fn serve(self: Arc<Self>, listener: UnixListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    eprintln!("failed to accept control connection: {err}");
+                    // errors like `EMFILE` persist until other connections are closed
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let this = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(err) = this.handle(stream).await {
+                    eprintln!("failed to handle control connection: {err:#}");
+                }
+            });
+        }
+    }
+
+    /// Executes commands of the file at `path` on every `SIGUSR2` until the task is aborted,
+    /// for one-shot runs to control individual instances without a socket
+    pub async fn on_signal(self: Arc<Self>, path: PathBuf) {
+        let mut user = match signal(SignalKind::user_defined2()) {
+            Ok(user) => user,
+            Err(err) => {
+                eprintln!("failed to install `SIGUSR2` handler: {err}");
+                return;
+            }
+        };
+        while user.recv().await.is_some() {
+            let cmds = match fs::read_to_string(&path).await {
+                Ok(cmds) => cmds,
+                Err(err) => {
+                    eprintln!(
+                        "received SIGUSR2, failed to read `{}`: {err}",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+            for cmd in cmds.lines().map(str::trim).filter(|cmd| !cmd.is_empty()) {
+                if let Err(err) = self.exec(cmd).await {
+                    eprintln!("failed to execute `{cmd}` on SIGUSR2: {err:#}");
+                }
+            }
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> anyhow::Result<()> {
+        let (rx, mut tx) = stream.into_split();
+        let mut lines = BufReader::new(rx).lines();
+        while let Some(line) = lines.next_line().await.context("failed to read command")? {
+            let res = match self.exec(line.trim()).await {
+                Ok(()) => "ok\n".to_string(),
+                Err(err) => format!("error: {err:#}\n"),
+            };
+            tx.write_all(res.as_bytes())
+                .await
+                .context("failed to write response")?;
+        }
+        Ok(())
+    }
+
+    async fn exec(&self, cmd: &str) -> anyhow::Result<()> {
+        let (cmd, args) = cmd.split_once(' ').unwrap_or((cmd, ""));
+        match cmd {
+            "freeze" => freeze(&self.sandbox(args)?, true).await,
+            "thaw" => freeze(&self.sandbox(args)?, false).await,
+            "cancel" => {
+                let index = index(args)?;
+                if self.cancel.cancel(index)? {
+                    eprintln!("instance {index} cancelled via control command");
+                }
+                Ok(())
+            }
+            "restart" => {
+                let index = index(args)?;
+                if !self.cancel.restart(index)? {
+                    bail!("instance {index} is not running");
+                }
+                eprintln!("instance {index} restarted via control command");
+                Ok(())
+            }
+            _ => bail!("unknown command `{cmd}`"),
+        }
+    }
+
+    /// Returns the cgroup path of the sandbox with index `arg`
+    fn sandbox(&self, arg: &str) -> anyhow::Result<PathBuf> {
+        let index = index(arg)?;
+        let name = self
+            .names
+            .get(index)
+            .with_context(|| format!("instance {index} does not exist"))?;
+        Ok(self.cg.join(name))
+    }
+}
+
+fn index(arg: &str) -> anyhow::Result<usize> {
+    arg.trim()
+        .parse()
+        .with_context(|| format!("invalid instance index `{arg}`"))
+}
+
+/// Freezes or thaws the cgroup at `path`
+async fn freeze(path: &Path, frozen: bool) -> anyhow::Result<()> {
+    let path = path.join("cgroup.freeze");
+    let v = if frozen { "1" } else { "0" };
+    fs::write(&path, v)
+        .await
+        .with_context(|| format!("failed to write `{v}` to `{}`", path.display()))
+}
@@ -0,0 +1,161 @@
+use core::fmt::{self, Display};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use anyhow::{ensure, Context as _};
+use serde::Serialize;
+
+use crate::invoke::Requests;
+use crate::source::WasmSource;
+
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+pub struct Args {
+    /// Path to the baseline version of the component, compared against the component to use
+    old: WasmSource,
+
+    /// File of `--invoke` function arguments, one call per line, called on both versions.
+    ///
+    /// Lines are encoded as for `--invoke-stdin`
+    #[clap(long, value_name = "FILE")]
+    inputs: PathBuf,
+
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
+
+    #[command(flatten)]
+    run: crate::Args,
+}
+
+/// Outcome of an instance or result of a call differing between versions,
+/// `None` if a version has none
+#[derive(Debug, Serialize)]
+struct Divergence {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.instance, self.line) {
+            (Some(i), _) => write!(f, "instance {i}: ")?,
+            (_, Some(n)) => write!(f, "line {n}: ")?,
+            (None, None) => {}
+        }
+        let (old, new) = (
+            self.old.as_deref().unwrap_or("none"),
+            self.new.as_deref().unwrap_or("none"),
+        );
+        write!(f, "old `{old}`, new `{new}`")
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    instances: usize,
+    lines: usize,
+    divergences: Vec<Divergence>,
+}
+
+/// Runs the component with `lines` as calls of the `--invoke` function
+/// and returns outcomes of all instances and results of all calls
+fn execute(
+    mut run: crate::Args,
+    wasm: WasmSource,
+    lines: &[String],
+) -> anyhow::Result<(Vec<String>, BTreeMap<usize, String>)> {
+    eprintln!("running `{wasm}`");
+    let requests = Requests::lines(lines.to_vec());
+    run.wasm = wasm;
+    run.requests = Some(Arc::clone(&requests));
+    let outcomes = crate::execute(run)?
+        .into_iter()
+        .map(|(outcome, _)| outcome.to_string())
+        .collect();
+    Ok((outcomes, requests.results()))
+}
+
+/// Runs both versions of the component in sandboxes with identical calls and deterministic
+/// host behavior and prints outcomes and results differing between them
+pub fn run(
+    Args {
+        old,
+        inputs,
+        json,
+        mut run,
+    }: Args,
+) -> anyhow::Result<ExitCode> {
+    ensure!(run.invoke.is_some(), "`diff` requires `--invoke`");
+    ensure!(
+        !run.invoke_stdin,
+        "`--invoke-stdin` cannot be used with `diff`, calls are read from `--inputs`"
+    );
+    ensure!(
+        !(old.is_stdin() && run.wasm.is_stdin()),
+        "only one version of the component can be read from stdin"
+    );
+    let lines = std::fs::read_to_string(&inputs)
+        .with_context(|| format!("failed to read `{}`", inputs.display()))?;
+    let lines: Vec<String> = lines.lines().map(String::from).collect();
+    run.deterministic = true;
+
+    let _otlp = crate::setup(&run)?;
+    let new = run.wasm.clone();
+    let (old_outcomes, old_results) = execute(run.clone(), old, &lines)?;
+    let (new_outcomes, new_results) = execute(run, new, &lines)?;
+
+    let instances = old_outcomes.len().max(new_outcomes.len());
+    let mut divergences = Vec::new();
+    for i in 0..instances {
+        let (old, new) = (old_outcomes.get(i), new_outcomes.get(i));
+        if old != new {
+            divergences.push(Divergence {
+                instance: Some(i),
+                line: None,
+                old: old.cloned(),
+                new: new.cloned(),
+            });
+        }
+    }
+    let calls: BTreeSet<_> = old_results.keys().chain(new_results.keys()).collect();
+    for n in calls {
+        let (old, new) = (old_results.get(n), new_results.get(n));
+        if old != new {
+            divergences.push(Divergence {
+                instance: None,
+                line: Some(*n),
+                old: old.cloned(),
+                new: new.cloned(),
+            });
+        }
+    }
+    let report = Report {
+        instances,
+        lines: lines.iter().filter(|line| !line.trim().is_empty()).count(),
+        divergences,
+    };
+    if json {
+        let buf = serde_json::to_string_pretty(&report).context("failed to encode report")?;
+        println!("{buf}");
+    } else {
+        println!("instances: {}", report.instances);
+        println!("lines: {}", report.lines);
+        println!("divergences: {}", report.divergences.len());
+        for divergence in &report.divergences {
+            println!("{divergence}");
+        }
+    }
+    if report.divergences.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
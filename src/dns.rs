@@ -0,0 +1,430 @@
+use core::net::{IpAddr, Ipv6Addr, SocketAddr};
+use core::str::FromStr;
+use core::time::Duration;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context as _};
+use tokio::fs;
+use tokio::net::UdpSocket;
+use tokio::runtime::Handle;
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::bindings::sockets::ip_name_lookup::{
+    self, HostResolveAddressStream, ResolveAddressStream,
+};
+use wasmtime_wasi::bindings::sockets::network::{self, ErrorCode, IpAddress, Network};
+use wasmtime_wasi::{Pollable, SocketError, WasiImpl, WasiView as _};
+
+use crate::Ctx;
+
+/// Timeout of a single query sent to a DNS server
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// DNS record types
+const A: u16 = 1;
+const AAAA: u16 = 28;
+
+/// Name resolution of `wasi:sockets/ip-name-lookup`, one of `host`, `static:FILE` or `server:ADDR`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Resolve names using the host resolver
+    #[default]
+    Host,
+    /// Resolve names only from a hosts file
+    Static(PathBuf),
+    /// Resolve names by querying a DNS server over UDP, port 53 by default
+    Server(SocketAddr),
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "host" => Ok(Self::Host),
+            Some(("static", path)) if !path.is_empty() => Ok(Self::Static(path.into())),
+            Some(("server", addr)) => {
+                let addr = addr
+                    .parse()
+                    .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .with_context(|| format!("invalid DNS server address `{addr}`"))?;
+                Ok(Self::Server(addr))
+            }
+            _ => bail!("invalid DNS mode `{s}`, expected `host`, `static:FILE` or `server:ADDR`"),
+        }
+    }
+}
+
+/// Hosts file of the form `[INDEX@]PATH`, entries of which take precedence over the DNS mode
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostsFile {
+    instance: Option<usize>,
+    path: PathBuf,
+}
+
+impl FromStr for HostsFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (instance, path) = match s.split_once('@') {
+            Some((instance, path)) => {
+                let instance = instance
+                    .parse()
+                    .with_context(|| format!("invalid instance index `{instance}` in `{s}`"))?;
+                (Some(instance), path)
+            }
+            None => (None, s),
+        };
+        ensure!(!path.is_empty(), "empty path in `{s}`");
+        Ok(Self {
+            instance,
+            path: path.into(),
+        })
+    }
+}
+
+/// Addresses of names in `/etc/hosts` format
+#[derive(Debug, Default)]
+struct Hosts(HashMap<String, Vec<IpAddr>>);
+
+impl Hosts {
+    /// Reads entries from the file at `path`, adding to existing ones
+    async fn load(&mut self, path: &Path) -> anyhow::Result<()> {
+        let buf = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        for (i, line) in buf.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(line, _)| line);
+            let mut fields = line.split_whitespace();
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+            let addr = addr.parse::<IpAddr>().with_context(|| {
+                format!(
+                    "invalid address `{addr}` on line {} of `{}`",
+                    i + 1,
+                    path.display()
+                )
+            })?;
+            for name in fields {
+                let name = name.trim_end_matches('.').to_ascii_lowercase();
+                let addrs = self.0.entry(name).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Option<Vec<IpAddr>> {
+        self.0.get(name).cloned()
+    }
+}
+
+/// Resolver queried for names not found in hosts files
+#[derive(Debug)]
+enum Upstream {
+    Host,
+    Server(SocketAddr),
+}
+
+/// Resolver shared by all instances.
+///
+/// Lookups run on the runtime the resolver is created on, within the host network namespace,
+/// since sandboxes have no network of their own to reach the host resolver from
+#[derive(Debug)]
+pub struct Resolver {
+    rt: Handle,
+    /// Resolver to query, if not resolving from hosts files only
+    upstream: Option<Upstream>,
+    hosts: Hosts,
+    instance_hosts: BTreeMap<usize, Hosts>,
+}
+
+impl Resolver {
+    /// Creates a resolver of the current runtime, loading all hosts files
+    pub async fn load(mode: &Mode, hosts_files: &[HostsFile]) -> anyhow::Result<Arc<Self>> {
+        let mut hosts = Hosts::default();
+        let upstream = match mode {
+            Mode::Host => Some(Upstream::Host),
+            Mode::Static(path) => {
+                hosts.load(path).await?;
+                None
+            }
+            Mode::Server(addr) => Some(Upstream::Server(*addr)),
+        };
+        let mut instance_hosts = BTreeMap::<usize, Hosts>::new();
+        for HostsFile { instance, path } in hosts_files {
+            match instance {
+                Some(instance) => instance_hosts.entry(*instance).or_default(),
+                None => &mut hosts,
+            }
+            .load(path)
+            .await?;
+        }
+        Ok(Arc::new(Self {
+            rt: Handle::current(),
+            upstream,
+            hosts,
+            instance_hosts,
+        }))
+    }
+
+    async fn resolve(&self, index: usize, mut name: String) -> Result<Vec<IpAddr>, ErrorCode> {
+        if name.ends_with('.') {
+            name.pop();
+        }
+        if let Some(addrs) = self
+            .instance_hosts
+            .get(&index)
+            .and_then(|hosts| hosts.get(&name))
+            .or_else(|| self.hosts.get(&name))
+        {
+            return Ok(addrs);
+        }
+        let addrs = match self.upstream {
+            None => return Err(ErrorCode::NameUnresolvable),
+            Some(Upstream::Host) => self.rt.spawn(async move {
+                let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                    .await
+                    .map_err(|_| ErrorCode::NameUnresolvable)?;
+                Ok(addrs.map(|addr| addr.ip().to_canonical()).collect())
+            }),
+            Some(Upstream::Server(server)) => self.rt.spawn(async move {
+                let (a, aaaa) = tokio::join!(query(server, &name, A), query(server, &name, AAAA));
+                match (a, aaaa) {
+                    (Err(err), Err(_)) => {
+                        eprintln!("failed to resolve `{name}` using `{server}`: {err:#}");
+                        Err(ErrorCode::TemporaryResolverFailure)
+                    }
+                    (a, aaaa) => Ok(a.into_iter().chain(aaaa).flatten().collect()),
+                }
+            }),
+        };
+        let addrs: Vec<IpAddr> = addrs
+            .await
+            .map_err(|_| ErrorCode::TemporaryResolverFailure)??;
+        if addrs.is_empty() {
+            return Err(ErrorCode::NameUnresolvable);
+        }
+        Ok(addrs)
+    }
+}
+
+/// Queries `server` for records of `qtype` of `name`, an unknown name has no records
+async fn query(server: SocketAddr, name: &str, qtype: u16) -> anyhow::Result<Vec<IpAddr>> {
+    let id = rand::random::<u16>();
+    let mut msg = Vec::with_capacity(512);
+    msg.extend(id.to_be_bytes());
+    // recursion desired, one question
+    msg.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        ensure!(
+            !label.is_empty() && label.len() < 64,
+            "invalid label `{label}`"
+        );
+        msg.push(label.len() as u8);
+        msg.extend(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend(qtype.to_be_bytes());
+    msg.extend([0, 1]);
+
+    let bind: SocketAddr = if server.is_ipv4() {
+        (IpAddr::from([0; 4]), 0).into()
+    } else {
+        (IpAddr::from(Ipv6Addr::UNSPECIFIED), 0).into()
+    };
+    let socket = UdpSocket::bind(bind)
+        .await
+        .context("failed to bind UDP socket")?;
+    socket
+        .connect(server)
+        .await
+        .context("failed to connect UDP socket")?;
+    socket.send(&msg).await.context("failed to send query")?;
+    let mut buf = [0; 1232];
+    let n = tokio::time::timeout(QUERY_TIMEOUT, async {
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            // ignore responses to other queries
+            if n >= 12 && buf[..2] == id.to_be_bytes() {
+                return std::io::Result::Ok(n);
+            }
+        }
+    })
+    .await
+    .context("query timed out")?
+    .context("failed to receive response")?;
+    parse_response(&buf[..n], qtype)
+}
+
+fn parse_response(buf: &[u8], qtype: u16) -> anyhow::Result<Vec<IpAddr>> {
+    let u16_at = |pos: usize| {
+        buf.get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .context("truncated response")
+    };
+    match buf[3] & 0x0f {
+        0 => {}
+        // NXDOMAIN
+        3 => return Ok(Vec::new()),
+        rcode => bail!("server responded with code {rcode}"),
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(buf, pos)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(buf, pos)?;
+        let ty = u16_at(pos)?;
+        let class = u16_at(pos + 2)?;
+        let len = usize::from(u16_at(pos + 8)?);
+        pos += 10;
+        let data = buf.get(pos..pos + len).context("truncated record")?;
+        pos += len;
+        if ty != qtype || class != 1 {
+            continue;
+        }
+        match (ty, <[u8; 4]>::try_from(data), <[u8; 16]>::try_from(data)) {
+            (A, Ok(ip), _) => addrs.push(IpAddr::from(ip)),
+            (AAAA, _, Ok(ip)) => addrs.push(IpAddr::from(ip).to_canonical()),
+            _ => bail!("invalid record of type {ty}"),
+        }
+    }
+    Ok(addrs)
+}
+
+/// Returns the position after the, possibly compressed, name at `pos`
+fn skip_name(buf: &[u8], mut pos: usize) -> anyhow::Result<usize> {
+    loop {
+        let len = *buf.get(pos).context("truncated name")?;
+        match len {
+            0 => return Ok(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += 1 + usize::from(len),
+        }
+    }
+}
+
+/// Name resolution of a single instance
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    resolver: Arc<Resolver>,
+    index: usize,
+}
+
+impl Lookup {
+    pub fn new(resolver: Arc<Resolver>, index: usize) -> Self {
+        Self { resolver, index }
+    }
+}
+
+/// `wasi:sockets/ip-name-lookup` implementation using the [`Resolver`]
+pub struct NameLookup<'a> {
+    wasi: WasiImpl<&'a mut Ctx>,
+}
+
+impl<'a> NameLookup<'a> {
+    pub fn new(ctx: &'a mut Ctx) -> Self {
+        Self {
+            wasi: WasiImpl(ctx),
+        }
+    }
+}
+
+/// Replaces `wasi:sockets/ip-name-lookup` in the linker with one using the [`Resolver`]
+pub fn add_to_linker(
+    linker: &mut Linker<Ctx>,
+    f: impl Fn(&mut Ctx) -> NameLookup<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.allow_shadowing(true);
+    let res = ip_name_lookup::add_to_linker_get_host(linker, f);
+    linker.allow_shadowing(false);
+    res
+}
+
+impl network::Host for NameLookup<'_> {
+    fn convert_error_code(&mut self, err: SocketError) -> anyhow::Result<ErrorCode> {
+        self.wasi.convert_error_code(err)
+    }
+
+    fn network_error_code(
+        &mut self,
+        err: Resource<anyhow::Error>,
+    ) -> anyhow::Result<Option<ErrorCode>> {
+        self.wasi.network_error_code(err)
+    }
+}
+
+impl network::HostNetwork for NameLookup<'_> {
+    fn drop(&mut self, network: Resource<Network>) -> anyhow::Result<()> {
+        network::HostNetwork::drop(&mut self.wasi, network)
+    }
+}
+
+#[async_trait::async_trait]
+impl ip_name_lookup::Host for NameLookup<'_> {
+    fn resolve_addresses(
+        &mut self,
+        network: Resource<Network>,
+        name: String,
+    ) -> Result<Resource<ResolveAddressStream>, SocketError> {
+        let network = self.wasi.table().get(&network)?;
+        // validates the name and converts unicode domains to punycode, like the default implementation
+        let host = match url::Host::parse(&name) {
+            Ok(host) => host,
+            Err(_) => match name.parse::<Ipv6Addr>() {
+                Ok(addr) => url::Host::Ipv6(addr),
+                Err(_) => return Err(ErrorCode::InvalidArgument.into()),
+            },
+        };
+        if !network.allow_ip_name_lookup {
+            return Err(ErrorCode::PermanentResolverFailure.into());
+        }
+        let stream = match host {
+            url::Host::Ipv4(addr) => {
+                ResolveAddressStream::Done(Ok(vec![IpAddress::from(IpAddr::V4(addr))].into_iter()))
+            }
+            url::Host::Ipv6(addr) => ResolveAddressStream::Done(Ok(vec![IpAddress::from(
+                IpAddr::V6(addr).to_canonical(),
+            )]
+            .into_iter())),
+            url::Host::Domain(name) => {
+                let Lookup { resolver, index } = self.wasi.0.dns.clone();
+                ResolveAddressStream::Waiting(wasmtime_wasi::runtime::spawn(async move {
+                    let addrs = resolver.resolve(index, name).await?;
+                    Ok(addrs.into_iter().map(IpAddress::from).collect())
+                }))
+            }
+        };
+        Ok(self.wasi.table().push(stream)?)
+    }
+}
+
+#[async_trait::async_trait]
+impl HostResolveAddressStream for NameLookup<'_> {
+    fn resolve_next_address(
+        &mut self,
+        stream: Resource<ResolveAddressStream>,
+    ) -> Result<Option<IpAddress>, SocketError> {
+        self.wasi.resolve_next_address(stream)
+    }
+
+    fn subscribe(
+        &mut self,
+        stream: Resource<ResolveAddressStream>,
+    ) -> anyhow::Result<Resource<Pollable>> {
+        HostResolveAddressStream::subscribe(&mut self.wasi, stream)
+    }
+
+    fn drop(&mut self, stream: Resource<ResolveAddressStream>) -> anyhow::Result<()> {
+        HostResolveAddressStream::drop(&mut self.wasi, stream)
+    }
+}
@@ -0,0 +1,325 @@
+use core::fmt::{self, Display};
+use core::num::NonZeroUsize;
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use nix::unistd::{access, AccessFlags};
+use wasmtime::InstanceAllocationStrategy;
+
+use crate::addrspace::Budget;
+use crate::{
+    cgroup, max_memory_size_or_default, new_pooling_config, use_pooling_allocator_by_default,
+    POOLING_SLOTS_PER_INSTANCE,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Amount of instances to check the pooling allocator for
+    #[clap(long, short, default_value = "1")]
+    count: NonZeroUsize,
+}
+
+/// Controllers used by sandbox cgroups
+const CONTROLLERS: [&str; 4] = ["cpu", "cpuset", "pids", "memory"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Warn => write!(f, "WARN"),
+            Self::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// Result of a single environment check with a remediation hint, if it did not pass
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Reads and parses a single integer from `path`, like a sysctl
+fn read_int(path: impl AsRef<Path>) -> Option<i64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Returns the cgroup v2 path of the process within the hierarchy mounted at `root`, if any
+fn own_cgroup(root: &Path) -> Option<PathBuf> {
+    let cg = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    cg.lines()
+        .find_map(|line| line.strip_prefix("0::/"))
+        .map(|cg| root.join(cg))
+}
+
+/// Checks the cgroup v2 hierarchy, delegation of the cgroup of the process
+/// and controllers available within it
+fn check_cgroups(checks: &mut Vec<Check>) {
+    let mounts = match cgroup::Mounts::read() {
+        Ok(mounts) => mounts,
+        Err(err) => {
+            checks.push(Check::fail(
+                "cgroup v2",
+                format!("failed to read `/proc/self/mountinfo`: {err}"),
+                "mount `/proc`",
+            ));
+            return;
+        }
+    };
+    let Some(root) = mounts.unified else {
+        checks.push(Check::fail(
+            "cgroup v2",
+            "cgroup v2 hierarchy is not mounted",
+            "boot with `systemd.unified_cgroup_hierarchy=1` or run via `--cgroups off`",
+        ));
+        return;
+    };
+    checks.push(Check::ok(
+        "cgroup v2",
+        format!("mounted at `{}`", root.display()),
+    ));
+
+    let Some(cg) = own_cgroup(&root) else {
+        checks.push(Check::fail(
+            "cgroup delegation",
+            "process does not run within cgroup v2",
+            "disable cgroup v1 controllers or run via `--cgroups off`",
+        ));
+        return;
+    };
+    let writable = ["cgroup.subtree_control", "cgroup.procs"]
+        .iter()
+        .map(|name| cg.join(name))
+        .find(|path| access(path, AccessFlags::W_OK).is_err());
+    checks.push(match writable {
+        None => Check::ok(
+            "cgroup delegation",
+            format!("`{}` is writable", cg.display()),
+        ),
+        Some(path) => Check::fail(
+            "cgroup delegation",
+            format!("`{}` is not writable", path.display()),
+            "run via `--cgroup-driver systemd`, \
+            `systemd-run --user --scope -p Delegate=yes` or pass a delegated cgroup via `--cgroup`",
+        ),
+    });
+
+    let controllers = match std::fs::read_to_string(cg.join("cgroup.controllers")) {
+        Ok(controllers) => controllers,
+        Err(err) => {
+            checks.push(Check::fail(
+                "cgroup controllers",
+                format!("failed to read `cgroup.controllers`: {err}"),
+                "run within a cgroup v2 cgroup",
+            ));
+            return;
+        }
+    };
+    let missing: Vec<_> = CONTROLLERS
+        .into_iter()
+        .filter(|c| !controllers.split_whitespace().any(|ac| ac == *c))
+        .collect();
+    checks.push(if missing.is_empty() {
+        Check::ok("cgroup controllers", controllers.trim())
+    } else {
+        Check::warn(
+            "cgroup controllers",
+            format!("missing {}", missing.join(" ")),
+            format!(
+                "delegate them, like `Delegate={}`, or enable them in `cgroup.subtree_control` \
+                of the parent cgroup, limits of missing controllers are not applied",
+                CONTROLLERS.join(" ")
+            ),
+        )
+    });
+}
+
+/// Checks whether unprivileged processes may create user namespaces
+fn check_userns(checks: &mut Vec<Check>) {
+    const NAME: &str = "user namespaces";
+
+    if read_int("/proc/sys/user/max_user_namespaces") == Some(0) {
+        checks.push(Check::fail(
+            NAME,
+            "`user.max_user_namespaces` is 0",
+            "run `sysctl -w user.max_user_namespaces=15000`",
+        ));
+    } else if read_int("/proc/sys/kernel/unprivileged_userns_clone") == Some(0) {
+        checks.push(Check::fail(
+            NAME,
+            "`kernel.unprivileged_userns_clone` is 0",
+            "run `sysctl -w kernel.unprivileged_userns_clone=1`",
+        ));
+    } else if read_int("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        checks.push(Check::fail(
+            NAME,
+            "`kernel.apparmor_restrict_unprivileged_userns` is 1",
+            "add an AppArmor profile granting `userns` to cgwasm or \
+            run `sysctl -w kernel.apparmor_restrict_unprivileged_userns=0`",
+        ));
+    } else {
+        checks.push(Check::ok(NAME, "unprivileged user namespaces are allowed"));
+    }
+}
+
+/// Checks the lowest port unprivileged processes may bind in the host network namespace
+fn check_ports(checks: &mut Vec<Check>) {
+    const NAME: &str = "unprivileged ports";
+
+    match read_int("/proc/sys/net/ipv4/ip_unprivileged_port_start") {
+        Some(start) if start > 0 => checks.push(Check::warn(
+            NAME,
+            format!("ports below {start} require privileges"),
+            "bind the health check server to a higher port or \
+            run `sysctl -w net.ipv4.ip_unprivileged_port_start=0`",
+        )),
+        Some(_) => checks.push(Check::ok(NAME, "all ports may be bound")),
+        None => checks.push(Check::warn(
+            NAME,
+            "failed to read `net.ipv4.ip_unprivileged_port_start`",
+            "ports below 1024 may require privileges",
+        )),
+    }
+}
+
+/// Checks whether soft rlimits capping the instance count can be raised
+fn check_rlimits(checks: &mut Vec<Check>) {
+    for (name, resource, flag) in [
+        ("NOFILE rlimit", rlimit::Resource::NOFILE, "-n"),
+        ("NPROC rlimit", rlimit::Resource::NPROC, "-u"),
+    ] {
+        match resource.get() {
+            Ok((soft, hard)) if soft < hard => checks.push(Check::warn(
+                name,
+                format!("soft limit {soft} is below hard limit {hard}"),
+                format!("run `ulimit {flag} {hard}` to allow more instances"),
+            )),
+            Ok((soft, _)) => checks.push(Check::ok(name, soft.to_string())),
+            Err(err) => checks.push(Check::fail(
+                name,
+                format!("failed to get rlimit: {err}"),
+                "check `getrlimit` is permitted",
+            )),
+        }
+    }
+}
+
+/// Checks whether the pooling allocator can reserve slots for `count` instances
+fn check_pooling(checks: &mut Vec<Check>, count: usize) {
+    const NAME: &str = "pooling allocator";
+
+    match use_pooling_allocator_by_default() {
+        Ok(true) => {}
+        Ok(false) => {
+            checks.push(Check::warn(
+                NAME,
+                "virtual address space is too small, on-demand allocator is used",
+                "raise `ulimit -v` or set `vm.overcommit_memory` to 0 or 1",
+            ));
+            return;
+        }
+        Err(err) => {
+            checks.push(Check::warn(
+                NAME,
+                format!("failed to probe virtual address space: {err:#}"),
+                "on-demand allocator is used, set `WASMTIME_POOLING=true` to force pooling",
+            ));
+            return;
+        }
+    }
+    let instances = u32::try_from(count)
+        .unwrap_or(u32::MAX)
+        .saturating_mul(POOLING_SLOTS_PER_INSTANCE);
+    let mut config = wasmtime::Config::default();
+    config.wasm_component_model(true);
+    config.async_support(true);
+    let max_memory_size = max_memory_size_or_default(None);
+    config.allocation_strategy(InstanceAllocationStrategy::Pooling(new_pooling_config(
+        instances,
+        max_memory_size,
+    )));
+    if let Some(limit) = Budget::read().exceeded(instances, max_memory_size) {
+        checks.push(Check::warn(
+            "address space",
+            format!("reservation of {instances} slots exceeds {limit}"),
+            "lower `--count` or `--max-memory-size` of runs, \
+            or raise `vm.max_map_count` or `ulimit -v`",
+        ));
+    }
+    checks.push(match wasmtime::Engine::new(&config) {
+        Ok(_) => Check::ok(NAME, format!("{instances} slots for {count} instances")),
+        Err(err) => Check::fail(
+            NAME,
+            format!("failed to reserve {instances} slots: {err:#}"),
+            "lower `--count` or `--max-memory-size` of runs, \
+            or raise `ulimit -v`",
+        ),
+    });
+}
+
+/// Checks the environment for kernel features and limits required to run sandboxes
+/// and prints remediation hints for failed checks
+pub fn run(Args { count }: Args) -> anyhow::Result<ExitCode> {
+    let mut checks = Vec::new();
+    check_cgroups(&mut checks);
+    check_userns(&mut checks);
+    check_ports(&mut checks);
+    check_rlimits(&mut checks);
+    check_pooling(&mut checks, count.into());
+
+    for Check {
+        name,
+        status,
+        detail,
+        hint,
+    } in &checks
+    {
+        println!("{status:<6}{name}: {detail}");
+        if let Some(hint) = hint {
+            println!("      hint: {hint}");
+        }
+    }
+    if checks.iter().any(|check| check.status == Status::Fail) {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
@@ -0,0 +1,233 @@
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use wasmtime::CallHook;
+use wasmtime_wasi_http::io::TokioIo;
+
+use crate::cancel::Cancel;
+
+const PENDING: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+/// Delay before accepting connections again after a failure to accept one
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct Instance {
+    state: AtomicU8,
+    /// Time of the last call between the guest and the host in milliseconds since [`Health::start`]
+    progress: AtomicU64,
+    /// Whether the guest is suspended in a host call, which makes it idle rather than wedged
+    in_host: AtomicBool,
+    /// Whether the instance was interrupted by the watchdog since it was last restarted
+    interrupted: AtomicBool,
+}
+
+/// Health of the sandbox pool, updated by sandboxes and reported by the health check server
+#[derive(Debug)]
+pub struct Health {
+    start: Instant,
+    wedged_after: Duration,
+    compiled: AtomicBool,
+    instances: Box<[Instance]>,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    compiled: bool,
+    instantiated: usize,
+    running: usize,
+    wedged: Vec<usize>,
+}
+
+impl Health {
+    /// Creates health state for `count` instances, considered wedged if they did not make
+    /// progress for `wedged_after`
+    pub fn new(count: usize, wedged_after: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            wedged_after,
+            compiled: AtomicBool::default(),
+            instances: (0..count).map(|_| Instance::default()).collect(),
+        }
+    }
+
+    pub fn set_compiled(&self) {
+        self.compiled.store(true, Ordering::Relaxed);
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start
+            .elapsed()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Marks instance at `index` as instantiated and running
+    pub fn set_running(&self, index: usize) {
+        if let Some(instance) = self.instances.get(index) {
+            instance
+                .progress
+                .store(self.elapsed_ms(), Ordering::Relaxed);
+            instance.in_host.store(false, Ordering::Relaxed);
+            instance.state.store(RUNNING, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks instance at `index` as pending again on restart, clearing its interruption
+    /// by the watchdog
+    pub fn reset(&self, index: usize) {
+        if let Some(instance) = self.instances.get(index) {
+            instance.state.store(PENDING, Ordering::Relaxed);
+            instance.interrupted.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks instance at `index` as done
+    pub fn set_done(&self, index: usize) {
+        if let Some(instance) = self.instances.get(index) {
+            instance.state.store(DONE, Ordering::Relaxed);
+        }
+    }
+
+    /// Records progress of instance at `index` on a call between the guest and the host,
+    /// to be called from the call hook of its store.
+    ///
+    /// Guests make progress by calling the host and are idle while suspended in a host call,
+    /// so only guests executing Wasm without calling the host can be wedged
+    pub fn on_call(&self, index: usize, hook: CallHook) {
+        let Some(instance) = self.instances.get(index) else {
+            return;
+        };
+        instance
+            .progress
+            .store(self.elapsed_ms(), Ordering::Relaxed);
+        // host calls may call back into the guest, e.g. to allocate results
+        let in_host = matches!(hook, CallHook::CallingHost | CallHook::ReturningFromWasm);
+        instance.in_host.store(in_host, Ordering::Relaxed);
+    }
+
+    /// Returns whether instance at `index` was interrupted by the watchdog
+    pub fn is_interrupted(&self, index: usize) -> bool {
+        self.instances
+            .get(index)
+            .is_some_and(|instance| instance.interrupted.load(Ordering::Relaxed))
+    }
+
+    /// Whether `instance` is running Wasm and did not call the host for `after`
+    fn is_stalled(&self, instance: &Instance, now: u64, after: Duration) -> bool {
+        let after = after.as_millis().try_into().unwrap_or(u64::MAX);
+        instance.state.load(Ordering::Relaxed) == RUNNING
+            && !instance.in_host.load(Ordering::Relaxed)
+            && now.saturating_sub(instance.progress.load(Ordering::Relaxed)) > after
+    }
+
+    /// Interrupts and restarts running instances which did not make progress for `timeout`
+    /// via `cancel`, checking every second until the task is aborted
+    pub async fn watchdog(self: Arc<Self>, timeout: Duration, cancel: Arc<Cancel>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let now = self.elapsed_ms();
+            for (index, instance) in self.instances.iter().enumerate() {
+                if !self.is_stalled(instance, now, timeout)
+                    || instance.interrupted.swap(true, Ordering::Relaxed)
+                {
+                    continue;
+                }
+                eprintln!("instance {index} made no progress for {timeout:?}, restart");
+                if let Err(err) = cancel.restart(index) {
+                    eprintln!("failed to restart instance {index}: {err:#}");
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        let now = self.elapsed_ms();
+        let mut status = Status {
+            compiled: self.compiled.load(Ordering::Relaxed),
+            instantiated: 0,
+            running: 0,
+            wedged: Vec::new(),
+        };
+        for (index, instance) in self.instances.iter().enumerate() {
+            match instance.state.load(Ordering::Relaxed) {
+                PENDING => continue,
+                RUNNING => {
+                    status.running += 1;
+                    if self.is_stalled(instance, now, self.wedged_after) {
+                        status.wedged.push(index);
+                    }
+                }
+                _ => {}
+            }
+            status.instantiated += 1;
+        }
+        status
+    }
+
+    fn handle(&self, req: &Request<hyper::body::Incoming>) -> Response<Full<Bytes>> {
+        let status = self.status();
+        let code = match req.uri().path() {
+            "/healthz" if status.wedged.is_empty() => StatusCode::OK,
+            "/healthz" => StatusCode::SERVICE_UNAVAILABLE,
+            "/readyz" if status.compiled => StatusCode::OK,
+            "/readyz" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => {
+                let mut res = Response::new(Full::default());
+                *res.status_mut() = StatusCode::NOT_FOUND;
+                return res;
+            }
+        };
+        let body = serde_json::to_vec(&status).unwrap_or_default();
+        let mut res = Response::new(Full::new(Bytes::from(body)));
+        *res.status_mut() = code;
+        res.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("application/json"),
+        );
+        res
+    }
+
+    /// Serves `/healthz` and `/readyz` on `listener` until the task is aborted
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    eprintln!("failed to accept health check connection: {err}");
+                    // errors like `EMFILE` persist until other connections are closed
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let health = Arc::clone(&self);
+            tokio::spawn(async move {
+                let service = service_fn(|req| {
+                    let res = health.handle(&req);
+                    async move { Ok::<_, Infallible>(res) }
+                });
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    eprintln!("failed to serve health check connection: {err}");
+                }
+            });
+        }
+    }
+}
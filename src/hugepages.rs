@@ -0,0 +1,375 @@
+use core::ops::Range;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use std::collections::BTreeSet;
+use std::io;
+
+use anyhow::{bail, Context as _};
+use wasmtime::{LinearMemory, MemoryCreator, MemoryType};
+
+/// Huge pages backing guest linear memories
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HugePages {
+    /// Regular pages only
+    #[default]
+    Off,
+    /// Transparent huge pages, advised via `madvise`, which the kernel falls back
+    /// from on its own
+    Transparent,
+    /// Explicit huge pages of the hugetlbfs pool, falling back to regular pages
+    /// for memories which the pool cannot back
+    Explicit,
+}
+
+/// Returns the default huge page size, `Hugepagesize` of `/proc/meminfo`, 2MiB if unknown
+pub fn size() -> usize {
+    std::fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|meminfo| {
+            let kib = meminfo
+                .lines()
+                .find_map(|line| line.strip_prefix("Hugepagesize:"))?;
+            kib.trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<usize>()
+                .ok()
+        })
+        .map_or(2 << 20, |kib| kib.saturating_mul(1024))
+}
+
+/// Returns whether transparent huge pages may be used for `madvise`d memory
+pub fn transparent_enabled() -> bool {
+    const ENABLED: &str = "/sys/kernel/mm/transparent_hugepage/enabled";
+    match std::fs::read_to_string(ENABLED) {
+        Ok(enabled) => !enabled.contains("[never]"),
+        Err(err) => {
+            eprintln!("failed to read `{ENABLED}`: {err}");
+            false
+        }
+    }
+}
+
+/// Returns the address ranges of anonymous mappings of the process
+pub fn anonymous_mappings() -> io::Result<BTreeSet<(usize, usize)>> {
+    let maps = std::fs::read_to_string("/proc/self/maps")?;
+    Ok(maps
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let range = fields.next()?;
+            // inode and, for file-backed or special mappings, path
+            let inode = fields.nth(3)?;
+            if inode != "0" || fields.next().is_some() {
+                return None;
+            }
+            let (start, end) = range.split_once('-')?;
+            let start = usize::from_str_radix(start, 16).ok()?;
+            let end = usize::from_str_radix(end, 16).ok()?;
+            Some((start, end))
+        })
+        .collect())
+}
+
+/// Advises transparent huge pages for the largest anonymous mapping created since `before`
+/// was taken, the linear memory slots of the pooling allocator reserved during engine
+/// construction, which dwarf its other reservations, and returns its size in bytes.
+///
+/// Slots keep the advice across reuse, since the pool resets them with `madvise`
+/// rather than by mapping them anew, except for pages of copy-on-write memory images
+pub fn advise_pool(before: &BTreeSet<(usize, usize)>) -> anyhow::Result<usize> {
+    let Some((start, end)) = anonymous_mappings()
+        .context("failed to read mappings of the process")?
+        .difference(before)
+        .copied()
+        .max_by_key(|(start, end)| end - start)
+    else {
+        bail!("no linear memory slots were reserved");
+    };
+    let len = end - start;
+    if unsafe { libc::madvise(start as *mut _, len, libc::MADV_HUGEPAGE) } == -1 {
+        return Err(io::Error::last_os_error()).context("failed to advise transparent huge pages");
+    }
+    Ok(len)
+}
+
+/// Creator of linear memories backed by huge pages, used with the on-demand allocator
+pub struct Creator {
+    mode: HugePages,
+    huge_page_size: usize,
+    /// Whether a fallback to regular pages was already reported
+    fallback: AtomicBool,
+}
+
+impl Creator {
+    pub fn new(mode: HugePages) -> Self {
+        Self {
+            mode,
+            huge_page_size: size(),
+            fallback: AtomicBool::new(false),
+        }
+    }
+}
+
+unsafe impl MemoryCreator for Creator {
+    fn new_memory(
+        &self,
+        _ty: MemoryType,
+        minimum: usize,
+        maximum: Option<usize>,
+        reserved_size_in_bytes: Option<usize>,
+        guard_size_in_bytes: usize,
+    ) -> Result<Box<dyn LinearMemory>, String> {
+        Memory::new(
+            self,
+            minimum,
+            maximum,
+            reserved_size_in_bytes,
+            guard_size_in_bytes,
+        )
+        .map(|memory| Box::new(memory) as _)
+        .map_err(|err| format!("{err:#}"))
+    }
+}
+
+/// Linear memory within a reservation aligned to the huge page size
+struct Memory {
+    /// Start and length of the whole reservation
+    mapping: (usize, usize),
+    base: usize,
+    size: usize,
+    /// Size memory can grow to without moving, excluding the guard
+    accessible: usize,
+    maximum: Option<usize>,
+    guard: usize,
+    /// Whether the memory moves to a larger reservation when growing beyond `accessible`,
+    /// which only memories created without a reservation may
+    movable: bool,
+    mode: HugePages,
+    align: usize,
+    /// Huge page size of hugetlbfs pages to commit memory with, if any
+    huge_page_size: Option<usize>,
+}
+
+impl Memory {
+    fn new(
+        creator: &Creator,
+        minimum: usize,
+        maximum: Option<usize>,
+        reserved: Option<usize>,
+        guard: usize,
+    ) -> anyhow::Result<Self> {
+        let mut memory = Self::reserve(
+            creator.mode,
+            creator.huge_page_size,
+            reserved.or(maximum).unwrap_or(minimum).max(minimum),
+            maximum,
+            guard,
+        )?;
+        memory.movable = reserved.is_none();
+        if let Err(err) = memory.commit(0, minimum) {
+            if memory.huge_page_size.take().is_none() {
+                return Err(err);
+            }
+            if !creator.fallback.swap(true, Ordering::Relaxed) {
+                eprintln!("huge pages exhausted, fallback to regular pages: {err:#}");
+            }
+            memory.commit(0, minimum)?;
+        }
+        memory.size = minimum;
+        Ok(memory)
+    }
+
+    /// Reserves `accessible` bytes followed by `guard` bytes, aligned to `align`,
+    /// without committing any of them
+    fn reserve(
+        mode: HugePages,
+        align: usize,
+        accessible: usize,
+        maximum: Option<usize>,
+        guard: usize,
+    ) -> anyhow::Result<Self> {
+        let len = accessible
+            .checked_add(guard)
+            .and_then(|len| len.checked_add(align))
+            .context("memory reservation is too large")?;
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error()).context("failed to reserve memory");
+        }
+        let start = ptr as usize;
+        let mut memory = Self {
+            mapping: (start, len),
+            base: start.next_multiple_of(align),
+            size: 0,
+            accessible,
+            maximum,
+            guard,
+            movable: false,
+            mode,
+            align,
+            huge_page_size: None,
+        };
+        match mode {
+            HugePages::Off => {}
+            HugePages::Transparent => {
+                if unsafe { libc::madvise(memory.base as *mut _, accessible, libc::MADV_HUGEPAGE) }
+                    == -1
+                {
+                    return Err(io::Error::last_os_error())
+                        .context("failed to advise transparent huge pages");
+                }
+            }
+            HugePages::Explicit => memory.huge_page_size = Some(align),
+        }
+        Ok(memory)
+    }
+
+    /// Moves the memory to a new reservation with room for at least `new_size` bytes,
+    /// twice the current one if the maximum permits, and grows it to `new_size`
+    fn grow_moving(&mut self, new_size: usize) -> anyhow::Result<()> {
+        let accessible = self
+            .accessible
+            .saturating_mul(2)
+            .min(self.maximum.unwrap_or(usize::MAX))
+            .max(new_size);
+        let mut memory =
+            Self::reserve(self.mode, self.align, accessible, self.maximum, self.guard)?;
+        memory.movable = true;
+        // keep regular pages once huge pages were exhausted
+        if self.huge_page_size.is_none() {
+            memory.huge_page_size = None;
+        }
+        if let Err(err) = memory.commit(0, new_size) {
+            if memory.huge_page_size.take().is_none() {
+                return Err(err);
+            }
+            memory.commit(0, new_size)?;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(self.base as *const u8, memory.base as *mut u8, self.size);
+        }
+        memory.size = new_size;
+        *self = memory;
+        Ok(())
+    }
+
+    /// Makes `[from, to)` of the memory accessible, with huge pages for whole huge pages
+    /// within the range if enabled
+    fn commit(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        let Some(huge) = self.huge_page_size else {
+            return self.protect(from..to);
+        };
+        let (start, end) = (from.next_multiple_of(huge), to / huge * huge);
+        if start >= end {
+            return self.protect(from..to);
+        }
+        self.protect(from..start)?;
+        self.map_huge(start..end)?;
+        self.protect(end..to)
+    }
+
+    /// Makes reserved pages in `range` of the memory readable and writable, keeping
+    /// the transparent huge pages advice of the reservation
+    fn protect(&self, range: Range<usize>) -> anyhow::Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let ptr = (self.base + range.start) as *mut _;
+        if unsafe { libc::mprotect(ptr, range.len(), libc::PROT_READ | libc::PROT_WRITE) } == -1 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to commit {} bytes", range.len()));
+        }
+        Ok(())
+    }
+
+    /// Maps zeroed read-write hugetlbfs pages over `range` of the memory
+    fn map_huge(&self, range: Range<usize>) -> anyhow::Result<()> {
+        let ptr = (self.base + range.start) as *mut _;
+        let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED;
+        let mapped = unsafe {
+            libc::mmap(
+                ptr,
+                range.len(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags | libc::MAP_HUGETLB,
+                -1,
+                0,
+            )
+        };
+        if mapped == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            // a failed fixed mapping may leave a hole, which is reserved again
+            unsafe {
+                libc::mmap(
+                    ptr,
+                    range.len(),
+                    libc::PROT_NONE,
+                    flags | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                );
+            }
+            return Err(err)
+                .with_context(|| format!("failed to map {} bytes of huge pages", range.len()));
+        }
+        Ok(())
+    }
+}
+
+unsafe impl LinearMemory for Memory {
+    fn byte_size(&self) -> usize {
+        self.size
+    }
+
+    fn maximum_byte_size(&self) -> Option<usize> {
+        self.maximum
+    }
+
+    fn grow_to(&mut self, new_size: usize) -> anyhow::Result<()> {
+        if new_size > self.accessible {
+            if !self.movable {
+                bail!(
+                    "memory cannot grow beyond its reservation of {} bytes",
+                    self.accessible
+                );
+            }
+            return self.grow_moving(new_size);
+        }
+        if let Err(err) = self.commit(self.size, new_size) {
+            if self.huge_page_size.take().is_none() {
+                return Err(err);
+            }
+            self.commit(self.size, new_size)?;
+        }
+        self.size = new_size;
+        Ok(())
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.base as *mut u8
+    }
+
+    fn wasm_accessible(&self) -> Range<usize> {
+        self.base..self.mapping.0 + self.mapping.1
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        let (start, len) = self.mapping;
+        unsafe {
+            libc::munmap(start as *mut _, len);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use core::fmt::{self, Display, Write as _};
+use core::str::FromStr;
+
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context as _};
+use serde::Deserialize;
+use tokio::fs;
+use wasmtime::component::Component;
+use wasmtime::Engine;
+
+/// Pattern of import names, like `wasi:http/outgoing-handler`, `wasi:sockets/*` or `*`.
+///
+/// Versions of imports are ignored, a trailing `*` matches any suffix
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ImportPattern(String);
+
+impl FromStr for ImportPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ensure!(!s.is_empty(), "empty import pattern");
+        ensure!(
+            !s.trim_end_matches('*').contains(['*', '@']),
+            "invalid import pattern `{s}`, expected `NAME`, `PREFIX*` or `*` without a version"
+        );
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for ImportPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for ImportPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ImportPattern {
+    fn matches(&self, name: &str) -> bool {
+        let name = name.split_once('@').map_or(name, |(name, _)| name);
+        match self.0.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == self.0,
+        }
+    }
+}
+
+/// Policy of component imports, checked before any sandbox is started
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportPolicy {
+    /// If not empty, only imports matching a pattern are permitted
+    #[serde(default)]
+    allow: Vec<ImportPattern>,
+    /// Imports matching a pattern are refused, even if allowed
+    #[serde(default)]
+    deny: Vec<ImportPattern>,
+}
+
+impl ImportPolicy {
+    /// Loads `allow` and `deny` pattern lists from a TOML file at `path`
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let buf = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        toml::from_str(&buf).with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    /// Returns the reason import `name` is refused, if it is
+    fn refuse(&self, name: &str) -> Option<String> {
+        if let Some(pattern) = self.deny.iter().find(|pattern| pattern.matches(name)) {
+            Some(format!("denied by `{pattern}`"))
+        } else if !self.allow.is_empty() && !self.allow.iter().any(|p| p.matches(name)) {
+            Some("not allowed".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Checks that all imports of `component` are permitted, describing all violations on failure
+    pub fn check(&self, engine: &Engine, component: &Component) -> anyhow::Result<()> {
+        let mut msg = String::from("component imports exceed the import policy:");
+        let mut refused = false;
+        for (name, _) in component.component_type().imports(engine) {
+            if let Some(reason) = self.refuse(name) {
+                _ = write!(msg, "\n  - {name}: {reason}");
+                refused = true;
+            }
+        }
+        if refused {
+            bail!(msg)
+        }
+        Ok(())
+    }
+}
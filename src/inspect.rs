@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::Context as _;
+use wasmparser::{Parser, Payload};
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::Component;
+
+use crate::provenance::Provenance;
+use crate::{new_linker, validate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Path to a Wasm component to inspect
+    wasm: PathBuf,
+}
+
+fn describe(item: &ComponentItem) -> &'static str {
+    match item {
+        ComponentItem::ComponentFunc(..) => "func",
+        ComponentItem::CoreFunc(..) => "core func",
+        ComponentItem::Module(..) => "module",
+        ComponentItem::Component(..) => "component",
+        ComponentItem::ComponentInstance(..) => "instance",
+        ComponentItem::Type(..) => "type",
+        ComponentItem::Resource(..) => "resource",
+    }
+}
+
+/// Prints imports, exports, embedded core modules and resource requirements of a component
+/// and whether it can be run
+pub fn run(Args { wasm }: Args) -> anyhow::Result<ExitCode> {
+    let buf =
+        std::fs::read(&wasm).with_context(|| format!("failed to read `{}`", wasm.display()))?;
+    let buf = wat::parse_bytes(&buf).context("failed to parse component text")?;
+    let mut modules = 0;
+    for payload in Parser::new(0).parse_all(&buf) {
+        let payload = payload.context("failed to parse component")?;
+        if let Payload::ModuleSection { .. } = payload {
+            modules += 1;
+        }
+    }
+
+    let mut config = wasmtime::Config::default();
+    config.wasm_component_model(true);
+    config.async_support(true);
+    let engine = wasmtime::Engine::new(&config).context("failed to construct engine")?;
+    let component = Component::new(&engine, &buf).context("failed to compile component")?;
+    let ty = component.component_type();
+
+    println!("component: {}", wasm.display());
+    println!("core modules: {modules}");
+    Provenance::read(&buf).print();
+    if let Some(resources) = component.resources_required() {
+        print!("memories: {}", resources.num_memories);
+        if let Some(pages) = resources.max_initial_memory_size {
+            print!(", max initial size: {pages} pages ({} KiB)", pages * 64);
+        }
+        println!();
+        print!("tables: {}", resources.num_tables);
+        if let Some(elements) = resources.max_initial_table_size {
+            print!(", max initial size: {elements} elements");
+        }
+        println!();
+    } else {
+        println!("memories: imported");
+        println!("tables: imported");
+    }
+    println!("imports:");
+    for (name, item) in ty.imports(&engine) {
+        println!("  {} {name}", describe(&item));
+    }
+    println!("exports:");
+    for (name, item) in ty.exports(&engine) {
+        println!("  {} {name}", describe(&item));
+    }
+
+    let linker = new_linker(&engine)?;
+    match validate::validate(&linker, &component, true) {
+        Ok(()) => println!("run: compatible"),
+        Err(err) => println!("run: incompatible: {err:#}"),
+    }
+    if ty
+        .exports(&engine)
+        .any(|(name, _)| name.starts_with("wasi:http/incoming-handler@0.2."))
+    {
+        println!("serve: exports `wasi:http/incoming-handler`");
+    } else {
+        println!("serve: incompatible: component does not export `wasi:http/incoming-handler`");
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,114 @@
+use tokio::sync::watch;
+use wasmtime::component::{Linker, Resource, ResourceTable};
+use wasmtime_wasi::{Pollable, Subscribe};
+
+use bindings::cgwasm::instance::{lifecycle, metadata};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "cgwasm:instance/imports",
+        trappable_imports: true,
+        with: {
+            "wasi:io": wasmtime_wasi::bindings::io,
+        },
+    });
+}
+
+/// Per-instance `cgwasm:instance` state
+#[derive(Clone, Debug)]
+pub struct InstanceCtx {
+    index: usize,
+    count: usize,
+    cgroup: Option<String>,
+    hostname: String,
+    /// Number of CPUs available to the instance
+    cpus: usize,
+    /// Cancellation handle of the instance, set once shutdown is requested
+    shutdown: watch::Receiver<bool>,
+}
+
+impl InstanceCtx {
+    pub fn new(
+        index: usize,
+        count: usize,
+        cgroup: Option<String>,
+        hostname: String,
+        cpus: usize,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            index,
+            count,
+            cgroup,
+            hostname,
+            cpus,
+            shutdown,
+        }
+    }
+}
+
+/// Shutdown request of an instance, ready once the host requested the instance to shut down
+struct Shutdown(watch::Receiver<bool>);
+
+#[async_trait::async_trait]
+impl Subscribe for Shutdown {
+    async fn ready(&mut self) {
+        // the sender is only dropped once all instances are done
+        _ = self.0.wait_for(|v| *v).await;
+    }
+}
+
+/// A view into the `cgwasm:instance` state of an instance
+pub struct Instance<'a> {
+    ctx: &'a InstanceCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> Instance<'a> {
+    pub fn new(ctx: &'a InstanceCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+}
+
+impl metadata::Host for Instance<'_> {
+    fn index(&mut self) -> wasmtime::Result<u32> {
+        Ok(self.ctx.index.try_into()?)
+    }
+
+    fn count(&mut self) -> wasmtime::Result<u32> {
+        Ok(self.ctx.count.try_into()?)
+    }
+
+    fn cgroup(&mut self) -> wasmtime::Result<Option<String>> {
+        Ok(self.ctx.cgroup.clone())
+    }
+
+    fn hostname(&mut self) -> wasmtime::Result<String> {
+        Ok(self.ctx.hostname.clone())
+    }
+
+    fn cpus(&mut self) -> wasmtime::Result<u32> {
+        Ok(self.ctx.cpus.try_into().unwrap_or(u32::MAX))
+    }
+}
+
+impl lifecycle::Host for Instance<'_> {
+    fn shutdown_requested(&mut self) -> wasmtime::Result<bool> {
+        Ok(*self.ctx.shutdown.borrow())
+    }
+
+    fn subscribe_shutdown(&mut self) -> wasmtime::Result<Resource<Pollable>> {
+        let shutdown = self.table.push(Shutdown(self.ctx.shutdown.clone()))?;
+        wasmtime_wasi::subscribe(self.table, shutdown)
+    }
+}
+
+/// Adds `cgwasm:instance` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> Instance<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    metadata::add_to_linker_get_host(linker, f)?;
+    lifecycle::add_to_linker_get_host(linker, f)
+}
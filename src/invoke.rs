@@ -0,0 +1,618 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context as _};
+use serde::Deserialize;
+use serde_json::{json, Map, Value as Json};
+use tokio::io::{AsyncBufReadExt as _, BufReader};
+use tokio::sync::{mpsc, Mutex};
+use wasm_wave::untyped::UntypedFuncCall;
+use wasm_wave::value::{Type as WaveType, Value};
+use wasm_wave::wasm::{WasmTypeKind, WasmValue as _};
+use wasmtime::component::{Func, Instance, Type, Val};
+use wasmtime::Store;
+
+/// Exported function to call, of the form `[INTERFACE#]FUNCTION[(ARGS...)]`,
+/// like `wasi:cli/run#run()` or `add(1, 2)`, with WAVE-encoded arguments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invoke {
+    pub interface: Option<String>,
+    pub function: String,
+    /// Parenthesized WAVE arguments, `()` if omitted
+    pub args: String,
+}
+
+impl FromStr for Invoke {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, args) = s.find('(').map_or((s, "()"), |i| s.split_at(i));
+        let (interface, function) = match name.rsplit_once('#') {
+            Some((interface, function)) => (Some(interface), function),
+            None => (None, name),
+        };
+        if function.is_empty() || interface.is_some_and(str::is_empty) {
+            bail!("invalid call `{s}`, expected `[INTERFACE#]FUNCTION[(ARGS...)]`");
+        }
+        parse_args(args).with_context(|| format!("invalid arguments of `{s}`"))?;
+        Ok(Self {
+            interface: interface.map(Into::into),
+            function: function.into(),
+            args: args.into(),
+        })
+    }
+}
+
+impl Display for Invoke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(interface) = &self.interface {
+            write!(f, "{interface}#")?;
+        }
+        write!(f, "{}{}", self.function, self.args)
+    }
+}
+
+/// Parses parenthesized WAVE arguments without their types
+fn parse_args(args: &str) -> anyhow::Result<UntypedFuncCall<'static>> {
+    // the WAVE function call syntax only allows plain labels as names
+    let call = format!("f{args}");
+    Ok(UntypedFuncCall::parse(&call)?.into_owned())
+}
+
+/// Arguments of a single call, results are encoded the same way
+enum Args<'a> {
+    /// Parenthesized WAVE arguments
+    Wave(&'a str),
+    /// JSON array of arguments
+    Json(Vec<Json>),
+}
+
+impl<'a> Args<'a> {
+    /// Parses a line of arguments, which is a JSON array if it starts with `[`
+    fn parse(line: &'a str) -> anyhow::Result<Self> {
+        if line.starts_with('[') {
+            let args = serde_json::from_str(line).context("invalid JSON arguments")?;
+            Ok(Self::Json(args))
+        } else {
+            parse_args(line).context("invalid WAVE arguments")?;
+            Ok(Self::Wave(line))
+        }
+    }
+
+    /// Decodes the arguments as parameters of `func`
+    fn params<T>(&self, store: &Store<T>, func: &Func) -> anyhow::Result<Vec<Val>> {
+        let types = func.params(store);
+        match self {
+            Self::Wave(args) => {
+                let types = types
+                    .iter()
+                    .map(wave_type)
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                parse_args(args)?
+                    .to_wasm_params::<Value>(&types)
+                    .context("failed to decode arguments")?
+                    .iter()
+                    .map(val)
+                    .collect()
+            }
+            Self::Json(args) => {
+                ensure!(
+                    args.len() == types.len(),
+                    "expected {} arguments, got {}",
+                    types.len(),
+                    args.len()
+                );
+                args.iter()
+                    .zip(types.iter())
+                    .enumerate()
+                    .map(|(i, (v, ty))| {
+                        from_json(v, ty).with_context(|| format!("failed to decode argument {i}"))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Encodes `results` of `func` the same way as the arguments
+    fn results<T>(&self, store: &Store<T>, func: &Func, results: &[Val]) -> anyhow::Result<String> {
+        match self {
+            Self::Wave(..) => {
+                let results = results
+                    .iter()
+                    .zip(func.results(store).iter())
+                    .map(|(v, ty)| Ok(wasm_wave::to_string(&wave(v, ty)?)?))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(results.join(", "))
+            }
+            Self::Json(..) => {
+                let results = results.iter().map(to_json).collect::<anyhow::Result<_>>()?;
+                Ok(Json::Array(results).to_string())
+            }
+        }
+    }
+}
+
+/// Calls read from host stdin or a file, one line of arguments per call, shared by all instances
+#[derive(Debug)]
+pub struct Requests {
+    rx: Mutex<mpsc::Receiver<(usize, String)>>,
+    /// Results of calls by line number, if collected
+    results: Option<std::sync::Mutex<BTreeMap<usize, String>>>,
+}
+
+impl Requests {
+    /// Queues `lines` and collects results of the calls instead of writing them to stdout,
+    /// see [`Requests::results`]
+    pub fn lines(lines: Vec<String>) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(lines.len().max(1));
+        for (n, line) in lines.into_iter().enumerate() {
+            if !line.trim().is_empty() {
+                _ = tx.try_send((n + 1, line));
+            }
+        }
+        Arc::new(Self {
+            rx: Mutex::new(rx),
+            results: Some(std::sync::Mutex::default()),
+        })
+    }
+
+    /// Starts reading host stdin on the current runtime, buffering up to `count` lines
+    pub fn stdin(count: usize) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(count.max(1));
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+            let mut n = 0;
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => {
+                        n += 1;
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        if tx.send((n, line)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(err) => {
+                        eprintln!("failed to read stdin: {err}");
+                        return;
+                    }
+                }
+            }
+        });
+        Arc::new(Self {
+            rx: Mutex::new(rx),
+            results: None,
+        })
+    }
+
+    /// Returns the next line along with its 1-based number
+    async fn next(&self) -> Option<(usize, String)> {
+        self.rx.lock().await.recv().await
+    }
+
+    /// Writes the result of the call of line `n` by instance at `index` to stdout or collects it
+    fn respond(&self, index: usize, n: usize, result: String) {
+        if let Some(results) = &self.results {
+            let mut results = results.lock().unwrap_or_else(|err| err.into_inner());
+            results.insert(n, result);
+        } else {
+            println!("[{index}] {n}: {result}");
+        }
+    }
+
+    /// Returns results collected so far by line number, empty unless created by [`Requests::lines`]
+    pub fn results(&self) -> BTreeMap<usize, String> {
+        self.results
+            .as_ref()
+            .map_or_else(BTreeMap::default, |results| {
+                results
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .clone()
+            })
+    }
+}
+
+impl Invoke {
+    fn func<T>(&self, store: &mut Store<T>, instance: &Instance) -> anyhow::Result<Func> {
+        let func = if let Some(interface) = &self.interface {
+            let Some(export) = instance.get_export(&mut *store, None, interface) else {
+                bail!("component does not export `{interface}`");
+            };
+            instance
+                .get_export(&mut *store, Some(&export), &self.function)
+                .and_then(|export| instance.get_func(&mut *store, export))
+        } else {
+            instance.get_func(&mut *store, &self.function)
+        };
+        func.with_context(|| format!("component does not export function `{self}`"))
+    }
+
+    /// Calls the function on `instance` with `params`.
+    ///
+    /// A call fails if the function returns a single `result`, which is an `err`
+    async fn call<T: Send>(
+        store: &mut Store<T>,
+        func: &Func,
+        params: &[Val],
+    ) -> anyhow::Result<(Vec<Val>, bool)> {
+        let mut results = vec![Val::Bool(false); func.results(&*store).len()];
+        func.call_async(&mut *store, params, &mut results).await?;
+        func.post_return_async(&mut *store).await?;
+        let ok = !matches!(results.as_slice(), [Val::Result(Err(..))]);
+        Ok((results, ok))
+    }
+
+    /// Calls the function on `instance` of instance at `index`, writing results to stdout.
+    ///
+    /// The function is called once with [`Invoke::args`] or, if `requests` is set,
+    /// once per request until there are none left. Requests that cannot be decoded
+    /// fail without affecting the instance, while a trap stops it
+    pub async fn run<T: Send>(
+        &self,
+        index: usize,
+        store: &mut Store<T>,
+        instance: &Instance,
+        requests: Option<&Requests>,
+    ) -> anyhow::Result<Result<(), ()>> {
+        let func = self.func(store, instance)?;
+        let Some(requests) = requests else {
+            let args = Args::Wave(&self.args);
+            let params = args.params(store, &func)?;
+            let (results, ok) = Self::call(store, &func, &params).await?;
+            println!("[{index}] {}", args.results(store, &func, &results)?);
+            return Ok(if ok { Ok(()) } else { Err(()) });
+        };
+        let mut failed = false;
+        while let Some((n, line)) = requests.next().await {
+            let line = line.trim();
+            let decoded = Args::parse(line).and_then(|args| {
+                let params = args.params(store, &func)?;
+                Ok((args, params))
+            });
+            let (args, params) = match decoded {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    requests.respond(index, n, format!("error: {err:#}"));
+                    failed = true;
+                    continue;
+                }
+            };
+            match Self::call(store, &func, &params).await {
+                Ok((results, ok)) => {
+                    requests.respond(index, n, args.results(store, &func, &results)?);
+                    failed |= !ok;
+                }
+                Err(err) => {
+                    // the backtrace is logged once the instance stops
+                    requests.respond(index, n, format!("error: {}", err.root_cause()));
+                    return Err(err.context(format!("failed to call function for line {n}")));
+                }
+            }
+        }
+        Ok(if failed { Err(()) } else { Ok(()) })
+    }
+}
+
+/// Returns the WAVE type of a component value type
+fn wave_type(ty: &Type) -> anyhow::Result<WaveType> {
+    let ty = match ty {
+        Type::Bool => WaveType::BOOL,
+        Type::S8 => WaveType::S8,
+        Type::U8 => WaveType::U8,
+        Type::S16 => WaveType::S16,
+        Type::U16 => WaveType::U16,
+        Type::S32 => WaveType::S32,
+        Type::U32 => WaveType::U32,
+        Type::S64 => WaveType::S64,
+        Type::U64 => WaveType::U64,
+        Type::Float32 => WaveType::FLOAT32,
+        Type::Float64 => WaveType::FLOAT64,
+        Type::Char => WaveType::CHAR,
+        Type::String => WaveType::STRING,
+        Type::List(ty) => WaveType::list(wave_type(&ty.ty())?),
+        Type::Record(ty) => {
+            let fields = ty
+                .fields()
+                .map(|field| Ok((field.name, wave_type(&field.ty)?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            WaveType::record(fields).context("empty record")?
+        }
+        Type::Tuple(ty) => {
+            let types = ty
+                .types()
+                .map(|ty| wave_type(&ty))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            WaveType::tuple(types).context("empty tuple")?
+        }
+        Type::Variant(ty) => {
+            let cases = ty
+                .cases()
+                .map(|case| Ok((case.name, case.ty.as_ref().map(wave_type).transpose()?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            WaveType::variant(cases).context("empty variant")?
+        }
+        Type::Enum(ty) => WaveType::enum_ty(ty.names()).context("empty enum")?,
+        Type::Option(ty) => WaveType::option(wave_type(&ty.ty())?),
+        Type::Result(ty) => WaveType::result(
+            ty.ok().as_ref().map(wave_type).transpose()?,
+            ty.err().as_ref().map(wave_type).transpose()?,
+        ),
+        Type::Flags(ty) => WaveType::flags(ty.names()).context("empty flags")?,
+        Type::Own(..) | Type::Borrow(..) => bail!("resources cannot be encoded as WAVE"),
+    };
+    Ok(ty)
+}
+
+/// Converts a WAVE value to a component value
+fn val(v: &Value) -> anyhow::Result<Val> {
+    let boxed = |v: Option<Cow<'_, Value>>| v.map(|v| val(&v).map(Box::new)).transpose();
+    let v = match v.kind() {
+        WasmTypeKind::Bool => Val::Bool(v.unwrap_bool()),
+        WasmTypeKind::S8 => Val::S8(v.unwrap_s8()),
+        WasmTypeKind::U8 => Val::U8(v.unwrap_u8()),
+        WasmTypeKind::S16 => Val::S16(v.unwrap_s16()),
+        WasmTypeKind::U16 => Val::U16(v.unwrap_u16()),
+        WasmTypeKind::S32 => Val::S32(v.unwrap_s32()),
+        WasmTypeKind::U32 => Val::U32(v.unwrap_u32()),
+        WasmTypeKind::S64 => Val::S64(v.unwrap_s64()),
+        WasmTypeKind::U64 => Val::U64(v.unwrap_u64()),
+        WasmTypeKind::Float32 => Val::Float32(v.unwrap_float32()),
+        WasmTypeKind::Float64 => Val::Float64(v.unwrap_float64()),
+        WasmTypeKind::Char => Val::Char(v.unwrap_char()),
+        WasmTypeKind::String => Val::String(v.unwrap_string().into()),
+        WasmTypeKind::List => {
+            Val::List(v.unwrap_list().map(|v| val(&v)).collect::<Result<_, _>>()?)
+        }
+        WasmTypeKind::Record => Val::Record(
+            v.unwrap_record()
+                .map(|(name, v)| Ok((name.into(), val(&v)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        WasmTypeKind::Tuple => Val::Tuple(
+            v.unwrap_tuple()
+                .map(|v| val(&v))
+                .collect::<Result<_, _>>()?,
+        ),
+        WasmTypeKind::Variant => {
+            let (name, v) = v.unwrap_variant();
+            Val::Variant(name.into(), boxed(v)?)
+        }
+        WasmTypeKind::Enum => Val::Enum(v.unwrap_enum().into()),
+        WasmTypeKind::Option => Val::Option(boxed(v.unwrap_option())?),
+        WasmTypeKind::Result => Val::Result(match v.unwrap_result() {
+            Ok(v) => Ok(boxed(v)?),
+            Err(v) => Err(boxed(v)?),
+        }),
+        WasmTypeKind::Flags => Val::Flags(v.unwrap_flags().map(Into::into).collect()),
+        kind => bail!("unsupported WAVE value of kind `{kind}`"),
+    };
+    Ok(v)
+}
+
+/// Converts a component value of type `ty` to a WAVE value
+fn wave(v: &Val, ty: &Type) -> anyhow::Result<Value> {
+    let wave_ty = wave_type(ty)?;
+    let boxed = |v: &Option<Box<Val>>, ty: Option<Type>| -> anyhow::Result<Option<Value>> {
+        match (v, ty) {
+            (Some(v), Some(ty)) => Ok(Some(wave(v, &ty)?)),
+            (None, _) => Ok(None),
+            (Some(..), None) => bail!("unexpected payload"),
+        }
+    };
+    let v = match (v, ty) {
+        (Val::Bool(v), _) => Value::make_bool(*v),
+        (Val::S8(v), _) => Value::make_s8(*v),
+        (Val::U8(v), _) => Value::make_u8(*v),
+        (Val::S16(v), _) => Value::make_s16(*v),
+        (Val::U16(v), _) => Value::make_u16(*v),
+        (Val::S32(v), _) => Value::make_s32(*v),
+        (Val::U32(v), _) => Value::make_u32(*v),
+        (Val::S64(v), _) => Value::make_s64(*v),
+        (Val::U64(v), _) => Value::make_u64(*v),
+        (Val::Float32(v), _) => Value::make_float32(*v),
+        (Val::Float64(v), _) => Value::make_float64(*v),
+        (Val::Char(v), _) => Value::make_char(*v),
+        (Val::String(v), _) => Value::make_string(v.into()),
+        (Val::List(vs), Type::List(ty)) => {
+            let vs = vs
+                .iter()
+                .map(|v| wave(v, &ty.ty()))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Value::make_list(&wave_ty, vs)?
+        }
+        (Val::Record(fields), Type::Record(ty)) => {
+            let fields = fields
+                .iter()
+                .zip(ty.fields())
+                .map(|((name, v), field)| Ok((name.as_str(), wave(v, &field.ty)?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Value::make_record(&wave_ty, fields)?
+        }
+        (Val::Tuple(vs), Type::Tuple(ty)) => {
+            let vs = vs
+                .iter()
+                .zip(ty.types())
+                .map(|(v, ty)| wave(v, &ty))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Value::make_tuple(&wave_ty, vs)?
+        }
+        (Val::Variant(name, v), Type::Variant(ty)) => {
+            let case = ty
+                .cases()
+                .find(|case| case.name == name)
+                .with_context(|| format!("unknown variant case `{name}`"))?;
+            Value::make_variant(&wave_ty, name, boxed(v, case.ty)?)?
+        }
+        (Val::Enum(name), _) => Value::make_enum(&wave_ty, name)?,
+        (Val::Option(v), Type::Option(ty)) => {
+            Value::make_option(&wave_ty, boxed(v, Some(ty.ty()))?)?
+        }
+        (Val::Result(v), Type::Result(ty)) => {
+            let v = match v {
+                Ok(v) => Ok(boxed(v, ty.ok())?),
+                Err(v) => Err(boxed(v, ty.err())?),
+            };
+            Value::make_result(&wave_ty, v)?
+        }
+        (Val::Flags(names), _) => Value::make_flags(&wave_ty, names.iter().map(String::as_str))?,
+        (Val::Resource(..), _) => bail!("resources cannot be encoded as WAVE"),
+        _ => bail!("value does not match its type"),
+    };
+    Ok(v)
+}
+
+/// Decodes a JSON value as a component value of type `ty`.
+///
+/// Variants and results are objects with a single case key, like `{"ok": 1}`,
+/// or just the case name if there is no payload, options are `null` or the value
+fn from_json(v: &Json, ty: &Type) -> anyhow::Result<Val> {
+    fn de<'de, T: Deserialize<'de>>(v: &'de Json) -> anyhow::Result<T> {
+        Ok(T::deserialize(v)?)
+    }
+    fn case(v: &Json) -> anyhow::Result<(&str, Option<&Json>)> {
+        match v {
+            Json::String(name) => Ok((name, None)),
+            Json::Object(obj) if obj.len() == 1 => {
+                let (name, v) = obj.iter().next().unwrap();
+                Ok((name, Some(v)))
+            }
+            _ => bail!("expected a case name or an object with a single case key"),
+        }
+    }
+    fn payload(v: Option<&Json>, ty: Option<Type>) -> anyhow::Result<Option<Box<Val>>> {
+        match (v, ty) {
+            (Some(v), Some(ty)) => Ok(Some(Box::new(from_json(v, &ty)?))),
+            (None | Some(Json::Null), None) => Ok(None),
+            (None, Some(..)) => bail!("missing payload"),
+            (Some(..), None) => bail!("unexpected payload"),
+        }
+    }
+    fn array(v: &Json) -> anyhow::Result<&Vec<Json>> {
+        v.as_array().context("expected an array")
+    }
+    let v = match ty {
+        Type::Bool => Val::Bool(de(v)?),
+        Type::S8 => Val::S8(de(v)?),
+        Type::U8 => Val::U8(de(v)?),
+        Type::S16 => Val::S16(de(v)?),
+        Type::U16 => Val::U16(de(v)?),
+        Type::S32 => Val::S32(de(v)?),
+        Type::U32 => Val::U32(de(v)?),
+        Type::S64 => Val::S64(de(v)?),
+        Type::U64 => Val::U64(de(v)?),
+        Type::Float32 => Val::Float32(de(v)?),
+        Type::Float64 => Val::Float64(de(v)?),
+        Type::Char => Val::Char(de(v)?),
+        Type::String => Val::String(de(v)?),
+        Type::List(ty) => Val::List(
+            array(v)?
+                .iter()
+                .map(|v| from_json(v, &ty.ty()))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Type::Record(ty) => {
+            let obj = v.as_object().context("expected an object")?;
+            Val::Record(
+                ty.fields()
+                    .map(|field| {
+                        let v = obj.get(field.name).unwrap_or(&Json::Null);
+                        let v = from_json(v, &field.ty)
+                            .with_context(|| format!("invalid field `{}`", field.name))?;
+                        Ok((field.name.into(), v))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        }
+        Type::Tuple(ty) => {
+            let vs = array(v)?;
+            ensure!(vs.len() == ty.types().len(), "tuple length mismatch");
+            Val::Tuple(
+                vs.iter()
+                    .zip(ty.types())
+                    .map(|(v, ty)| from_json(v, &ty))
+                    .collect::<anyhow::Result<_>>()?,
+            )
+        }
+        Type::Variant(ty) => {
+            let (name, v) = case(v)?;
+            let case = ty
+                .cases()
+                .find(|case| case.name == name)
+                .with_context(|| format!("unknown variant case `{name}`"))?;
+            Val::Variant(name.into(), payload(v, case.ty)?)
+        }
+        Type::Enum(ty) => {
+            let name: String = de(v)?;
+            ensure!(ty.names().any(|n| n == name), "unknown enum case `{name}`");
+            Val::Enum(name)
+        }
+        Type::Option(ty) => match v {
+            Json::Null => Val::Option(None),
+            v => Val::Option(Some(Box::new(from_json(v, &ty.ty())?))),
+        },
+        Type::Result(ty) => match case(v)? {
+            ("ok", v) => Val::Result(Ok(payload(v, ty.ok())?)),
+            ("err", v) => Val::Result(Err(payload(v, ty.err())?)),
+            (name, _) => bail!("unknown result case `{name}`, expected `ok` or `err`"),
+        },
+        Type::Flags(ty) => {
+            let names: Vec<String> = de(v)?;
+            if let Some(name) = names.iter().find(|name| !ty.names().any(|n| n == *name)) {
+                bail!("unknown flag `{name}`");
+            }
+            Val::Flags(names)
+        }
+        Type::Own(..) | Type::Borrow(..) => bail!("resources cannot be encoded as JSON"),
+    };
+    Ok(v)
+}
+
+/// Encodes a component value as JSON, the inverse of [`from_json`]
+fn to_json(v: &Val) -> anyhow::Result<Json> {
+    let case = |name: &str, v: &Option<Box<Val>>| -> anyhow::Result<Json> {
+        let Some(v) = v else {
+            return Ok(Json::String(name.into()));
+        };
+        Ok(Json::Object(Map::from_iter([(name.into(), to_json(v)?)])))
+    };
+    let v = match v {
+        Val::Bool(v) => json!(v),
+        Val::S8(v) => json!(v),
+        Val::U8(v) => json!(v),
+        Val::S16(v) => json!(v),
+        Val::U16(v) => json!(v),
+        Val::S32(v) => json!(v),
+        Val::U32(v) => json!(v),
+        Val::S64(v) => json!(v),
+        Val::U64(v) => json!(v),
+        Val::Float32(v) => json!(v),
+        Val::Float64(v) => json!(v),
+        Val::Char(v) => json!(v),
+        Val::String(v) => json!(v),
+        Val::List(vs) | Val::Tuple(vs) => {
+            Json::Array(vs.iter().map(to_json).collect::<anyhow::Result<_>>()?)
+        }
+        Val::Record(fields) => Json::Object(
+            fields
+                .iter()
+                .map(|(name, v)| Ok((name.clone(), to_json(v)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Val::Variant(name, v) => case(name, v)?,
+        Val::Enum(name) => json!(name),
+        Val::Option(None) => Json::Null,
+        Val::Option(Some(v)) => to_json(v)?,
+        Val::Result(Ok(v)) => case("ok", v)?,
+        Val::Result(Err(v)) => case("err", v)?,
+        Val::Flags(names) => json!(names),
+        Val::Resource(..) => bail!("resources cannot be encoded as JSON"),
+    };
+    Ok(v)
+}
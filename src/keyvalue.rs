@@ -0,0 +1,393 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context as _};
+use redb::{ReadableTable as _, TableDefinition, TableError};
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+use bindings::wasi::keyvalue::{atomics, batch, store};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "wasi:keyvalue/imports",
+        trappable_imports: true,
+        with: {
+            "wasi:keyvalue/store/bucket": super::Bucket,
+        },
+    });
+}
+
+/// `wasi:keyvalue` backend selection
+#[derive(Clone, Debug, Default)]
+pub enum BackendConfig {
+    /// Volatile in-memory store, discarded at exit
+    #[default]
+    Memory,
+    /// Persistent store backed by a redb database at the path
+    Redb(PathBuf),
+}
+
+impl FromStr for BackendConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "memory" => Ok(Self::Memory),
+            Some(("redb", path)) if !path.is_empty() => Ok(Self::Redb(path.into())),
+            _ => bail!("invalid keyvalue backend `{s}`, expected `memory` or `redb:PATH`"),
+        }
+    }
+}
+
+impl Display for BackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Memory => write!(f, "memory"),
+            Self::Redb(path) => write!(f, "redb:{}", path.display()),
+        }
+    }
+}
+
+/// Scope of `wasi:keyvalue` buckets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Namespace {
+    /// Buckets are shared by all instances
+    #[default]
+    Shared,
+    /// Each instance gets its own set of buckets
+    Instance,
+}
+
+/// Storage of key-value pairs grouped by bucket
+pub trait Backend: Send + Sync {
+    fn get(&self, bucket: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    fn set(&self, bucket: &str, key: &str, value: Vec<u8>) -> anyhow::Result<()>;
+    fn delete(&self, bucket: &str, key: &str) -> anyhow::Result<()>;
+    fn list_keys(&self, bucket: &str) -> anyhow::Result<Vec<String>>;
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> anyhow::Result<u64>;
+
+    fn exists(&self, bucket: &str, key: &str) -> anyhow::Result<bool> {
+        self.get(bucket, key).map(|v| v.is_some())
+    }
+}
+
+impl dyn Backend {
+    pub fn new(config: &BackendConfig) -> anyhow::Result<Arc<dyn Backend>> {
+        match config {
+            BackendConfig::Memory => Ok(Arc::new(MemoryBackend::default())),
+            BackendConfig::Redb(path) => {
+                let db = redb::Database::create(path).with_context(|| {
+                    format!("failed to open redb database at `{}`", path.display())
+                })?;
+                Ok(Arc::new(RedbBackend(db)))
+            }
+        }
+    }
+}
+
+fn add(value: Option<&[u8]>, delta: u64) -> anyhow::Result<u64> {
+    let value = match value {
+        Some(value) => std::str::from_utf8(value)
+            .context("value is not valid UTF-8")?
+            .parse::<u64>()
+            .context("value is not a valid `u64`")?,
+        None => 0,
+    };
+    value.checked_add(delta).context("value overflow")
+}
+
+#[derive(Default)]
+struct MemoryBackend(Mutex<HashMap<String, BTreeMap<String, Vec<u8>>>>);
+
+impl Backend for MemoryBackend {
+    fn get(&self, bucket: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let buckets = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(buckets.get(bucket).and_then(|b| b.get(key)).cloned())
+    }
+
+    fn set(&self, bucket: &str, key: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        let mut buckets = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        buckets
+            .entry(bucket.to_string())
+            .or_default()
+            .insert(key.to_string(), value);
+        Ok(())
+    }
+
+    fn delete(&self, bucket: &str, key: &str) -> anyhow::Result<()> {
+        let mut buckets = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(b) = buckets.get_mut(bucket) {
+            b.remove(key);
+        }
+        Ok(())
+    }
+
+    fn list_keys(&self, bucket: &str) -> anyhow::Result<Vec<String>> {
+        let buckets = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(buckets
+            .get(bucket)
+            .map(|b| b.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> anyhow::Result<u64> {
+        let mut buckets = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        let b = buckets.entry(bucket.to_string()).or_default();
+        let v = add(b.get(key).map(Vec::as_slice), delta)?;
+        b.insert(key.to_string(), v.to_string().into_bytes());
+        Ok(v)
+    }
+}
+
+struct RedbBackend(redb::Database);
+
+/// All buckets are stored in a single table keyed by `(bucket, key)`
+const TABLE: TableDefinition<(&str, &str), &[u8]> = TableDefinition::new("wasi:keyvalue");
+
+impl Backend for RedbBackend {
+    fn get(&self, bucket: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let tx = self.0.begin_read()?;
+        let t = match tx.open_table(TABLE) {
+            Ok(t) => t,
+            Err(TableError::TableDoesNotExist(..)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(t.get((bucket, key))?.map(|v| v.value().to_vec()))
+    }
+
+    fn set(&self, bucket: &str, key: &str, value: Vec<u8>) -> anyhow::Result<()> {
+        let tx = self.0.begin_write()?;
+        tx.open_table(TABLE)?
+            .insert((bucket, key), value.as_slice())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete(&self, bucket: &str, key: &str) -> anyhow::Result<()> {
+        let tx = self.0.begin_write()?;
+        tx.open_table(TABLE)?.remove((bucket, key))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn list_keys(&self, bucket: &str) -> anyhow::Result<Vec<String>> {
+        let tx = self.0.begin_read()?;
+        let t = match tx.open_table(TABLE) {
+            Ok(t) => t,
+            Err(TableError::TableDoesNotExist(..)) => return Ok(Vec::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut keys = Vec::default();
+        for kv in t.range((bucket, "")..)? {
+            let (k, _) = kv?;
+            let (b, k) = k.value();
+            if b != bucket {
+                break;
+            }
+            keys.push(k.to_string());
+        }
+        Ok(keys)
+    }
+
+    fn increment(&self, bucket: &str, key: &str, delta: u64) -> anyhow::Result<u64> {
+        let tx = self.0.begin_write()?;
+        let v = {
+            let mut t = tx.open_table(TABLE)?;
+            let v = add(t.get((bucket, key))?.as_ref().map(|v| v.value()), delta)?;
+            t.insert((bucket, key), v.to_string().as_bytes())?;
+            v
+        };
+        tx.commit()?;
+        Ok(v)
+    }
+}
+
+/// An open `wasi:keyvalue` bucket
+pub struct Bucket {
+    name: String,
+}
+
+/// Per-instance `wasi:keyvalue` state
+#[derive(Clone)]
+pub struct KeyValueCtx {
+    backend: Arc<dyn Backend>,
+    prefix: String,
+}
+
+impl KeyValueCtx {
+    /// Constructs a new context for instance `index` within `namespace`
+    pub fn new(backend: Arc<dyn Backend>, namespace: Namespace, index: usize) -> Self {
+        let prefix = match namespace {
+            Namespace::Shared => String::default(),
+            Namespace::Instance => format!("{index}/"),
+        };
+        Self { backend, prefix }
+    }
+}
+
+/// A view into the `wasi:keyvalue` state of an instance
+pub struct KeyValue<'a> {
+    ctx: &'a KeyValueCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> KeyValue<'a> {
+    pub fn new(ctx: &'a KeyValueCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+
+    fn bucket(&self, bucket: &Resource<Bucket>) -> wasmtime::Result<&str> {
+        let Bucket { name } = self.table.get(bucket)?;
+        Ok(name)
+    }
+}
+
+fn other(err: anyhow::Error) -> store::Error {
+    store::Error::Other(format!("{err:#}"))
+}
+
+impl store::Host for KeyValue<'_> {
+    fn open(
+        &mut self,
+        identifier: String,
+    ) -> wasmtime::Result<Result<Resource<Bucket>, store::Error>> {
+        let name = format!("{}{identifier}", self.ctx.prefix);
+        let bucket = self.table.push(Bucket { name })?;
+        Ok(Ok(bucket))
+    }
+}
+
+impl store::HostBucket for KeyValue<'_> {
+    fn get(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<Option<Vec<u8>>, store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(self.ctx.backend.get(bucket, &key).map_err(other))
+    }
+
+    fn set(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        value: Vec<u8>,
+    ) -> wasmtime::Result<Result<(), store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(self.ctx.backend.set(bucket, &key, value).map_err(other))
+    }
+
+    fn delete(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<(), store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(self.ctx.backend.delete(bucket, &key).map_err(other))
+    }
+
+    fn exists(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+    ) -> wasmtime::Result<Result<bool, store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(self.ctx.backend.exists(bucket, &key).map_err(other))
+    }
+
+    fn list_keys(
+        &mut self,
+        bucket: Resource<Bucket>,
+        cursor: Option<u64>,
+    ) -> wasmtime::Result<Result<store::KeyResponse, store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        let keys = match self.ctx.backend.list_keys(bucket) {
+            Ok(keys) => keys,
+            Err(err) => return Ok(Err(other(err))),
+        };
+        let cursor = cursor.unwrap_or_default().try_into().unwrap_or(usize::MAX);
+        Ok(Ok(store::KeyResponse {
+            keys: keys.into_iter().skip(cursor).collect(),
+            cursor: None,
+        }))
+    }
+
+    fn drop(&mut self, bucket: Resource<Bucket>) -> wasmtime::Result<()> {
+        self.table.delete(bucket)?;
+        Ok(())
+    }
+}
+
+impl atomics::Host for KeyValue<'_> {
+    fn increment(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key: String,
+        delta: u64,
+    ) -> wasmtime::Result<Result<u64, store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(self
+            .ctx
+            .backend
+            .increment(bucket, &key, delta)
+            .map_err(other))
+    }
+}
+
+impl batch::Host for KeyValue<'_> {
+    fn get_many(
+        &mut self,
+        bucket: Resource<Bucket>,
+        keys: Vec<String>,
+    ) -> wasmtime::Result<Result<Vec<Option<(String, Vec<u8>)>>, store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(keys
+            .into_iter()
+            .map(|key| {
+                let value = self.ctx.backend.get(bucket, &key)?;
+                Ok(value.map(|value| (key, value)))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map_err(other))
+    }
+
+    fn set_many(
+        &mut self,
+        bucket: Resource<Bucket>,
+        key_values: Vec<(String, Vec<u8>)>,
+    ) -> wasmtime::Result<Result<(), store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(key_values
+            .into_iter()
+            .try_for_each(|(key, value)| self.ctx.backend.set(bucket, &key, value))
+            .map_err(other))
+    }
+
+    fn delete_many(
+        &mut self,
+        bucket: Resource<Bucket>,
+        keys: Vec<String>,
+    ) -> wasmtime::Result<Result<(), store::Error>> {
+        let bucket = self.bucket(&bucket)?;
+        Ok(keys
+            .into_iter()
+            .try_for_each(|key| self.ctx.backend.delete(bucket, &key))
+            .map_err(other))
+    }
+}
+
+/// Adds `wasi:keyvalue` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> KeyValue<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    store::add_to_linker_get_host(linker, f)?;
+    atomics::add_to_linker_get_host(linker, f)?;
+    batch::add_to_linker_get_host(linker, f)?;
+    Ok(())
+}
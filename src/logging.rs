@@ -0,0 +1,60 @@
+use tracing::{debug, error, info, trace, warn};
+use wasmtime::component::Linker;
+
+use bindings::wasi::logging::logging::{self, Level};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "wasi:logging/imports",
+        trappable_imports: true,
+    });
+}
+
+/// Per-instance `wasi:logging` state
+#[derive(Clone, Copy, Debug)]
+pub struct LoggingCtx {
+    index: usize,
+}
+
+impl LoggingCtx {
+    pub fn new(index: usize) -> Self {
+        Self { index }
+    }
+}
+
+/// A view into the `wasi:logging` state of an instance
+pub struct Logging<'a> {
+    ctx: &'a LoggingCtx,
+}
+
+impl<'a> Logging<'a> {
+    pub fn new(ctx: &'a LoggingCtx) -> Self {
+        Self { ctx }
+    }
+}
+
+impl logging::Host for Logging<'_> {
+    fn log(&mut self, level: Level, context: String, message: String) -> wasmtime::Result<()> {
+        let instance = self.ctx.index;
+        match level {
+            Level::Trace => trace!(target: "guest", instance, context, "{message}"),
+            Level::Debug => debug!(target: "guest", instance, context, "{message}"),
+            Level::Info => info!(target: "guest", instance, context, "{message}"),
+            Level::Warn => warn!(target: "guest", instance, context, "{message}"),
+            Level::Error => error!(target: "guest", instance, context, "{message}"),
+            Level::Critical => {
+                error!(target: "guest", instance, context, critical = true, "{message}")
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds `wasi:logging` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> Logging<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    logging::add_to_linker_get_host(linker, f)
+}
@@ -1,25 +1,137 @@
-use core::fmt::Debug;
+use core::any::Any;
+use core::fmt::{self, Debug, Display};
 use core::num::NonZeroUsize;
 
 use core::str::FromStr;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env::{self, VarError};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::{process, thread};
+use std::process::{self, ExitCode};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Context as _};
-use clap::Parser;
+use anyhow::{anyhow, bail, Context as _};
+use clap::{Parser, Subcommand};
+use futures::FutureExt as _;
 use nix::sched::{unshare, CloneFlags};
-use tokio::sync::{broadcast, oneshot};
-use tokio::{fs, join, try_join};
-use wasmtime::component::{Component, Linker};
-use wasmtime::{InstanceAllocationStrategy, PoolingAllocationConfig, Store};
+use nix::unistd::sethostname;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio::{fs, join, select, try_join};
+use tracing::{info_span, Instrument as _, Span};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Layer as _, SubscriberExt as _};
+use tracing_subscriber::util::SubscriberInitExt as _;
+use tracing_subscriber::EnvFilter;
+use wasmtime::component::{Component, InstancePre, Linker};
+use wasmtime::{
+    InstanceAllocationStrategy, PoolConcurrencyLimitError, PoolingAllocationConfig, Store,
+    StoreLimits, StoreLimitsBuilder, Trap, UpdateDeadline, WasmBacktrace, WasmBacktraceDetails,
+    WasmCoreDump,
+};
 use wasmtime_wasi::bindings::CommandPre;
-use wasmtime_wasi::{ResourceTable, WasiCtx, WasiCtxBuilder, WasiView};
-use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+use wasmtime_wasi::{I32Exit, ResourceTable, WasiCtx, WasiCtxBuilder, WasiView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
+
+use crate::blobstore::{Blobstore, BlobstoreCtx};
+use crate::config::{Config, ConfigCtx};
+use crate::instance::{Instance, InstanceCtx};
+use crate::keyvalue::{KeyValue, KeyValueCtx};
+use crate::logging::{Logging, LoggingCtx};
+use crate::messaging::{Messaging, MessagingCtx};
+use crate::network::{NetPolicy, NetRule};
+use crate::outgoing::{HostRule, HttpPolicy};
+use crate::pubsub::{Pubsub, PubsubCtx};
+use crate::report::Stats;
+
+mod addrspace;
+mod audit;
+mod bench;
+mod blobstore;
+mod cancel;
+mod caps;
+mod cgroup;
+mod chaos;
+mod clocks;
+mod compose;
+mod config;
+mod control;
+mod diff;
+mod dns;
+mod doctor;
+mod health;
+mod hugepages;
+mod imports;
+mod inspect;
+mod instance;
+mod invoke;
+mod keyvalue;
+mod logging;
+mod messaging;
+mod mount;
+mod network;
+#[cfg(feature = "wasi-nn")]
+mod nn;
+mod numa;
+mod otlp;
+mod outgoing;
+mod permissions;
+mod plan;
+mod pressure;
+mod profile;
+mod provenance;
+mod proxy;
+mod pubsub;
+mod random;
+mod ratelimit;
+mod report;
+mod sched;
+mod signature;
+mod source;
+mod stdin;
+mod systemd;
+mod top;
+mod trace;
+mod usage;
+mod validate;
+mod version;
 
 /// Run containerized Wasm on a Linux system.
 #[derive(Parser, Debug)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Option<Args>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print imports, exports and resource requirements of a component
+    Inspect(inspect::Args),
+    /// Run the component in sandboxes and print latency and resource usage statistics
+    Bench(Box<bench::Args>),
+    /// Run two versions of the component with identical inputs and report divergences
+    Diff(Box<diff::Args>),
+    /// Check the environment for kernel features and limits required to run sandboxes
+    Doctor(doctor::Args),
+    /// Print the version and, with `--features`, capabilities of the binary
+    Version(version::Args),
+}
+
+#[derive(clap::Args, Clone, Debug)]
 pub struct Args {
     /// Amount of cgroups/namespaces to create.
     ///
@@ -31,8 +143,640 @@ pub struct Args {
     #[clap(long)]
     cgroup: Option<PathBuf>,
 
-    /// Path to a Wasm command component to use
-    wasm: PathBuf,
+    /// Way sandbox cgroups are created.
+    ///
+    /// `systemd` moves the process into a transient `cgwasm-PID.scope` unit with delegation,
+    /// created via D-Bus, and creates sandbox cgroups within it
+    #[clap(long, value_enum, default_value_t, conflicts_with = "cgroup")]
+    cgroup_driver: systemd::CgroupDriver,
+
+    /// Whether each sandbox runs in its own cgroup.
+    ///
+    /// `off` runs sandboxes in the cgroup of the process, e.g. if it is not delegated,
+    /// without per-instance resource accounting and limits
+    #[clap(long, value_enum, default_value_t)]
+    cgroups: cgroup::Mode,
+
+    /// Number of host CPUs withheld from sandboxes for workloads other than cgwasm.
+    ///
+    /// The highest-numbered CPUs are removed from `cpuset.cpus` of the `--cgroup-prefix` cgroup
+    #[clap(long, value_name = "N")]
+    reserve_cpus: Option<NonZeroUsize>,
+
+    /// Host memory withheld from sandboxes for workloads other than cgwasm, like `4g`.
+    ///
+    /// The computed instance count only fits the remaining memory and `memory.max`
+    /// of the cgroup of the process is lowered accordingly until exit
+    #[clap(long, value_name = "BYTES")]
+    reserve_memory: Option<mount::Size>,
+
+    /// NUMA placement of sandboxes, one of `auto`, `node:N` or `interleave`.
+    ///
+    /// `auto` spreads instances across nodes, binding `cpuset.cpus` and `cpuset.mems`
+    /// of each sandbox cgroup to a single node. `node:N` binds all sandboxes to node `N`,
+    /// `interleave` interleaves memory of sandbox threads across all nodes. Has no effect
+    /// on single-node hosts.
+    ///
+    /// Pooling allocator slots are shared by all instances, so pages a slot keeps resident
+    /// across instances are not moved to the node of the instance reusing it
+    #[clap(long)]
+    numa: Option<numa::NumaConfig>,
+
+    /// Cancel all remaining instances as soon as one of them fails
+    #[clap(long)]
+    fail_fast: bool,
+
+    /// Print the computed plan and exit without creating cgroups or running instances.
+    ///
+    /// The plan lists the derived instance count, pooling allocator configuration
+    /// after environment overrides, cgroups and controllers to set up
+    /// and WASI capabilities granted to guests
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Path to write a JSON report of per-instance results to at the end of the run
+    #[clap(long)]
+    report: Option<PathBuf>,
+
+    /// `wasi:keyvalue` backend to use, either `memory` or `redb:PATH`
+    #[clap(long, default_value_t)]
+    kv_backend: keyvalue::BackendConfig,
+
+    /// Whether `wasi:keyvalue` buckets are shared by all instances or private to each one
+    #[clap(long, value_enum, default_value_t)]
+    kv_namespace: keyvalue::Namespace,
+
+    /// Maximum number of `cgwasm:cluster/pubsub` messages buffered per topic,
+    /// subscriptions lagging further behind miss messages
+    #[clap(long, default_value = "1024")]
+    pubsub_capacity: NonZeroUsize,
+
+    /// `wasi:messaging` backend to connect to, like `nats:127.0.0.1:4222`,
+    /// the connection is shared by all instances
+    #[clap(long)]
+    messaging_backend: Option<messaging::BackendConfig>,
+
+    /// Consumer group all instances join on `wasi:messaging` subscriptions,
+    /// so each message is delivered to a single instance
+    #[clap(long, requires = "messaging_backend")]
+    messaging_queue_group: Option<String>,
+
+    /// `wasi:blobstore` backend to use, either `fs:PATH` or `s3:BUCKET`,
+    /// S3 credentials, region and endpoint are read from `AWS_*` environment variables
+    #[clap(long)]
+    blobstore_backend: Option<blobstore::BackendConfig>,
+
+    /// Maximum size of a `wasi:blobstore` object written by a guest, like `64m`.
+    ///
+    /// Written data is buffered in memory until the object is stored,
+    /// so the output stream of an outgoing value is closed once it reaches this size
+    #[clap(long, value_name = "BYTES", default_value = "64m")]
+    blobstore_max_object_size: mount::Size,
+
+    /// `wasi-nn` model to preload for all instances, of the form `ENCODING::DIR`,
+    /// can be specified multiple times
+    #[cfg(feature = "wasi-nn")]
+    #[clap(long, value_name = "ENCODING::DIR")]
+    nn_model: Vec<nn::Preload>,
+
+    /// Device to load `wasi-nn` models on
+    #[cfg(feature = "wasi-nn")]
+    #[clap(long, value_enum, default_value_t)]
+    nn_device: nn::Device,
+
+    /// `wasi:config` value to expose to guests, can be specified multiple times
+    #[clap(long, value_name = "KEY=VALUE", value_parser = config::parse_key_value)]
+    guest_config: Vec<(String, String)>,
+
+    /// Path to a TOML file with a `[config]` table of `wasi:config` values to expose to guests.
+    ///
+    /// Values specified via `--guest-config` take precedence
+    #[clap(long)]
+    guest_config_file: Option<PathBuf>,
+
+    /// Guest clock behavior, one of `host`, `frozen`, `scaled:FACTOR` or `step:DURATION`.
+    ///
+    /// Each instance gets its own virtual clocks, started when the instance is created.
+    /// Defaults to `host`, or `step:1ms` if `--deterministic` is set
+    #[clap(long)]
+    clock: Option<clocks::ClockConfig>,
+
+    /// Wall clock time, in seconds since UNIX epoch, virtual clocks start at.
+    ///
+    /// If not set, host time at startup is used, or UNIX epoch if `--deterministic` is set
+    #[clap(long)]
+    clock_epoch: Option<u64>,
+
+    /// Source of guest `wasi:random`, one of `system`, `seed:HEX` or `per-instance-seed[:HEX]`.
+    ///
+    /// `per-instance-seed` seeds the generators of each instance by the seed plus its index.
+    /// Defaults to `system`, or `seed:0` if `--deterministic` is set
+    #[clap(long)]
+    random: Option<random::RandomConfig>,
+
+    /// Eliminate host nondeterminism observable by guests.
+    ///
+    /// This seeds guest randomness, virtualizes clocks, instantiates instances
+    /// one at a time in index order and canonicalizes NaNs
+    #[clap(long)]
+    deterministic: bool,
+
+    /// Record host interactions of the instance with index `INDEX` to `PATH`, of the form `[INDEX@]PATH`.
+    ///
+    /// Clock reads, random bytes and outgoing `wasi:http` responses are written as JSON lines
+    #[clap(long, value_name = "[INDEX@]PATH", conflicts_with = "replay")]
+    record: Option<trace::TraceFile>,
+
+    /// Replay host interactions recorded by `--record` to the instance with index `INDEX`.
+    ///
+    /// Replayed calls never reach the host, calls past the end of the trace observe
+    /// the last clock reads, zeroed random bytes and failing `wasi:http` requests
+    #[clap(long, value_name = "[INDEX@]PATH")]
+    replay: Option<trace::TraceFile>,
+
+    /// Allow outgoing `wasi:http` requests to `[SCHEME://]HOST[:PORT]`, can be specified multiple times.
+    ///
+    /// `HOST` may be `*` or start with `*.` to match subdomains.
+    /// If set, requests to any destination not allowed are denied
+    #[clap(long, value_name = "RULE")]
+    allow_http_host: Vec<HostRule>,
+
+    /// Deny outgoing `wasi:http` requests to `[SCHEME://]HOST[:PORT]`, can be specified multiple times.
+    ///
+    /// Takes precedence over `--allow-http-host`
+    #[clap(long, value_name = "RULE")]
+    deny_http_host: Vec<HostRule>,
+
+    /// Maximum duration of outgoing `wasi:http` requests until response headers are received
+    #[clap(long, value_parser = humantime::parse_duration)]
+    http_timeout: Option<Duration>,
+
+    /// Maximum size of outgoing `wasi:http` request and response bodies in bytes
+    #[clap(long)]
+    http_max_body_size: Option<u64>,
+
+    /// Proxy to send outgoing `wasi:http` `http` requests through, `HTTP_PROXY` by default
+    #[clap(long, value_name = "URL")]
+    http_proxy: Option<proxy::ProxyUrl>,
+
+    /// Proxy to tunnel outgoing `wasi:http` `https` requests through, `HTTPS_PROXY` by default
+    #[clap(long, value_name = "URL")]
+    https_proxy: Option<proxy::ProxyUrl>,
+
+    /// Comma-separated hosts and domains to send outgoing `wasi:http` requests to directly,
+    /// `NO_PROXY` by default.
+    ///
+    /// Domains also match their subdomains, `*` matches all hosts
+    #[clap(long, value_name = "HOSTS", value_delimiter = ',')]
+    no_proxy: Vec<String>,
+
+    /// Allow outgoing `wasi:sockets` connections to `[INDEX@]CIDR[:PORT]`, can be repeated.
+    ///
+    /// Sandboxes have a network namespace of their own with only a loopback interface,
+    /// so `wasi:sockets` are loopback-only and connections to other addresses are always
+    /// denied, outgoing `wasi:http` requests are sent from the host network namespace.
+    /// Rules prefixed by `INDEX@` only apply to the instance with that index.
+    /// If set, loopback connections to any destination not allowed are denied
+    #[clap(long, value_name = "RULE")]
+    allow_net: Vec<NetRule>,
+
+    /// Maximum rate of outgoing connections per second of each instance.
+    ///
+    /// Applies to `wasi:sockets` connects and outgoing `wasi:http` requests,
+    /// connections exceeding the rate are delayed rather than denied
+    #[clap(long, value_name = "CONNS_PER_SEC", value_parser = ratelimit::parse_rate)]
+    net_rate: Option<f64>,
+
+    /// Maximum number of outgoing `wasi:http` requests and `wasi:sockets` TCP connections
+    /// in flight of each instance.
+    ///
+    /// A request is in flight until its response headers are received, a TCP connection
+    /// until its socket is dropped, ones exceeding the limit wait for a free slot
+    #[clap(long, value_name = "CONNS")]
+    net_max_concurrent: Option<NonZeroUsize>,
+
+    /// Name resolution of `wasi:sockets/ip-name-lookup`, one of `host`, `static:FILE` or `server:ADDR`.
+    ///
+    /// `host` uses the host resolver, `static:FILE` only resolves names in the hosts file and
+    /// `server:ADDR` queries the DNS server at `ADDR` over UDP.
+    /// Lookups are performed in the host network namespace, resolved addresses other than
+    /// loopback ones are not reachable by loopback-only `wasi:sockets`
+    #[clap(long, value_name = "MODE", default_value = "host")]
+    dns: dns::Mode,
+
+    /// Hosts file of the form `[INDEX@]PATH` consulted before `--dns`, can be repeated.
+    ///
+    /// Files prefixed by `INDEX@` only apply to the instance with that index
+    /// and take precedence over the others
+    #[clap(long, value_name = "FILE")]
+    dns_hosts: Vec<dns::HostsFile>,
+
+    /// Path to a TOML file of `[[profile]]` or `[[instance]]` tables granting
+    /// WASI capabilities and arguments per instance.
+    ///
+    /// Each profile applies to the `instances` range, like `0-3` or `4-`, and may disable
+    /// `network`, covering sockets and `wasi:http`, or `inherit_env`,
+    /// replace `--allow-net` by `allow_net` rules,
+    /// pass `args`, set `env` variables and add `preopens`, like
+    /// `{ host = "/data", guest = "/data", writable = false }`.
+    /// The first matching profile applies, instances without one keep the defaults
+    #[clap(long, visible_alias = "manifest", value_name = "PATH")]
+    permission_profiles: Option<PathBuf>,
+
+    /// Path to a TOML file of `allow` and `deny` lists of import patterns,
+    /// like `allow = ["wasi:http/outgoing-handler", "wasi:cli/*"]` and `deny = ["wasi:sockets/*"]`.
+    ///
+    /// Versions are ignored and a trailing `*` matches any suffix. Components with imports
+    /// matching a `deny` pattern or, if `allow` is not empty, no `allow` pattern are refused
+    /// before any sandbox is started
+    #[clap(long, value_name = "PATH")]
+    import_policy: Option<PathBuf>,
+
+    /// Stub imports not provided by the host instead of refusing the component.
+    ///
+    /// With `trap`, the default, calls of stubbed functions trap. With `noop`, calls
+    /// of stubbed functions without results return and others trap.
+    /// Imports provided by the host with mismatching types are still refused
+    #[clap(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        default_missing_value = "trap"
+    )]
+    stub_missing_imports: Option<validate::Stub>,
+
+    /// Path to write an audit log of host operations performed by guests to.
+    ///
+    /// Each line is a JSON object with the time, instance index and `event`, one of
+    /// `open` for `wasi:filesystem` opens, `connect` for `wasi:sockets` destinations
+    /// and `http` for outgoing `wasi:http` requests
+    #[clap(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+
+    /// Inject faults into guest host calls, of the form `TARGET:fail=P[,delay=P@DURATION]`,
+    /// like `http:fail=0.1,delay=0.5@200ms`, can be specified multiple times.
+    ///
+    /// `TARGET` is one of `fs` for `wasi:filesystem` descriptor and stream operations, `net` for
+    /// `wasi:sockets` connects and `http` for outgoing `wasi:http` requests. Each call is
+    /// delayed by `DURATION` with probability `delay` and fails with probability `fail`.
+    /// Faults are drawn from the `--random` seed of the instance, if any
+    #[clap(long, value_name = "RULE")]
+    chaos: Vec<chaos::Rule>,
+
+    /// `io.max` limit of the form `DEV=RIOPS:WIOPS:RBPS:WBPS` of the whole process.
+    ///
+    /// This is a single budget shared by all sandboxes, not a per-instance limit: `io` is not
+    /// a threaded controller, so the limit is written to the cgroup of the process and restored
+    /// on exit. `DEV` is either `MAJOR:MINOR` or a block device path, limits are numbers
+    /// or `max`. Can be specified multiple times, requires the `io` controller in the cgroup
+    /// of the process
+    #[clap(long, value_name = "LIMIT")]
+    io_max: Vec<cgroup::IoMax>,
+
+    /// Stack size of sandbox threads in bytes, the Rust default of 2 MiB if not set.
+    ///
+    /// Guests run on separate fiber stacks, so this only bounds host code
+    #[clap(long, value_name = "BYTES")]
+    thread_stack_size: Option<usize>,
+
+    /// Nice value of sandbox threads, from -20 to 19.
+    ///
+    /// Negative values require `CAP_SYS_NICE`
+    #[clap(long, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-20..=19))]
+    nice: Option<i8>,
+
+    /// Scheduling policy of sandbox threads
+    #[clap(long, value_enum, default_value_t)]
+    sched_policy: sched::Policy,
+
+    /// Comma-separated CPU priority tiers of the form `NAME=WEIGHT`, like `gold=1000,silver=100`.
+    ///
+    /// Each sandbox cgroup is assigned a tier by `--cpu-tier-policy` and gets its weight
+    /// written to `cpu.weight`, so instances of higher tiers get proportionally more CPU time
+    /// under contention
+    #[clap(long, value_name = "TIERS", value_delimiter = ',')]
+    cpu_weight: Vec<cgroup::CpuTier>,
+
+    /// Policy assigning instances to `--cpu-weight` tiers
+    #[clap(long, value_enum, default_value_t, requires = "cpu_weight")]
+    cpu_tier_policy: cgroup::TierPolicy,
+
+    /// `cpu.weight` of the `cgwasm_control` cgroup holding the supervisor threads.
+    ///
+    /// The cgroup is a sibling of the sandbox cgroups, which default to a weight of 100,
+    /// so guests under contention cannot starve the runtime managing them
+    #[clap(long, value_name = "WEIGHT", default_value_t = 1000, value_parser = clap::value_parser!(u16).range(1..=10000))]
+    control_cpu_weight: u16,
+
+    /// Interval to poll `cpu.pressure` and `memory.pressure` of sandbox cgroups at.
+    ///
+    /// Pressure is logged at `debug` level with `pressure` target.
+    /// Defaults to `1s` if `--pressure-threshold` is set, pressure is not polled otherwise
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pressure_interval: Option<Duration>,
+
+    /// `some avg10` CPU or memory pressure percentage of all sandboxes combined,
+    /// above which instantiation of new instances is paused
+    #[clap(long, value_name = "PERCENT")]
+    pressure_threshold: Option<f64>,
+
+    /// Path to a Unix socket to accept control commands on.
+    ///
+    /// Commands are newline-delimited, `freeze INDEX` pauses an instance by freezing its cgroup,
+    /// `thaw INDEX` resumes it, `cancel INDEX` interrupts it without affecting other instances
+    /// and `restart INDEX` interrupts it and instantiates it anew, up to `--max-restarts` times.
+    /// Each command is answered by `ok` or `error: REASON`. All instances are cancelled
+    /// on `SIGINT` or `SIGTERM` and the process exits immediately on a second one
+    #[clap(long)]
+    control: Option<PathBuf>,
+
+    /// File of control commands to execute on `SIGUSR2`, one per line.
+    ///
+    /// Controls individual instances of runs without `--control`, e.g. `restart 3` is executed
+    /// by writing it to the file and sending `SIGUSR2` to the process.
+    /// The file is read anew on every signal
+    #[clap(long)]
+    control_file: Option<PathBuf>,
+
+    /// Remove sandbox cgroups without threads left over by previous runs on startup
+    #[clap(long)]
+    clean_stale_cgroups: bool,
+
+    /// Address to serve `/healthz` and `/readyz` HTTP health checks on.
+    ///
+    /// `/readyz` succeeds once the component is compiled, `/healthz` fails
+    /// if any running instance is wedged
+    #[clap(long)]
+    health_addr: Option<SocketAddr>,
+
+    /// Duration after which a running instance executing Wasm without calling the host
+    /// is considered wedged.
+    ///
+    /// Instances suspended in host calls, like polling for I/O, are idle rather than wedged
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    health_wedged_after: Duration,
+
+    /// Interrupt and restart running instances executing Wasm without calling the host
+    /// for this duration.
+    ///
+    /// Instances suspended in host calls, like polling for I/O, are idle rather than wedged.
+    /// The Wasm backtrace of interrupted instances is logged, those which cannot be restarted
+    /// anymore complete with a `wedged` outcome
+    #[clap(long, value_parser = humantime::parse_duration)]
+    watchdog_timeout: Option<Duration>,
+
+    /// Number of times each instance may be restarted.
+    ///
+    /// Restarts instantiate the component anew in a fresh store within the same sandbox
+    /// and cgroup, which keep their state
+    #[clap(long, default_value_t = 3)]
+    max_restarts: u32,
+
+    /// Restart instances on panics in host code, up to `--max-restarts` times.
+    ///
+    /// Panics are caught either way, instances which are not restarted complete
+    /// with a `panicked` outcome
+    #[clap(long)]
+    restart_on_panic: bool,
+
+    /// Grace period between requesting cancelled instances to shut down and interrupting them.
+    ///
+    /// Guests observe the request via `cgwasm:instance/lifecycle` and may flush state
+    /// and return from `wasi:cli/run` in the meantime.
+    /// If not set, cancelled instances are interrupted immediately
+    #[clap(long, value_parser = humantime::parse_duration)]
+    shutdown_timeout: Option<Duration>,
+
+    /// Show a live table of sandboxes on stderr, refreshed every second.
+    ///
+    /// The table lists the state, CPU usage and `memory.current` of each sandbox cgroup,
+    /// the linear memory size of its store, how often its instantiation was retried
+    /// and how often it was restarted
+    #[clap(long)]
+    tui: bool,
+
+    /// Write the state report dumped on `SIGUSR1` to this file instead of stderr.
+    ///
+    /// The report lists the state, CPU usage, `memory.current`, store memory, instantiation
+    /// retries and restarts of each sandbox and the pooling allocator slot utilization
+    #[clap(long)]
+    state_dump: Option<PathBuf>,
+
+    /// Path of the cgroup, relative to `--cgroup`, all sandbox cgroups are nested in.
+    ///
+    /// May contain `/` to nest within a sub-hierarchy and `{component}`,
+    /// which is substituted by the component file stem
+    #[clap(long, default_value = "cgwasm")]
+    cgroup_prefix: cgroup::NameTemplate,
+
+    /// Path of each sandbox cgroup, relative to `--cgroup-prefix`.
+    ///
+    /// May contain `/` to nest within a sub-hierarchy, `{component}`,
+    /// which is substituted by the component file stem, and `{index}`,
+    /// which is substituted by the instance index
+    #[clap(long, default_value = "cgwasm_sandbox_{index}")]
+    cgroup_name: cgroup::NameTemplate,
+
+    /// Guest stdin, one of `inherit`, `null`, `file:PATH` or `per-instance-dir:PATH`.
+    ///
+    /// `per-instance-dir:PATH` feeds `PATH/INDEX` to the instance with index `INDEX`
+    #[clap(long, default_value_t)]
+    stdin: stdin::StdinConfig,
+
+    /// Path to a component whose exports are plugged into matching imports of the command
+    /// component before pre-instantiation, can be specified multiple times
+    #[clap(long, value_name = "PATH")]
+    compose: Vec<PathBuf>,
+
+    /// Path to a WAC document composing the command component, available to it as `cgwasm:main`,
+    /// with other components into the component to run.
+    ///
+    /// Packages `NS:NAME` referenced by the document are read from `deps/NS/NAME.wasm`
+    /// next to it
+    #[clap(long, value_name = "PATH", conflicts_with = "compose")]
+    compose_config: Option<PathBuf>,
+
+    /// Path to an Ed25519 public key, raw or base64-encoded, components must be signed by.
+    ///
+    /// Detached signatures over the component bytes are read from `--signature`
+    /// and those of `--compose` plugs and `--compose-config` packages from `PATH.sig`,
+    /// unverified components are refused
+    #[clap(long, value_name = "PATH")]
+    verify_signature: Option<PathBuf>,
+
+    /// Path to the raw or base64-encoded detached signature of the component,
+    /// `WASM.sig` by default
+    #[clap(long, value_name = "PATH", requires = "verify_signature")]
+    signature: Option<PathBuf>,
+
+    /// Exported function to call instead of `wasi:cli/run`, of the form
+    /// `[INTERFACE#]FUNCTION[(ARGS...)]` with WAVE-encoded arguments, like `add(1, 2)`.
+    ///
+    /// Results are written to stdout, prefixed by the instance index
+    #[clap(long, value_name = "CALL")]
+    invoke: Option<invoke::Invoke>,
+
+    /// Read arguments of the `--invoke` function from stdin, one call per line,
+    /// instead of calling it once.
+    ///
+    /// Each line is either parenthesized WAVE arguments, like `(1, 2)`, or a JSON array,
+    /// like `[1, 2]`, with results encoded the same way. Lines are distributed among
+    /// instances and results are written to stdout, prefixed by the instance index
+    /// and the line number. Guest stdin defaults to `null`
+    #[clap(long, requires = "invoke")]
+    invoke_stdin: bool,
+
+    /// Maximum duration of instantiation of each instance, including retries
+    #[clap(long, value_parser = humantime::parse_duration)]
+    instantiate_timeout: Option<Duration>,
+
+    /// Amount of times instantiation is retried if instance pool slots are exhausted
+    #[clap(long, default_value_t = 0)]
+    instantiate_retries: u32,
+
+    /// Delay before the first instantiation retry, doubled on each subsequent retry
+    #[clap(long, value_parser = humantime::parse_duration, default_value = "100ms")]
+    instantiate_backoff: Duration,
+
+    /// Interval to stagger instantiation of instances by, instance `INDEX` is instantiated
+    /// no earlier than `INDEX` intervals after startup
+    #[clap(long, value_parser = humantime::parse_duration)]
+    ramp_up_interval: Option<Duration>,
+
+    /// Optimization level of generated code
+    #[clap(long, value_enum)]
+    opt_level: Option<OptLevel>,
+
+    /// Compiler to use for generating code
+    #[clap(long, value_enum, default_value_t)]
+    compiler: Compiler,
+
+    /// Whether functions are compiled in parallel, enabled by default
+    #[clap(long, value_name = "BOOL")]
+    parallel_compilation: Option<bool>,
+
+    /// Cranelift setting of the form `NAME[=VALUE]`, can be specified multiple times.
+    ///
+    /// Settings without a value are enabled, e.g. `has_avx2` to assume target CPU support
+    /// of AVX2 instead of detecting it
+    #[clap(long, value_name = "SETTING")]
+    cranelift_flag: Vec<CraneliftFlag>,
+
+    /// Comma-separated WebAssembly proposals to enable or disable, of the form `NAME[=BOOL]`.
+    ///
+    /// NAME is one of `memory64`, `multi-memory`, `threads`, `relaxed-simd`, `tail-call`
+    /// or `gc`, proposals not listed keep the Wasmtime defaults. Enabling `threads` disables
+    /// the pooling allocator, which does not support shared memories, and enabling
+    /// `multi-memory` raises the pooling limit of memories per module
+    #[clap(long, value_name = "FEATURES", value_delimiter = ',')]
+    wasm_features: Vec<WasmFeature>,
+
+    /// Maximum size of each linear memory of an instance, like `512m`.
+    ///
+    /// Defaults to `WASMTIME_POOLING_MAX_MEMORY_SIZE` or 4 GiB. Bounds memory growth
+    /// of instances, the pooling allocator slots and the computed instance count
+    #[clap(long, value_name = "BYTES")]
+    max_memory_size: Option<mount::Size>,
+
+    /// Disable copy-on-write initialization of linear memories from memory images.
+    ///
+    /// By default, memory images are created once per component and mapped copy-on-write
+    /// into each instance, so pages not written to are shared by all instances
+    #[clap(long)]
+    no_cow: bool,
+
+    /// Huge pages backing guest linear memories.
+    ///
+    /// `transparent` advises transparent huge pages for the linear memory slots of the pooling
+    /// allocator or, if it is not used, for each linear memory. `explicit` backs linear
+    /// memories with pages of the hugetlbfs pool, which requires the on-demand allocator,
+    /// and falls back to regular pages for memories the pool cannot back.
+    ///
+    /// With the on-demand allocator, copy-on-write initialization of linear memories
+    /// from memory images is disabled
+    #[clap(long, value_enum, default_value_t)]
+    huge_pages: hugepages::HugePages,
+
+    /// Size in bytes up to which memory images are created even for sparse initial memory
+    #[clap(long, value_name = "BYTES")]
+    memory_guaranteed_dense_image_size: Option<u64>,
+
+    /// Directory to write a Wasm core dump, including memories and the stack,
+    /// of each trapped instance to as `INDEX.coredump`
+    #[clap(long)]
+    coredump_dir: Option<PathBuf>,
+
+    /// Generate DWARF debug info for guests and register it with native debuggers via
+    /// the GDB JIT interface.
+    ///
+    /// Code is not optimized unless `--opt-level` is set. The TID of each sandbox thread
+    /// is logged on startup and included in the report
+    #[clap(long)]
+    debug: bool,
+
+    /// Hostname of each sandbox, set in its UTS namespace.
+    ///
+    /// May contain `{component}`, which is substituted by the component file stem,
+    /// and `{index}`, which is substituted by the instance index
+    #[clap(long, default_value = "cgwasm-{index}")]
+    hostname: cgroup::NameTemplate,
+
+    /// Capability to keep on sandbox threads, like `CAP_NET_BIND_SERVICE`,
+    /// can be specified multiple times.
+    ///
+    /// Sandbox threads set `no_new_privs` and drop all other capabilities
+    /// once their cgroup and namespaces are set up
+    #[clap(long, value_name = "CAP")]
+    keep_cap: Vec<caps::Cap>,
+
+    /// Profiling mode, one of `guest[,INTERVAL]`, `perfmap`, `jitdump` or `vtune`.
+    ///
+    /// `guest` samples guest stacks of each instance at `INTERVAL`, `10ms` by default.
+    /// The other modes let native profilers, like `perf record` of all sandbox threads,
+    /// attribute samples to Wasm function names
+    #[clap(long)]
+    profile: Option<profile::ProfileConfig>,
+
+    /// Directory to write guest profiles of instances to as `INDEX.json`,
+    /// in the Firefox profiler format
+    #[clap(long, default_value = ".")]
+    profile_dir: PathBuf,
+
+    /// OTLP/HTTP collector endpoint to export compilation and instance lifecycle spans to,
+    /// like `http://localhost:4318`
+    #[clap(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Address to serve Tokio task instrumentation of the root and sandbox runtimes on
+    /// for `tokio-console`, like `127.0.0.1:6669`
+    #[cfg(feature = "tokio-console")]
+    #[clap(long)]
+    tokio_console: Option<SocketAddr>,
+
+    /// Size of a private tmpfs mounted for each instance, like `64m`,
+    /// preopened for the guest at `--tmpfs-dir` and discarded once the instance exits
+    #[clap(long)]
+    tmpfs: Option<mount::Size>,
+
+    /// Guest path to preopen the `--tmpfs` at
+    #[clap(long, default_value = "/tmp", requires = "tmpfs")]
+    tmpfs_dir: String,
+
+    /// Host directory to preopen read-only for guests as `HOST::GUEST`.
+    ///
+    /// The directory is also bind-mounted read-only in the mount namespace of each sandbox.
+    /// Can be specified multiple times
+    #[clap(long)]
+    ro_dir: Vec<mount::RoDir>,
+
+    /// Path to a Wasm command component to use, `-` to read it from stdin
+    /// or `fd:N` to read it from inherited file descriptor `N`
+    wasm: source::WasmSource,
+
+    /// Calls of the `--invoke` function to distribute among instances instead of stdin
+    #[clap(skip)]
+    requests: Option<Arc<invoke::Requests>>,
 }
 
 fn getenv<T>(key: &str) -> Option<T>
@@ -54,7 +798,37 @@ where
     }
 }
 
-fn new_pooling_config(instances: u32) -> PoolingAllocationConfig {
+/// Default maximum size of a linear memory of the pooling allocator
+const DEFAULT_MAX_MEMORY_SIZE: usize = 1 << 32;
+
+/// Pooling allocator slots of each kind reserved per instance
+const POOLING_SLOTS_PER_INSTANCE: u32 = 4;
+
+/// Maximum number of memories per module of the pooling allocator with `multi-memory` enabled
+const MULTI_MEMORY_MAX_MEMORIES: u32 = 4;
+
+/// Default maximum number of elements of a table, that of the pooling allocator
+const DEFAULT_MAX_TABLE_ELEMENTS: usize = 20_000;
+
+/// Maximum number of core instances of a store, which components create several of
+const STORE_MAX_INSTANCES: usize = 64;
+
+/// Maximum number of tables of a store
+const STORE_MAX_TABLES: usize = 64;
+
+/// Maximum number of linear memories of a store
+const STORE_MAX_MEMORIES: usize = 16;
+
+/// Returns the maximum size of a linear memory, `size` if set,
+/// otherwise `WASMTIME_POOLING_MAX_MEMORY_SIZE` or the default
+fn max_memory_size_or_default(size: Option<mount::Size>) -> usize {
+    match size {
+        Some(mount::Size(n)) => usize::try_from(n).unwrap_or(usize::MAX),
+        None => getenv("WASMTIME_POOLING_MAX_MEMORY_SIZE").unwrap_or(DEFAULT_MAX_MEMORY_SIZE),
+    }
+}
+
+fn new_pooling_config(instances: u32, max_memory_size: usize) -> PoolingAllocationConfig {
     let mut config = PoolingAllocationConfig::default();
     if let Some(v) = getenv("WASMTIME_POOLING_MAX_UNUSED_WASM_SLOTS") {
         config.max_unused_warm_slots(v);
@@ -123,9 +897,7 @@ fn new_pooling_config(instances: u32) -> PoolingAllocationConfig {
     if let Some(v) = getenv("WASMTIME_POOLING_MAX_MEMORIES_PER_MODULE") {
         config.max_memories_per_module(v);
     }
-    if let Some(v) = getenv("WASMTIME_POOLING_MAX_MEMORY_SIZE") {
-        config.max_memory_size(v);
-    }
+    config.max_memory_size(max_memory_size);
     // TODO: Add memory protection key support
     if let Some(v) = getenv("WASMTIME_POOLING_TOTAL_GC_HEAPS") {
         config.total_gc_heaps(v);
@@ -152,329 +924,2184 @@ fn use_pooling_allocator_by_default() -> anyhow::Result<bool> {
     Ok(wasmtime::Memory::new(&mut store, ty).is_ok())
 }
 
-pub struct Ctx {
-    pub table: ResourceTable,
-    pub wasi: WasiCtx,
-    pub http: WasiHttpCtx,
+/// Optimization level of generated code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OptLevel {
+    /// No optimizations
+    None,
+    /// Optimize for speed
+    Speed,
+    /// Optimize for speed and size
+    SpeedAndSize,
 }
 
-impl WasiView for Ctx {
-    fn ctx(&mut self) -> &mut WasiCtx {
-        &mut self.wasi
-    }
-    fn table(&mut self) -> &mut ResourceTable {
-        &mut self.table
+impl From<OptLevel> for wasmtime::OptLevel {
+    fn from(level: OptLevel) -> Self {
+        match level {
+            OptLevel::None => Self::None,
+            OptLevel::Speed => Self::Speed,
+            OptLevel::SpeedAndSize => Self::SpeedAndSize,
+        }
     }
 }
 
-impl WasiHttpView for Ctx {
-    fn ctx(&mut self) -> &mut WasiHttpCtx {
-        &mut self.http
+/// Compiler used for generating code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compiler {
+    /// Optimizing compiler
+    #[default]
+    Cranelift,
+    /// Baseline compiler with fast compilation
+    Winch,
+}
+
+impl From<Compiler> for wasmtime::Strategy {
+    fn from(compiler: Compiler) -> Self {
+        match compiler {
+            Compiler::Cranelift => Self::Cranelift,
+            Compiler::Winch => Self::Winch,
+        }
     }
-    fn table(&mut self) -> &mut ResourceTable {
-        &mut self.table
+}
+
+/// Cranelift setting of the form `NAME[=VALUE]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CraneliftFlag {
+    name: String,
+    value: Option<String>,
+}
+
+impl FromStr for CraneliftFlag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (s, None),
+        };
+        if name.is_empty() {
+            bail!("empty Cranelift setting name in `{s}`");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value,
+        })
     }
 }
 
-fn main() -> anyhow::Result<()> {
-    let Args {
-        count,
-        wasm,
-        cgroup,
-    } = Args::parse();
+/// WebAssembly proposal toggled by `--wasm-features`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum WasmProposal {
+    /// 64-bit linear memories
+    Memory64,
+    /// Multiple linear memories per module
+    MultiMemory,
+    /// Shared memories and atomics
+    Threads,
+    /// Relaxed SIMD instructions
+    RelaxedSimd,
+    /// Tail calls
+    TailCall,
+    /// Garbage collection, implies function references
+    Gc,
+}
 
-    unshare(CloneFlags::CLONE_NEWUSER).context("failed to unshare user namespace")?;
+/// WebAssembly proposal setting of the form `NAME[=BOOL]`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WasmFeature {
+    proposal: WasmProposal,
+    enabled: bool,
+}
 
-    let pid = process::id();
-    let nofile = rlimit::Resource::NOFILE
-        .get_soft()
-        .context("failed to get `NOFILE` rlimit")?;
+impl FromStr for WasmFeature {
+    type Err = anyhow::Error;
 
-    let rt = tokio::runtime::Builder::new_multi_thread()
-        .enable_io()
-        .enable_time()
-        .thread_name("cgwasm")
-        .build()
-        .context("failed to build root Tokio runtime")?;
-    let rt = rt.handle();
-    rt.block_on(async move {
-        let (cg, wasm) = try_join!(
-            async {
-                if let Some(cgroup) = cgroup {
-                    Ok(cgroup)
-                } else {
-                    let cg = fs::read_to_string("/proc/self/cgroup")
-                        .await
-                        .context("failed to read `/proc/self/cgroup`")?;
-                    let cg = cg
-                        .trim()
-                        .strip_prefix("0::/")
-                        .context("process does not run within cgroup v2")?;
-                    Ok(Path::new("/sys/fs/cgroup").join(cg))
-                }
-            },
-            async {
-                fs::read(&wasm)
-                    .await
-                    .with_context(|| format!("failed to read `{}`", wasm.display()))
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, enabled) = match s.split_once('=') {
+            Some((name, value)) => {
+                let enabled = value
+                    .parse()
+                    .with_context(|| format!("invalid value of WebAssembly proposal `{s}`"))?;
+                (name, enabled)
             }
-        )?;
+            None => (s, true),
+        };
+        let proposal = clap::ValueEnum::from_str(name, false)
+            .map_err(|_| anyhow!("unknown WebAssembly proposal `{name}`"))?;
+        Ok(Self { proposal, enabled })
+    }
+}
 
-        let count = if let Some(count) = count {
-            count.into()
-        } else {
-            let pids_current_path = cg.join("pids.current");
-            let pids_max_path = cg.join("pids.max");
-            let (threads_max, pids_current, pids_max) = join!(
-                async {
-                    let threads_max = fs::read_to_string("/proc/sys/kernel/threads-max")
-                        .await
-                        .context("failed to read `/proc/sys/kernel/threads-max`")?;
-                    threads_max
-                        .trim()
-                        .parse::<usize>()
-                        .context("failed to parse `/proc/sys/kernel/threads-max` contents")
-                },
-                fs::read_to_string(&pids_current_path),
-                fs::read_to_string(&pids_max_path),
-            );
-            let threads_max = threads_max?;
-            let nproc = rlimit::Resource::NPROC
-                .get_soft()
-                .context("failed to get `NPROC` rlimit")?;
-            eprintln!("threads-max: {threads_max}");
-            eprintln!("NPROC: {nproc}");
-            let mut count = threads_max
-                .min(nproc.try_into().unwrap_or(usize::MAX))
-                .min(nofile.try_into().unwrap_or(usize::MAX));
-            match pids_max.as_deref().map(str::trim) {
-                Ok("max") => {
-                    eprintln!("pids.max: max");
-                }
-                Ok(pids_max) => {
-                    eprintln!("pids.max: {pids_max}");
-                    let pids_max = pids_max.parse::<usize>().with_context(|| {
-                        format!("failed to parse `{}` contents", pids_max_path.display())
-                    })?;
-                    match pids_current {
-                        Ok(pids_current) => {
-                            eprintln!("pids.current: {pids_current}");
-                            let pids_current =
-                                pids_current.trim().parse::<usize>().with_context(|| {
-                                    format!(
-                                        "failed to parse `{}` contents",
-                                        pids_current_path.display()
-                                    )
-                                })?;
-                            count = count.min(pids_max.saturating_sub(pids_current));
+impl WasmFeature {
+    /// Returns whether `proposal` is set to be enabled by the last matching setting
+    fn enabled(features: &[Self], proposal: WasmProposal) -> Option<bool> {
+        features
+            .iter()
+            .rev()
+            .find(|feature| feature.proposal == proposal)
+            .map(|feature| feature.enabled)
+    }
+
+    fn configure(&self, config: &mut wasmtime::Config) {
+        match self.proposal {
+            WasmProposal::Memory64 => config.wasm_memory64(self.enabled),
+            WasmProposal::MultiMemory => config.wasm_multi_memory(self.enabled),
+            WasmProposal::Threads => config.wasm_threads(self.enabled),
+            WasmProposal::RelaxedSimd => config.wasm_relaxed_simd(self.enabled),
+            WasmProposal::TailCall => config.wasm_tail_call(self.enabled),
+            WasmProposal::Gc if self.enabled => config.wasm_function_references(true).wasm_gc(true),
+            WasmProposal::Gc => config.wasm_gc(false),
+        };
+    }
+}
+
+/// Outcome of a single sandbox instance
+#[derive(Debug)]
+pub enum Outcome {
+    /// `wasi:cli/run` returned success
+    Success,
+    /// `wasi:cli/run` returned failure
+    Failure,
+    /// Guest explicitly exited with a status code
+    Exit(i32),
+    /// Instance was cancelled before it completed
+    Cancelled,
+    /// Instance could not be instantiated, because instance pool slots were exhausted
+    PoolExhausted(anyhow::Error),
+    /// Instance could not be set up, instantiated or has trapped
+    Error(anyhow::Error),
+    /// Host code panicked on the sandbox thread, with the panic message
+    Panicked(String),
+    /// Instance made no progress and was interrupted by the watchdog
+    Wedged,
+}
+
+impl Outcome {
+    fn new(res: anyhow::Result<Result<(), ()>>) -> Self {
+        match res {
+            Ok(Ok(())) => Self::Success,
+            Ok(Err(())) => Self::Failure,
+            Err(err) => {
+                if let Some(I32Exit(code)) = err.downcast_ref() {
+                    Self::Exit(*code)
+                } else if err.is::<PoolConcurrencyLimitError>() {
+                    Self::PoolExhausted(err)
+                } else {
+                    Self::Error(err)
+                }
+            }
+        }
+    }
+
+    pub fn status(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::Exit(..) => "exit",
+            Self::Cancelled => "cancelled",
+            Self::PoolExhausted(..) => "pool_exhausted",
+            Self::Error(..) => "error",
+            Self::Panicked(..) => "panicked",
+            Self::Wedged => "wedged",
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Success | Self::Exit(0))
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Success => write!(f, "success"),
+            Self::Failure => write!(f, "failure"),
+            Self::Exit(code) => write!(f, "exit({code})"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::PoolExhausted(err) => write!(f, "pool exhausted: {err:#}"),
+            Self::Error(err) => write!(f, "error: {err:#}"),
+            Self::Panicked(msg) => write!(f, "panicked: {msg}"),
+            Self::Wedged => write!(f, "wedged"),
+        }
+    }
+}
+
+pub struct Ctx {
+    pub table: ResourceTable,
+    pub wasi: WasiCtx,
+    pub http: WasiHttpCtx,
+    pub http_policy: Arc<HttpPolicy>,
+    pub keyvalue: KeyValueCtx,
+    pub config: ConfigCtx,
+    pub logging: LoggingCtx,
+    pub instance: InstanceCtx,
+    pub pubsub: PubsubCtx,
+    pub messaging: MessagingCtx,
+    pub blobstore: BlobstoreCtx,
+    #[cfg(feature = "wasi-nn")]
+    pub nn: wasmtime_wasi_nn::wit::WasiNnCtx,
+    /// Audit log of host operations, if enabled
+    pub audit: Option<audit::Audit>,
+    /// Fault injector of `--chaos`, if enabled
+    pub chaos: Option<Arc<chaos::Chaos>>,
+    /// Recording or replay of host interactions, if enabled for the instance
+    pub trace: Option<Arc<trace::Trace>>,
+    /// Name resolution of `wasi:sockets/ip-name-lookup`
+    pub dns: dns::Lookup,
+    /// Outgoing connection limits, if any
+    pub net_limiter: Option<Arc<ratelimit::NetLimiter>>,
+    pub limiter: usage::Limiter,
+}
+
+impl WasiView for Ctx {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiHttpView for Ctx {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.http
+    }
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let limiter = self.net_limiter.clone();
+        let chaos = self.chaos.clone();
+        let trace = self.trace.clone();
+        let Some(audit) = &self.audit else {
+            return self
+                .http_policy
+                .send_request(request, config, limiter, chaos, trace);
+        };
+        let method = request.method().clone();
+        let uri = request.uri().to_string();
+        let res = self
+            .http_policy
+            .send_request(request, config, limiter, chaos, trace);
+        audit.record(audit::Event::Http {
+            method: method.as_str(),
+            uri: &uri,
+            allowed: res.is_ok(),
+        });
+        res
+    }
+}
+
+/// Store data of a sandbox
+pub trait SandboxView: WasiView + WasiHttpView + Send + 'static {
+    /// Returns the resource limiter of the store, the limits of which are set by the sandbox
+    /// before instantiation
+    fn limiter(&mut self) -> &mut usage::Limiter;
+}
+
+impl SandboxView for Ctx {
+    fn limiter(&mut self) -> &mut usage::Limiter {
+        &mut self.limiter
+    }
+}
+
+/// Derives store limits from `max_memory_size` and the share of each of `instances`
+/// in the [memory budget](cgroup::memory_budget) of the cgroup at `path`, if any,
+/// so guests fail to grow memories instead of being OOM-killed.
+///
+/// Returns the limits along with the share, which bounds the total size of all linear
+/// memories of the store
+fn store_limits(
+    path: &Path,
+    max_memory_size: usize,
+    instances: usize,
+) -> (StoreLimits, Option<u64>) {
+    let share = cgroup::memory_budget(path)
+        .map(|budget| budget / u64::try_from(instances.max(1)).unwrap_or(u64::MAX));
+    let memory_size = share.map_or(max_memory_size, |share| {
+        usize::try_from(share)
+            .unwrap_or(usize::MAX)
+            .min(max_memory_size)
+    });
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(memory_size)
+        .table_elements(
+            getenv("WASMTIME_POOLING_TABLE_ELEMENTS").unwrap_or(DEFAULT_MAX_TABLE_ELEMENTS),
+        )
+        .instances(STORE_MAX_INSTANCES)
+        .tables(STORE_MAX_TABLES)
+        .memories(STORE_MAX_MEMORIES)
+        .build();
+    (limits, share)
+}
+
+/// Progress of sequential instantiation
+#[derive(Debug, Default)]
+pub struct Turns {
+    /// Index of the instance whose turn it is
+    next: usize,
+    /// Indices of instances past `next` which are done already, e.g. because they failed early
+    done: BTreeSet<usize>,
+}
+
+/// Turn of an instance in sequential instantiation order.
+///
+/// The turn is passed on to the next instance on drop, once all preceding instances are done
+pub struct Turn {
+    tx: watch::Sender<Turns>,
+    index: usize,
+}
+
+impl Turn {
+    pub fn new(tx: watch::Sender<Turns>, index: usize) -> Self {
+        Self { tx, index }
+    }
+
+    /// Waits until all instances preceding this one are done
+    pub async fn wait(&self) {
+        _ = self
+            .tx
+            .subscribe()
+            .wait_for(|turns| turns.next >= self.index)
+            .await;
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.tx.send_modify(|turns| {
+            turns.done.insert(self.index);
+            while turns.done.remove(&turns.next) {
+                turns.next += 1;
+            }
+        });
+    }
+}
+
+/// Instantiation timeout and retry behavior
+#[derive(Clone, Copy, Debug)]
+pub struct Instantiate {
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Instantiate {
+    /// Instantiates `pre` in `store`, retrying with exponential backoff while instance pool
+    /// slots are exhausted, for at most the configured timeout
+    pub async fn instantiate<T: WasiView + WasiHttpView + Send>(
+        &self,
+        pre: &InstancePre<T>,
+        store: &mut Store<T>,
+        on_retry: impl Fn(),
+    ) -> anyhow::Result<wasmtime::component::Instance> {
+        let attempts = async {
+            let mut backoff = self.backoff;
+            let mut retries = self.retries;
+            loop {
+                match pre.instantiate_async(&mut *store).await {
+                    Err(err) if retries > 0 && err.is::<PoolConcurrencyLimitError>() => {
+                        eprintln!("instance pool slots exhausted, retry in {backoff:?}: {err}");
+                        on_retry();
+                        tokio::time::sleep(backoff).await;
+                        backoff = backoff.saturating_mul(2);
+                        retries -= 1;
+                    }
+                    res => return res,
+                }
+            }
+        };
+        if let Some(timeout) = self.timeout {
+            tokio::time::timeout(timeout, attempts)
+                .await
+                .with_context(|| format!("instantiation timed out after {timeout:?}"))?
+        } else {
+            attempts.await
+        }
+    }
+}
+
+/// Host-side state of a single sandbox running a component with store data `T`
+pub struct Sandbox<T> {
+    pub index: usize,
+    pub name: String,
+    pub engine: wasmtime::Engine,
+    pub wasm_rx: broadcast::Receiver<InstancePre<T>>,
+    /// Exported function to call instead of `wasi:cli/run`
+    pub invoke: Option<invoke::Invoke>,
+    /// Calls of [`Sandbox::invoke`] read from stdin
+    pub requests: Option<Arc<invoke::Requests>>,
+    pub cancel_rx: watch::Receiver<bool>,
+    /// Restart requests, counted
+    pub restart_rx: watch::Receiver<u64>,
+    /// Number of times the instance may be restarted
+    pub max_restarts: u32,
+    /// Whether the instance is restarted on panics in host code
+    pub restart_on_panic: bool,
+    pub turn: Option<Turn>,
+    pub throttle_rx: watch::Receiver<bool>,
+    pub limits: Arc<cgroup::Limits>,
+    pub health: Option<Arc<health::Health>>,
+    /// Lifecycle shown by `--tui` and dumped on `SIGUSR1`
+    pub top: Arc<top::Top>,
+    pub instantiate: Instantiate,
+    /// Point in time instantiation must not start before
+    pub start_at: Option<Instant>,
+    /// Directory to write core dumps of trapped instances to
+    pub coredump_dir: Option<Arc<Path>>,
+    /// Whether the TID is logged for native debuggers to attach to
+    pub debug: bool,
+    pub guest_profile: Option<profile::GuestProfile>,
+    /// Maximum size of each linear memory of the instance
+    pub max_memory_size: usize,
+    /// Number of instances sharing the memory budget of the cgroup
+    pub instances: usize,
+    pub hostname: String,
+    /// Capabilities retained by the sandbox thread
+    pub keep_caps: Arc<[caps::Cap]>,
+    /// Whether the sandbox thread is moved into its own cgroup
+    pub cgroup: bool,
+    /// Span covering the instance lifecycle
+    pub span: Span,
+    /// Filesystems to mount in the mount namespace of the sandbox
+    pub mounts: Arc<mount::Mounts>,
+    /// Scheduling attributes of the sandbox thread
+    pub sched: sched::Sched,
+}
+
+/// Creates a linker with all host interfaces available to guests
+pub fn new_linker(engine: &wasmtime::Engine) -> anyhow::Result<Linker<Ctx>> {
+    let mut linker = Linker::new(engine);
+    wasmtime_wasi::add_to_linker_async(&mut linker).context("failed to link WASI")?;
+    audit::add_to_linker(&mut linker, |ctx: &mut Ctx| audit::Filesystem::new(ctx))
+        .context("failed to link `wasi:filesystem`")?;
+    dns::add_to_linker(&mut linker, |ctx: &mut Ctx| dns::NameLookup::new(ctx))
+        .context("failed to link `wasi:sockets/ip-name-lookup`")?;
+    ratelimit::add_to_linker(&mut linker, |ctx: &mut Ctx| ratelimit::Tcp::new(ctx))
+        .context("failed to link `wasi:sockets/tcp`")?;
+    wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)
+        .context("failed to link `wasi:http`")?;
+    keyvalue::add_to_linker(&mut linker, |ctx: &mut Ctx| {
+        KeyValue::new(&ctx.keyvalue, &mut ctx.table)
+    })
+    .context("failed to link `wasi:keyvalue`")?;
+    config::add_to_linker(&mut linker, |ctx: &mut Ctx| Config::new(&ctx.config))
+        .context("failed to link `wasi:config`")?;
+    logging::add_to_linker(&mut linker, |ctx: &mut Ctx| Logging::new(&ctx.logging))
+        .context("failed to link `wasi:logging`")?;
+    instance::add_to_linker(&mut linker, |ctx: &mut Ctx| {
+        Instance::new(&ctx.instance, &mut ctx.table)
+    })
+    .context("failed to link `cgwasm:instance`")?;
+    pubsub::add_to_linker(&mut linker, |ctx: &mut Ctx| {
+        Pubsub::new(&ctx.pubsub, &mut ctx.table)
+    })
+    .context("failed to link `cgwasm:cluster`")?;
+    messaging::add_to_linker(&mut linker, |ctx: &mut Ctx| {
+        Messaging::new(&mut ctx.messaging, &mut ctx.table)
+    })
+    .context("failed to link `wasi:messaging`")?;
+    blobstore::add_to_linker(&mut linker, |ctx: &mut Ctx| {
+        Blobstore::new(&ctx.blobstore, &mut ctx.table)
+    })
+    .context("failed to link `wasi:blobstore`")?;
+    #[cfg(feature = "wasi-nn")]
+    wasmtime_wasi_nn::wit::add_to_linker(&mut linker, |ctx: &mut Ctx| {
+        wasmtime_wasi_nn::wit::WasiNnView::new(&mut ctx.table, &mut ctx.nn)
+    })
+    .context("failed to link `wasi-nn`")?;
+    Ok(linker)
+}
+
+/// Logs the Wasm backtrace of a trap or host error of instance at `index`, if any
+fn log_backtrace(index: usize, err: &anyhow::Error) {
+    if err.is::<I32Exit>() {
+        return;
+    }
+    let Some(backtrace) = err.downcast_ref::<WasmBacktrace>() else {
+        return;
+    };
+    if let Some(trap) = err.downcast_ref::<Trap>() {
+        eprintln!("instance {index} trapped: {trap}");
+    } else {
+        eprintln!("instance {index} failed: {}", err.root_cause());
+    }
+    for line in backtrace.to_string().lines() {
+        eprintln!("[{index}] {line}");
+    }
+}
+
+/// Writes the core dump attached to a trap of instance at `index` to `dir`, if any
+async fn write_coredump<T>(index: usize, dir: &Path, err: &anyhow::Error, store: &mut Store<T>) {
+    let Some(coredump) = err.downcast_ref::<WasmCoreDump>() else {
+        return;
+    };
+    let name = format!("{index}.coredump");
+    let path = dir.join(&name);
+    let buf = coredump.serialize(store, &name);
+    if let Err(err) = fs::write(&path, buf).await {
+        eprintln!(
+            "failed to write core dump of instance {index} to `{}`: {err}",
+            path.display()
+        );
+    } else {
+        eprintln!(
+            "wrote core dump of instance {index} to `{}`",
+            path.display()
+        );
+    }
+}
+
+/// Cancels all instances on `SIGINT` or `SIGTERM` until the task is aborted.
+///
+/// Guests blocked in host calls are not interrupted by cancellation, so the process exits
+/// immediately on a second signal, without restoring the cgroup configuration
+async fn cancel_on_signal(cancel: Arc<cancel::Cancel>) {
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("failed to install signal handlers: {err}");
+            return;
+        }
+    };
+    let mut received = false;
+    loop {
+        let (sig, signo) = select! {
+            _ = interrupt.recv() => ("SIGINT", libc::SIGINT),
+            _ = terminate.recv() => ("SIGTERM", libc::SIGTERM),
+        };
+        if received {
+            eprintln!("received {sig} again, exit");
+            process::exit(128 + signo);
+        }
+        received = true;
+        if cancel.cancel_all() {
+            eprintln!("received {sig}, cancel all instances");
+        }
+    }
+}
+
+/// Returns the message of a panic `payload`
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Sets up the sandbox for the current thread and runs the component within it.
+///
+/// The store data is built by `ctx` from the WASI context of the sandbox
+fn run_sandbox<T: SandboxView>(
+    sandbox: Sandbox<T>,
+    mut wasi: WasiCtxBuilder,
+    ctx: impl FnOnce(WasiCtx) -> T,
+    stats: &mut Stats,
+) -> anyhow::Result<Outcome> {
+    let Sandbox {
+        index,
+        name,
+        engine,
+        mut wasm_rx,
+        invoke,
+        requests,
+        cancel_rx,
+        mut restart_rx,
+        max_restarts,
+        restart_on_panic,
+        mut turn,
+        mut throttle_rx,
+        limits,
+        health,
+        top,
+        instantiate,
+        start_at,
+        coredump_dir,
+        debug,
+        guest_profile,
+        max_memory_size,
+        instances,
+        hostname,
+        keep_caps,
+        cgroup,
+        span,
+        mounts,
+        sched,
+    } = sandbox;
+    let cg = stats.cgroup.clone();
+    let tid = unsafe { libc::gettid() };
+    stats.tid = Some(tid);
+    if debug {
+        eprintln!("instance {index} runs on TID {tid}");
+    }
+    if cgroup {
+        std::fs::create_dir_all(&cg)
+            .with_context(|| format!("failed to create `{name}` cgroup"))?;
+        let path = cg.join("cgroup.type");
+        std::fs::write(&path, b"threaded")
+            .with_context(|| format!("failed to write `threaded` to `{}`", path.display()))?;
+        let path = cg.join("cgroup.threads");
+        std::fs::write(&path, tid.to_string())
+            .with_context(|| format!("failed to write `{tid}` to `{}`", path.display()))?;
+        limits
+            .apply(index, &cg)
+            .with_context(|| format!("failed to apply `{name}` cgroup limits"))?;
+    }
+    sched
+        .apply()
+        .context("failed to set scheduling attributes")?;
+    stats.start();
+    unshare(
+        CloneFlags::CLONE_NEWIPC
+            | CloneFlags::CLONE_NEWNET
+            | CloneFlags::CLONE_NEWNS
+            | CloneFlags::CLONE_NEWUTS,
+    )
+    .context("failed to unshare thread")?;
+    network::loopback_up().context("failed to bring up the loopback interface")?;
+    sethostname(&hostname).with_context(|| format!("failed to set hostname `{hostname}`"))?;
+    // preopens must be opened within the mount namespace of the sandbox
+    mounts.apply(&mut wasi).context("failed to set up mounts")?;
+    let ctx = ctx(wasi.build());
+    caps::drop_privileges(&keep_caps).context("failed to drop privileges")?;
+    // TODO: `pivot_root` etc.
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .thread_name(&name)
+        .build()
+        .with_context(|| format!("failed to build runtime for sandbox {name}"))?;
+
+    Ok(rt.block_on(
+        async {
+            let run = async {
+                let wasm: InstancePre<T> = wasm_rx.recv().await.context("Wasm sender closed")?;
+                if let Some(start_at) = start_at {
+                    tokio::time::sleep_until(start_at.into()).await;
+                }
+                if let Some(turn) = &turn {
+                    turn.wait().await;
+                }
+                // restarts instantiate the component anew in a fresh store within the same
+                // sandbox and cgroup, keeping the context of the previous store
+                let mut data = ctx;
+                let mut restarts = 0;
+                loop {
+                    let mut store = Store::new(&engine, data);
+                    let profiler = guest_profile.as_ref().map(|profile| {
+                        Arc::new(Mutex::new(Some(profile::GuestProfiler::new(
+                            &name,
+                            tid.try_into().unwrap_or_default(),
+                            profile.interval,
+                        ))))
+                    });
+                    if let Some(health) = health.clone() {
+                        store.call_hook(move |_, hook| {
+                            health.on_call(index, hook);
+                            Ok(())
+                        });
+                    }
+                    // restarts requested so far are satisfied by this instantiation
+                    restart_rx.borrow_and_update();
+                    // the engine epoch is incremented on cancellation or restart of any instance
+                    // and at the sampling interval when profiling
+                    store.set_epoch_deadline(1);
+                    let cancelled = cancel_rx.clone();
+                    let restart = restart_rx.clone();
+                    let sampler = profiler.clone();
+                    store.epoch_deadline_callback(move |store| {
+                        if *cancelled.borrow() || restart.has_changed().unwrap_or(false) {
+                            return Err(Trap::Interrupt.into());
+                        }
+                        if let Some(sampler) = &sampler {
+                            let backtrace = WasmBacktrace::capture(&store);
+                            if let Some(profiler) = &mut *sampler.lock().unwrap() {
+                                profiler.sample(&backtrace);
+                            }
                         }
-                        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                            count = count.min(pids_max);
+                        Ok(UpdateDeadline::Yield(1))
+                    });
+                    _ = throttle_rx.wait_for(|throttled| !*throttled).await;
+                    // limits of the cgroup may have changed while waiting
+                    let (limits, memory_budget) = store_limits(&cg, max_memory_size, instances);
+                    let limiter = store.data_mut().limiter();
+                    limiter.limits = limits;
+                    limiter.memory_budget = memory_budget;
+                    store.limiter(|data| data.limiter());
+                    top.set_instantiating(index);
+                    let start = Instant::now();
+                    let instance = instantiate
+                        .instantiate(&wasm, &mut store, || top.retried(index))
+                        .instrument(info_span!("instantiate"))
+                        .await
+                        .context("failed to instantiate the component")?;
+                    stats.instantiate = Some(start.elapsed());
+                    drop(turn.take());
+                    if let Some(health) = &health {
+                        health.set_running(index);
+                    }
+                    top.set_running(index);
+                    let start = Instant::now();
+                    let call = async {
+                        if let Some(invoke) = &invoke {
+                            invoke
+                                .run(index, &mut store, &instance, requests.as_deref())
+                                .await
+                        } else {
+                            wasmtime_wasi::bindings::Command::new(&mut store, &instance)?
+                                .wasi_cli_run()
+                                .call_run(&mut store)
+                                .await
                         }
-                        Err(err) => {
+                    }
+                    .instrument(info_span!("run"));
+                    // guests suspended in host calls are not interrupted by the epoch
+                    let mut restart = restart_rx.clone();
+                    let res = select! {
+                        res = AssertUnwindSafe(call).catch_unwind() => {
+                            Some(res.map_err(|payload| panic_message(&*payload)))
+                        }
+                        Ok(()) = restart.changed() => None,
+                    };
+                    stats.run = Some(start.elapsed());
+                    stats.sample_memory();
+                    stats.store = Some(store.data_mut().limiter().usage());
+                    match &res {
+                        Some(Ok(Err(err))) => {
+                            log_backtrace(index, err);
+                            if let Some(dir) = &coredump_dir {
+                                write_coredump(index, dir, err, &mut store).await;
+                            }
+                        }
+                        Some(Err(msg)) => eprintln!("instance {index} panicked: {msg}"),
+                        _ => {}
+                    }
+                    if let (Some(profile), Some(profiler)) = (
+                        &guest_profile,
+                        profiler.and_then(|profiler| profiler.lock().unwrap().take()),
+                    ) {
+                        let path = profile.dir.join(format!("{index}.json"));
+                        if let Err(err) = profiler.finish(&path) {
+                            eprintln!("failed to write guest profile of instance {index}: {err:#}");
+                        } else {
                             eprintln!(
-                                "failed to read `{}` contents: {err}",
-                                pids_current_path.display()
-                            )
+                                "wrote guest profile of instance {index} to `{}`",
+                                path.display()
+                            );
                         }
                     }
+                    let panicked = matches!(res, Some(Err(..)));
+                    if (restart_rx.has_changed().unwrap_or(false) || panicked && restart_on_panic)
+                        && !*cancel_rx.borrow()
+                    {
+                        if restarts < max_restarts {
+                            restarts += 1;
+                            stats.restarts = restarts;
+                            top.restarted(index);
+                            eprintln!("restarting instance {index} ({restarts}/{max_restarts})");
+                            data = store.into_data();
+                            *WasiView::table(&mut data) = ResourceTable::new();
+                            data.limiter().reset();
+                            if let Some(health) = &health {
+                                health.reset(index);
+                            }
+                            continue;
+                        }
+                        eprintln!("instance {index} was restarted {restarts} times, stop");
+                    }
+                    if let Some(health) = &health {
+                        health.set_done(index);
+                    }
+                    return anyhow::Ok(match res {
+                        Some(Ok(res)) if !restart_rx.has_changed().unwrap_or(false) => {
+                            Outcome::new(res.context("failed to run component"))
+                        }
+                        Some(Err(msg)) => Outcome::Panicked(msg),
+                        // interrupted for a restart, which was not done
+                        _ => Outcome::Cancelled,
+                    });
                 }
-                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
-                Err(err) => {
-                    eprintln!(
-                        "failed to read `{}` contents: {err}",
-                        pids_max_path.display()
-                    )
+            };
+            let mut cancel = cancel_rx.clone();
+            let outcome = select! {
+                res = run => {
+                    if *cancel_rx.borrow() {
+                        Outcome::Cancelled
+                    } else {
+                        res.unwrap_or_else(|err| Outcome::new(Err(err)))
+                    }
                 }
-            }
-            count.saturating_div(4).max(1)
-        };
-        eprintln!(
-            "PID: {pid}, NOFILE: {nofile}, count: {count}, cgroup: {}",
-            cg.display()
-        );
+                Ok(_) = cancel.wait_for(|v| *v) => Outcome::Cancelled,
+            };
+            Span::current().record("outcome", outcome.status());
+            outcome
+        }
+        .instrument(span),
+    ))
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let Cli { command, args } = Cli::parse();
+
+    match command {
+        Some(Command::Inspect(args)) => {
+            init_tracing(None, None);
+            inspect::run(args)
+        }
+        Some(Command::Bench(args)) => bench::run(*args),
+        Some(Command::Diff(args)) => diff::run(*args),
+        Some(Command::Doctor(args)) => doctor::run(args),
+        Some(Command::Version(args)) => version::run(args),
+        None => run(args.context("missing run arguments")?),
+    }
+}
+
+/// Installs the global `tracing` subscriber, exporting spans via `otlp` if set
+/// and serving Tokio instrumentation for `tokio-console` on `console` if set
+fn init_tracing(otlp: Option<&otlp::Otlp>, console: Option<SocketAddr>) {
+    // the console layer consumes `tokio` trace-level events, so `RUST_LOG` only filters
+    // the log and OTLP layers
+    #[cfg(feature = "tokio-console")]
+    let console = console.map(|addr| {
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(addr)
+            .spawn()
+    });
+    #[cfg(not(feature = "tokio-console"))]
+    let console = console.map(|_| tracing_subscriber::layer::Identity::new());
+    tracing_subscriber::registry()
+        .with(console)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .and_then(
+                    otlp.map(|otlp| tracing_opentelemetry::layer().with_tracer(otlp.tracer())),
+                )
+                .with_filter(
+                    EnvFilter::builder()
+                        .with_default_directive(LevelFilter::INFO.into())
+                        .from_env_lossy(),
+                ),
+        )
+        .init();
+}
+
+/// Performs the process-wide setup shared by all runs: moves the process into a systemd
+/// scope, unshares the user namespace and installs the global `tracing` subscriber.
+///
+/// Must be called once, before any threads are spawned. The returned exporter, if any,
+/// flushes spans on drop
+pub fn setup(args: &Args) -> anyhow::Result<Option<otlp::Otlp>> {
+    let Args {
+        cgroup_driver,
+        dry_run,
+        otlp_endpoint,
+        #[cfg(feature = "tokio-console")]
+        tokio_console,
+        ..
+    } = args;
+
+    let pid = process::id();
+    if *cgroup_driver == systemd::CgroupDriver::Systemd && !*dry_run {
+        // D-Bus must be used before unsharing the user namespace for credentials to be valid
+        // and from a single thread for the unshare to succeed
+        let unit = format!("cgwasm-{pid}.scope");
+        tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .context("failed to build D-Bus Tokio runtime")?
+            .block_on(systemd::start_transient_scope(&unit, pid))?;
+        eprintln!("moved into `{unit}` systemd scope");
+    }
+
+    if !*dry_run {
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        unshare(CloneFlags::CLONE_NEWUSER).context("failed to unshare user namespace")?;
+        // map the IDs onto themselves for files created within the namespace to have an owner
+        std::fs::write("/proc/self/uid_map", format!("{uid} {uid} 1"))
+            .context("failed to write `/proc/self/uid_map`")?;
+        std::fs::write("/proc/self/setgroups", "deny")
+            .context("failed to write `/proc/self/setgroups`")?;
+        std::fs::write("/proc/self/gid_map", format!("{gid} {gid} 1"))
+            .context("failed to write `/proc/self/gid_map`")?;
+    }
+
+    // the exporter spawns threads, so it can only be started once the user namespace is unshared
+    let otlp = otlp_endpoint.as_deref().map(otlp::Otlp::new).transpose()?;
+    #[cfg(feature = "tokio-console")]
+    init_tracing(otlp.as_ref(), *tokio_console);
+    #[cfg(not(feature = "tokio-console"))]
+    init_tracing(otlp.as_ref(), None);
+    Ok(otlp)
+}
+
+/// Runs the component in sandboxes
+fn run(args: Args) -> anyhow::Result<ExitCode> {
+    let _otlp = setup(&args)?;
+    let outcomes = execute(args)?;
+    if outcomes.iter().all(|(outcome, _)| outcome.is_success()) {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Runs the component in sandboxes and returns outcomes and statistics of all instances
+pub fn execute(args: Args) -> anyhow::Result<Vec<(Outcome, Stats)>> {
+    let Args {
+        count,
+        wasm: wasm_path,
+        cgroup,
+        cgroup_driver,
+        cgroups,
+        reserve_cpus,
+        reserve_memory,
+        numa,
+        fail_fast,
+        dry_run,
+        report,
+        kv_backend,
+        kv_namespace,
+        pubsub_capacity,
+        messaging_backend,
+        messaging_queue_group,
+        blobstore_backend,
+        blobstore_max_object_size: mount::Size(blobstore_max_object_size),
+        #[cfg(feature = "wasi-nn")]
+        nn_model,
+        #[cfg(feature = "wasi-nn")]
+        nn_device,
+        guest_config,
+        guest_config_file,
+        clock,
+        clock_epoch,
+        random,
+        deterministic,
+        record,
+        replay,
+        allow_http_host,
+        deny_http_host,
+        http_timeout,
+        http_max_body_size,
+        http_proxy,
+        https_proxy,
+        no_proxy,
+        allow_net,
+        net_rate,
+        net_max_concurrent,
+        dns,
+        dns_hosts,
+        permission_profiles,
+        import_policy,
+        stub_missing_imports,
+        audit_log,
+        chaos,
+        io_max,
+        thread_stack_size,
+        nice,
+        sched_policy,
+        cpu_weight,
+        cpu_tier_policy,
+        control_cpu_weight,
+        pressure_interval,
+        pressure_threshold,
+        control,
+        control_file,
+        clean_stale_cgroups,
+        health_addr,
+        health_wedged_after,
+        watchdog_timeout,
+        max_restarts,
+        restart_on_panic,
+        shutdown_timeout,
+        tui,
+        state_dump,
+        cgroup_prefix,
+        cgroup_name,
+        stdin,
+        compose,
+        compose_config,
+        verify_signature,
+        signature,
+        invoke,
+        invoke_stdin,
+        instantiate_timeout,
+        instantiate_retries,
+        instantiate_backoff,
+        ramp_up_interval,
+        opt_level,
+        compiler,
+        parallel_compilation,
+        cranelift_flag,
+        wasm_features,
+        max_memory_size,
+        no_cow,
+        huge_pages,
+        memory_guaranteed_dense_image_size,
+        coredump_dir,
+        debug,
+        hostname,
+        keep_cap,
+        profile,
+        profile_dir,
+        otlp_endpoint: _,
+        #[cfg(feature = "tokio-console")]
+            tokio_console: _,
+        tmpfs,
+        tmpfs_dir,
+        ro_dir,
+        requests,
+    } = args;
+
+    let pid = process::id();
+    let max_memory_size = max_memory_size_or_default(max_memory_size);
+    let nofile = rlimit::Resource::NOFILE
+        .get_soft()
+        .context("failed to get `NOFILE` rlimit")?;
 
-        let controllers = fs::read_to_string(cg.join("cgroup.controllers"))
-            .await
-            .context("failed to read `cgroup.controllers`")?;
-        eprintln!("cgroup.controllers: {controllers}");
-        let controllers = controllers.split_whitespace().fold(
-            String::with_capacity("+cpuset +cpu +pids".len()),
-            |mut s, c| {
-                if c == "cpuset" {
-                    if s.is_empty() {
-                        s.push_str("+cpuset")
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .thread_name("cgwasm")
+        .build()
+        .context("failed to build root Tokio runtime")?;
+    let rt = rt.handle();
+    if invoke_stdin && wasm_path.is_stdin() {
+        bail!("`--invoke-stdin` cannot be used when reading the component from stdin");
+    }
+    let span = info_span!("cgwasm", wasm = %wasm_path);
+    rt.block_on(
+        async move {
+            let ((cg, cgroups), (wasm, provenance)) = try_join!(
+                async {
+                    if let Some(cgroup) = cgroup {
+                        return Ok((cgroup, cgroups));
+                    }
+                    let mounts = cgroup::Mounts::read().context("failed to read cgroup mounts")?;
+                    let legacy = mounts
+                        .legacy
+                        .iter()
+                        .map(|(_, controllers)| controllers.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let cg = async {
+                        let root = mounts
+                            .unified
+                            .as_deref()
+                            .context("cgroup v2 hierarchy is not mounted")?;
+                        let cg = fs::read_to_string("/proc/self/cgroup")
+                            .await
+                            .context("failed to read `/proc/self/cgroup`")?;
+                        let cg = cg
+                            .lines()
+                            .find_map(|line| line.strip_prefix("0::/"))
+                            .context("process does not run within cgroup v2")?;
+                        if !legacy.is_empty() {
+                            eprintln!(
+                                "hybrid cgroup hierarchy detected, controllers bound to cgroup v1 \
+                            are not available to sandbox cgroups: {legacy}"
+                            );
+                        }
+                        anyhow::Ok(root.join(cg))
+                    }
+                    .await;
+                    match cg {
+                        Ok(cg) => Ok((cg, cgroups)),
+                        Err(err) if cgroups == cgroup::Mode::Off => {
+                            eprintln!("failed to find cgroup of the process: {err:#}");
+                            Ok((PathBuf::from("/sys/fs/cgroup"), cgroups))
+                        }
+                        // degrade to running without per-instance cgroups on legacy hierarchies
+                        Err(err) if !legacy.is_empty() => {
+                            eprintln!(
+                                "{err:#}, legacy cgroup v1 hierarchy detected ({legacy}), \
+                            running without per-instance cgroups"
+                            );
+                            Ok((PathBuf::from("/sys/fs/cgroup"), cgroup::Mode::Off))
+                        }
+                        Err(err) => Err(err),
+                    }
+                },
+                async {
+                    let wasm = wasm_path.read().await?;
+                    let mut provenance = report
+                        .is_some()
+                        .then(|| provenance::Provenance::read(&wasm));
+                    let verifier = if let Some(path) = &verify_signature {
+                        let verifier = signature::Verifier::load(path).await?;
+                        let signature = signature
+                            .as_deref()
+                            .map(Path::to_path_buf)
+                            .or_else(|| wasm_path.signature_path())
+                            .context("`--signature` is required when reading the component from stdin or a file descriptor")?;
+                        verifier
+                            .verify(&wasm, &signature)
+                            .await
+                            .with_context(|| format!("failed to verify `{wasm_path}`"))?;
+                        eprintln!("verified signature `{}`", signature.display());
+                        Some(verifier)
+                    } else {
+                        None
+                    };
+                    let (wasm, composed) = if let Some(path) = &compose_config {
+                        compose::compose(wasm, path, verifier.as_ref()).await?
+                    } else if compose.is_empty() {
+                        (wasm, BTreeMap::new())
                     } else {
-                        s.push_str(" +cpuset")
+                        compose::plug(wasm, &compose, verifier.as_ref()).await?
+                    };
+                    if let Some(provenance) = &mut provenance {
+                        provenance.composed = composed;
                     }
-                } else if c == "cpu" {
-                    if s.is_empty() {
-                        s.push_str("+cpu")
+                    anyhow::Ok((wasm, provenance))
+                }
+            )?;
+
+            let count = if let Some(count) = count {
+                count.into()
+            } else {
+                let pids_current_path = cg.join("pids.current");
+                let pids_max_path = cg.join("pids.max");
+                let (threads_max, pids_current, pids_max) = join!(
+                    async {
+                        let threads_max = fs::read_to_string("/proc/sys/kernel/threads-max")
+                            .await
+                            .context("failed to read `/proc/sys/kernel/threads-max`")?;
+                        threads_max
+                            .trim()
+                            .parse::<usize>()
+                            .context("failed to parse `/proc/sys/kernel/threads-max` contents")
+                    },
+                    fs::read_to_string(&pids_current_path),
+                    fs::read_to_string(&pids_max_path),
+                );
+                let threads_max = threads_max?;
+                let nproc = rlimit::Resource::NPROC
+                    .get_soft()
+                    .context("failed to get `NPROC` rlimit")?;
+                eprintln!("threads-max: {threads_max}");
+                eprintln!("NPROC: {nproc}");
+                let mut count = threads_max
+                    .min(nproc.try_into().unwrap_or(usize::MAX))
+                    .min(nofile.try_into().unwrap_or(usize::MAX));
+                match pids_max.as_deref().map(str::trim) {
+                    Ok("max") => {
+                        eprintln!("pids.max: max");
+                    }
+                    Ok(pids_max) => {
+                        eprintln!("pids.max: {pids_max}");
+                        let pids_max = pids_max.parse::<usize>().with_context(|| {
+                            format!("failed to parse `{}` contents", pids_max_path.display())
+                        })?;
+                        match pids_current {
+                            Ok(pids_current) => {
+                                eprintln!("pids.current: {pids_current}");
+                                let pids_current =
+                                    pids_current.trim().parse::<usize>().with_context(|| {
+                                        format!(
+                                            "failed to parse `{}` contents",
+                                            pids_current_path.display()
+                                        )
+                                    })?;
+                                count = count.min(pids_max.saturating_sub(pids_current));
+                            }
+                            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                                count = count.min(pids_max);
+                            }
+                            Err(err) => {
+                                eprintln!(
+                                    "failed to read `{}` contents: {err}",
+                                    pids_current_path.display()
+                                )
+                            }
+                        }
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => {
+                        eprintln!(
+                            "failed to read `{}` contents: {err}",
+                            pids_max_path.display()
+                        )
+                    }
+                }
+                let count = count.saturating_div(4).max(1);
+
+                // every instance may grow its linear memory up to the maximum memory size
+                eprintln!("max memory size: {max_memory_size}");
+                let memory_max_path = cg.join("memory.max");
+                let memory_current_path = cg.join("memory.current");
+                let (meminfo, memory_max, memory_current) = join!(
+                    fs::read_to_string("/proc/meminfo"),
+                    fs::read_to_string(&memory_max_path),
+                    fs::read_to_string(&memory_current_path),
+                );
+                let mut available = match meminfo {
+                    Ok(meminfo) => meminfo
+                        .lines()
+                        .find_map(|line| line.strip_prefix("MemAvailable:"))
+                        .and_then(|kib| {
+                            kib.trim().trim_end_matches("kB").trim().parse::<u64>().ok()
+                        })
+                        .map(|kib| kib.saturating_mul(1024)),
+                    Err(err) => {
+                        eprintln!("failed to read `/proc/meminfo` contents: {err}");
+                        None
+                    }
+                };
+                if let Some(available) = available {
+                    eprintln!("MemAvailable: {available}");
+                }
+                match memory_max.as_deref().map(str::trim) {
+                    Ok("max") => {
+                        eprintln!("memory.max: max");
+                    }
+                    Ok(memory_max) => {
+                        eprintln!("memory.max: {memory_max}");
+                        let memory_max = memory_max.parse::<u64>().with_context(|| {
+                            format!("failed to parse `{}` contents", memory_max_path.display())
+                        })?;
+                        let memory_current = match memory_current {
+                            Ok(memory_current) => {
+                                eprintln!("memory.current: {}", memory_current.trim());
+                                memory_current.trim().parse::<u64>().with_context(|| {
+                                    format!(
+                                        "failed to parse `{}` contents",
+                                        memory_current_path.display()
+                                    )
+                                })?
+                            }
+                            Err(err) => {
+                                if err.kind() != std::io::ErrorKind::NotFound {
+                                    eprintln!(
+                                        "failed to read `{}` contents: {err}",
+                                        memory_current_path.display()
+                                    );
+                                }
+                                0
+                            }
+                        };
+                        let cg_available = memory_max.saturating_sub(memory_current);
+                        available = Some(available.map_or(cg_available, |v| v.min(cg_available)));
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => {
+                        eprintln!(
+                            "failed to read `{}` contents: {err}",
+                            memory_max_path.display()
+                        )
+                    }
+                }
+                if let (Some(available), Some(mount::Size(reserve))) = (&mut available, reserve_memory)
+                {
+                    *available = available.saturating_sub(reserve);
+                    eprintln!("available after reservation: {available}");
+                }
+                if let Some(available) = available {
+                    let max_memory_size = u64::try_from(max_memory_size).unwrap_or(u64::MAX).max(1);
+                    let fit = usize::try_from(available / max_memory_size).unwrap_or(usize::MAX);
+                    count.min(fit.max(1))
+                } else {
+                    count
+                }
+            };
+            eprintln!(
+                "PID: {pid}, NOFILE: {nofile}, count: {count}, cgroup: {}",
+                cg.display()
+            );
+
+            let mut engine_config = wasmtime::Config::default();
+            engine_config.wasm_component_model(true);
+            engine_config.async_support(true);
+            let threads = WasmFeature::enabled(&wasm_features, WasmProposal::Threads) == Some(true);
+            let pooling = matches!(use_pooling_allocator_by_default(), Ok(true));
+            if threads && pooling {
+                eprintln!("pooling allocator does not support shared memories of `threads`, fallback to on-demand allocator");
+            }
+            let explicit_huge_pages = huge_pages == hugepages::HugePages::Explicit;
+            if explicit_huge_pages && pooling && !threads {
+                eprintln!(
+                    "pooling allocator does not support explicit huge pages, \
+                fallback to on-demand allocator"
+                );
+            }
+            if huge_pages == hugepages::HugePages::Transparent && !hugepages::transparent_enabled() {
+                eprintln!(
+                    "transparent huge pages are disabled, `--huge-pages=transparent` has no effect"
+                );
+            }
+            // component instance slots of the pool, reported on `SIGUSR1`
+            let mut pool_slots = (pooling && !threads && !explicit_huge_pages).then(|| {
+                let slots = u32::try_from(count)
+                    .unwrap_or(u32::MAX)
+                    .saturating_mul(POOLING_SLOTS_PER_INSTANCE);
+                getenv("WASMTIME_POOLING_TOTAL_COMPONENT_INSTANCES").unwrap_or(slots)
+            });
+            // reservations exceeding the address space fail with `mmap` errors on engine
+            // construction and fewer slots than the instances need exhaust the pool,
+            // so the on-demand allocator is used unless the number was set explicitly
+            if let Some(slots) = pool_slots {
+                let budget = addrspace::Budget::read();
+                if let Some(limit) = budget.exceeded(slots, max_memory_size) {
+                    if getenv::<u32>("WASMTIME_POOLING_TOTAL_COMPONENT_INSTANCES").is_some() {
+                        eprintln!("pooling allocator reservation of {slots} slots exceeds {limit}");
                     } else {
-                        s.push_str(" +cpu")
+                        let fit = budget.fit(slots, max_memory_size);
+                        eprintln!(
+                            "pooling allocator reservation of {slots} slots exceeds {limit}, \
+                        only {fit} slots fit for {count} instances, fallback to on-demand allocator"
+                        );
+                        pool_slots = None;
                     }
-                } else if c == "pids" {
-                    if s.is_empty() {
-                        s.push_str("+pids")
+                }
+            }
+            let pooling_config = pool_slots.map(|slots| {
+                let mut config = new_pooling_config(slots, max_memory_size);
+                if WasmFeature::enabled(&wasm_features, WasmProposal::MultiMemory) == Some(true)
+                    && getenv::<u32>("WASMTIME_POOLING_MAX_MEMORIES_PER_MODULE").is_none()
+                {
+                    config.max_memories_per_module(MULTI_MEMORY_MAX_MEMORIES);
+                }
+                config
+            });
+            if let Some(config) = &pooling_config {
+                engine_config
+                    .allocation_strategy(InstanceAllocationStrategy::Pooling(config.clone()));
+            } else {
+                engine_config.allocation_strategy(InstanceAllocationStrategy::OnDemand);
+            }
+            // linear memories of the on-demand allocator are backed by huge pages on creation,
+            // memory images are only mapped by the memory creator of `wasmtime` though
+            let host_memory = |engine_config: &mut wasmtime::Config| {
+                engine_config.with_host_memory(Arc::new(hugepages::Creator::new(huge_pages)));
+                if !no_cow {
+                    eprintln!("huge pages disable copy-on-write initialization of linear memories");
+                }
+            };
+            if huge_pages != hugepages::HugePages::Off && pooling_config.is_none() {
+                host_memory(&mut engine_config);
+            }
+            if deterministic {
+                engine_config.cranelift_nan_canonicalization(true);
+            }
+            let profile_interval = match profile {
+                Some(profile::ProfileConfig::Guest(interval)) => Some(interval),
+                _ => None,
+            };
+            if let Some(profile) = profile {
+                engine_config.profiler(profile.strategy());
+            }
+            // used to interrupt running instances on cancellation and to sample guest stacks
+            engine_config.epoch_interruption(true);
+            if let Some(v) = getenv("WASMTIME_DEBUG_INFO") {
+                engine_config.debug_info(v);
+                if v {
+                    // resolve source locations of backtrace frames using DWARF
+                    engine_config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+                }
+            }
+            if let Some(v) = getenv("WASMTIME_MAX_WASM_STACK") {
+                engine_config.max_wasm_stack(v);
+            }
+            if let Some(v) = getenv("WASMTIME_ASYNC_STACK_SIZE") {
+                engine_config.async_stack_size(v);
+            }
+            engine_config.strategy(compiler.into());
+            engine_config.memory_init_cow(!no_cow);
+            if coredump_dir.is_some() {
+                engine_config.coredump_on_trap(true);
+            }
+            if let Some(size) = memory_guaranteed_dense_image_size {
+                engine_config.memory_guaranteed_dense_image_size(size);
+            }
+            if debug {
+                engine_config.debug_info(true);
+                engine_config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+                engine_config.cranelift_opt_level(wasmtime::OptLevel::None);
+            }
+            if let Some(level) = opt_level {
+                engine_config.cranelift_opt_level(level.into());
+            }
+            if let Some(v) = parallel_compilation {
+                engine_config.parallel_compilation(v);
+            }
+            for feature in &wasm_features {
+                feature.configure(&mut engine_config);
+            }
+            for CraneliftFlag { name, value } in &cranelift_flag {
+                unsafe {
+                    if let Some(value) = value {
+                        engine_config.cranelift_flag_set(name, value);
                     } else {
-                        s.push_str(" +pids")
+                        engine_config.cranelift_flag_enable(name);
                     }
                 }
-                s
-            },
-        );
-        fs::write(cg.join("cgroup.subtree_control"), &controllers)
-            .await
-            .context("failed to enable threaded controllers in `cgwasm` cgroup")?;
-
-        let cg = cg.join("cgwasm");
-        fs::create_dir_all(&cg)
-            .await
-            .context("failed to create `cgwasm` cgroup")?;
-        fs::write(cg.join("cgroup.type"), "threaded")
-            .await
-            .context("failed to set `cgwasm` group type to `threaded`")?;
-        fs::write(cg.join("cgroup.procs"), pid.to_string())
-            .await
-            .context("failed to add PID to `cgwasm` cgroup")?;
-        fs::write(cg.join("cgroup.subtree_control"), &controllers)
-            .await
-            .context("failed to enable threaded controllers in `cgwasm` cgroup")?;
-
-        let mut engine_config = wasmtime::Config::default();
-        engine_config.wasm_component_model(true);
-        engine_config.async_support(true);
-        if let Ok(true) = use_pooling_allocator_by_default() {
-            engine_config.allocation_strategy(InstanceAllocationStrategy::Pooling(
-                new_pooling_config(count.saturating_mul(4).try_into().unwrap_or(u32::MAX)),
-            ));
-        } else {
-            engine_config.allocation_strategy(InstanceAllocationStrategy::OnDemand);
-        }
-        if let Some(v) = getenv("WASMTIME_DEBUG_INFO") {
-            engine_config.debug_info(v);
-        }
-        if let Some(v) = getenv("WASMTIME_MAX_WASM_STACK") {
-            engine_config.max_wasm_stack(v);
-        }
-        if let Some(v) = getenv("WASMTIME_ASYNC_STACK_SIZE") {
-            engine_config.async_stack_size(v);
-        }
-        let engine =
-            match wasmtime::Engine::new(&engine_config).context("failed to construct engine") {
-                Ok(engine) => engine,
+            }
+            // the pool is reserved during engine construction
+            let mappings = (pool_slots.is_some() && huge_pages == hugepages::HugePages::Transparent)
+                .then(hugepages::anonymous_mappings)
+                .transpose()
+                .context("failed to read mappings of the process")?;
+            let engine = match wasmtime::Engine::new(&engine_config)
+                .context("failed to construct engine")
+            {
+                Ok(engine) => {
+                    if let Some(mappings) = mappings {
+                        match hugepages::advise_pool(&mappings) {
+                            Ok(n) => eprintln!(
+                                "advised transparent huge pages for {n} bytes of linear memory slots"
+                            ),
+                            Err(err) => eprintln!(
+                                "failed to advise transparent huge pages for the pool: {err:#}"
+                            ),
+                        }
+                    }
+                    engine
+                }
                 Err(err) => {
                     eprintln!("failed to construct engine, fallback to on-demand allocator: {err}");
                     engine_config.allocation_strategy(InstanceAllocationStrategy::OnDemand);
+                    if huge_pages != hugepages::HugePages::Off && pool_slots.is_some() {
+                        host_memory(&mut engine_config);
+                    }
+                    pool_slots = None;
                     wasmtime::Engine::new(&engine_config).context("failed to construct engine")?
                 }
             };
 
-        let cg: Arc<Path> = cg.into_boxed_path().into();
-        let (wasm_tx, _) = broadcast::channel(1);
-        let mut tasks = Vec::with_capacity(count);
-        for i in 0..count {
-            let name = format!("cgwasm_sandbox_{i}");
-            let engine = engine.clone();
-            let cg = cg.join(&name);
-            let mut wasm_rx = wasm_tx.subscribe();
-            let (done_tx, done_rx) = oneshot::channel();
-            let Ok(task) = thread::Builder::new().name(name.clone()).spawn({
-                let name = name.clone();
-                move || {
-                    let tid = unsafe { libc::gettid() };
-                    std::fs::create_dir_all(&cg)
-                        .with_context(|| format!("failed to create `{name}` cgroup"))?;
-                    let path = cg.join("cgroup.type");
-                    std::fs::write(&path, b"threaded").with_context(|| {
-                        format!("failed to write `threaded` to `{}`", path.display())
-                    })?;
-                    let path = cg.join("cgroup.threads");
-                    std::fs::write(&path, tid.to_string()).with_context(|| {
-                        format!("failed to write `{tid}` to `{}`", path.display())
-                    })?;
-                    unshare(
-                        CloneFlags::CLONE_NEWIPC
-                            | CloneFlags::CLONE_NEWNET
-                            | CloneFlags::CLONE_NEWNS
-                            | CloneFlags::CLONE_NEWUTS,
-                    )
-                    .context("failed to unshare thread")?;
-                    // TODO: `pivot_root` etc.
-                    let rt = tokio::runtime::Builder::new_current_thread()
-                        .enable_io()
-                        .enable_time()
-                        .thread_name(name.clone())
-                        .build()
-                        .with_context(|| format!("failed to build runtime for sandbox {name}"))?;
-
-                    let res = rt.block_on(async {
-                        let wasm: CommandPre<Ctx> =
-                            wasm_rx.recv().await.context("Wasm sender closed")?;
-                        let mut store = Store::new(
-                            &engine,
-                            Ctx {
-                                wasi: WasiCtxBuilder::new()
-                                    .inherit_env()
-                                    .inherit_stdio()
-                                    .inherit_network()
-                                    .allow_ip_name_lookup(true)
-                                    .allow_tcp(true)
-                                    .allow_udp(true)
-                                    .args(&["main.wasm".to_string()])
-                                    .build(),
-                                http: WasiHttpCtx::new(),
-                                table: ResourceTable::new(),
-                            },
-                        );
-                        let wasm = wasm
-                            .instantiate_async(&mut store)
-                            .await
-                            .context("failed to instantiate the component")?;
-                        let res = wasm
-                            .wasi_cli_run()
-                            .call_run(&mut store)
-                            .await
-                            .context("failed to run component")?;
-                        anyhow::Ok(res)
-                    });
-                    done_tx
-                        .send(())
-                        .map_err(|_| anyhow!("done receiver closed"))?;
-                    anyhow::Ok(res)
-                }
-            }) else {
-                eprintln!("failed to create thread {i}, stop");
-                break;
+            let import_policy = if let Some(path) = &import_policy {
+                Some(imports::ImportPolicy::load(path).await?)
+            } else {
+                None
             };
-            tasks.push(rt.spawn(async move {
-                _ = done_rx.await;
-                eprintln!("joining thread...");
-                let res = task
-                    .join()
-                    .map_err(|_| anyhow!("thread panicked"))?
-                    .context("thread failed")?;
-                eprintln!("task completed: {res:?}");
-                anyhow::Ok(())
-            }));
-        }
-        let component = Component::new(&engine, wasm).context("failed to compile component")?;
-
-        let mut linker = Linker::new(&engine);
-        wasmtime_wasi::add_to_linker_async(&mut linker).context("failed to link WASI")?;
-        wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)
-            .context("failed to link `wasi:http`")?;
-        let pre = linker
-            .instantiate_pre(&component)
-            .context("failed to pre-instantiate component")?;
-        let pre = CommandPre::new(pre).context("component does not export `wasi:cli/command`")?;
-        wasm_tx
-            .send(pre)
-            .map_err(|_| anyhow!("Wasm receiver closed"))?;
-        for task in tasks {
-            eprintln!("joining task...");
-            task.await.context("task panicked")??
+            let component = info_span!("compile")
+                .in_scope(|| Component::new(&engine, wasm))
+                .context("failed to compile component")?;
+            if let Some(policy) = &import_policy {
+                policy
+                    .check(&engine, &component)
+                    .context("invalid component")?;
+            }
+
+            let mut linker = new_linker(&engine)?;
+            if let Some(mode) = stub_missing_imports {
+                for name in validate::stub_missing(&mut linker, &component, mode)? {
+                    eprintln!("stubbed import `{name}` not provided by the host");
+                }
+            }
+            validate::validate(&linker, &component, invoke.is_none())
+                .context("invalid component")?;
+            let pre = info_span!("pre_instantiate").in_scope(|| {
+                let pre = linker
+                    .instantiate_pre(&component)
+                    .context("failed to pre-instantiate component")?;
+                if invoke.is_none() {
+                    CommandPre::new(pre.clone())
+                        .context("component does not export `wasi:cli/command`")?;
+                }
+                anyhow::Ok(pre)
+            })?;
+
+            if cgroup_prefix.is_per_instance() {
+                bail!("`--cgroup-prefix` must not contain `{{index}}`");
+            }
+            if count > 1 && !cgroup_name.is_per_instance() {
+                bail!("`--cgroup-name` must contain `{{index}}` when running multiple instances");
+            }
+            let (clock, clock_epoch) = if deterministic {
+                let clock = clock.unwrap_or(clocks::ClockConfig::Step(Duration::from_millis(1)));
+                if !clock.is_deterministic() {
+                    bail!("`--clock={clock}` cannot be used with `--deterministic`");
+                }
+                (
+                    clock,
+                    clock_epoch.map_or(Duration::ZERO, Duration::from_secs),
+                )
+            } else {
+                (
+                    clock.unwrap_or_default(),
+                    clock_epoch.map_or_else(clocks::now, Duration::from_secs),
+                )
+            };
+            let random = if deterministic {
+                let random = random.unwrap_or(random::RandomConfig::Seed(0));
+                if !random.is_deterministic() {
+                    bail!("`--random={random}` cannot be used with `--deterministic`");
+                }
+                random
+            } else {
+                random.unwrap_or_default()
+            };
+            let component_name = wasm_path.name();
+            let cgroup_enabled = cgroups == cgroup::Mode::On;
+            if !cgroup_enabled {
+                if !io_max.is_empty() {
+                    bail!("`--io-max` cannot be used with `--cgroups=off`");
+                }
+                if reserve_cpus.is_some() {
+                    bail!("`--reserve-cpus` cannot be used with `--cgroups=off`");
+                }
+                if numa.is_some() {
+                    bail!("`--numa` cannot be used with `--cgroups=off`");
+                }
+                if !cpu_weight.is_empty() {
+                    bail!("`--cpu-weight` cannot be used with `--cgroups=off`");
+                }
+                if reserve_memory.is_some() {
+                    eprintln!(
+                        "per-instance cgroups disabled, `--reserve-memory` only limits the instance count"
+                    );
+                }
+                if pressure_interval.is_some() || pressure_threshold.is_some() {
+                    bail!("pressure monitoring cannot be used with `--cgroups=off`");
+                }
+            }
+            // threaded controllers and those among them to be enabled by us
+            let cgroup_controllers = if cgroup_enabled {
+                let controllers = fs::read_to_string(cg.join("cgroup.controllers"))
+                    .await
+                    .context("failed to read `cgroup.controllers`")?;
+                eprintln!("cgroup.controllers: {controllers}");
+                let subtree_control = fs::read_to_string(cg.join("cgroup.subtree_control"))
+                    .await
+                    .context("failed to read `cgroup.subtree_control`")?;
+                let controllers = controllers.split_whitespace().fold(
+                    String::with_capacity("+cpuset +cpu +pids".len()),
+                    |mut s, c| {
+                        if c == "cpuset" {
+                            if s.is_empty() {
+                                s.push_str("+cpuset")
+                            } else {
+                                s.push_str(" +cpuset")
+                            }
+                        } else if c == "cpu" {
+                            if s.is_empty() {
+                                s.push_str("+cpu")
+                            } else {
+                                s.push_str(" +cpu")
+                            }
+                        } else if c == "pids" {
+                            if s.is_empty() {
+                                s.push_str("+pids")
+                            } else {
+                                s.push_str(" +pids")
+                            }
+                        }
+                        s
+                    },
+                );
+                // controllers enabled by us, to be disabled again on exit
+                let enabled = controllers
+                    .split_whitespace()
+                    .filter(|c| {
+                        let c = c.trim_start_matches('+');
+                        !subtree_control.split_whitespace().any(|sc| sc == c)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if reserve_cpus.is_some() && !controllers.contains("cpuset") {
+                    bail!("`--reserve-cpus` requires the `cpuset` controller");
+                }
+                if numa.is_some() && !controllers.contains("cpuset") {
+                    bail!("`--numa` requires the `cpuset` controller");
+                }
+                Some((controllers, enabled))
+            } else {
+                None
+            };
+            if dry_run {
+                let prefix = cgroup_prefix.render(component_name, 0);
+                let scope = format!("cgwasm-{pid}.scope");
+                plan::Plan {
+                    wasm: &wasm_path,
+                    component: component_name,
+                    count,
+                    pooling: pooling_config.as_ref(),
+                    scope: (cgroup_driver == systemd::CgroupDriver::Systemd)
+                        .then_some(scope.as_str()),
+                    cgroup: cgroup_controllers.as_ref().map(|(controllers, enabled)| {
+                        plan::CgroupPlan {
+                            parent: &cg,
+                            prefix: &prefix,
+                            controllers,
+                            enabled,
+                            control_cpu_weight,
+                            name: &cgroup_name,
+                        }
+                    }),
+                    capabilities: plan::Capabilities {
+                        allow_http_host: &allow_http_host,
+                        deny_http_host: &deny_http_host,
+                        allow_net: &allow_net,
+                        permission_profiles: permission_profiles.as_deref(),
+                        stdin: &stdin,
+                        tmpfs: tmpfs.as_ref().map(|size| (size, tmpfs_dir.as_str())),
+                        ro_dir: &ro_dir,
+                        keep_cap: &keep_cap,
+                    },
+                }
+                .print();
+                return Ok(Vec::new());
+            }
+            // undone on exit, including early returns on errors
+            let (cg, controllers, mut setup) = if let Some((controllers, enabled)) = cgroup_controllers
+            {
+                if let Err(err) = fs::write(cg.join("cgroup.subtree_control"), &controllers).await {
+                    if err.kind() == std::io::ErrorKind::PermissionDenied {
+                        return Err(err).with_context(|| {
+                            format!(
+                                "cgroup `{}` is not delegated to the current user, \
+                            run via `--cgroup-driver systemd`, pass a delegated cgroup \
+                            via `--cgroup`, run as root or disable per-instance cgroups \
+                            via `--cgroups off`",
+                                cg.display()
+                            )
+                        });
+                    }
+                    return Err(err)
+                        .context("failed to enable threaded controllers in `cgwasm` cgroup");
+                }
+
+                let prefix = cgroup_prefix.render(component_name, 0);
+                let mut setup = cgroup::Setup {
+                    parent: cg.clone(),
+                    prefix: prefix.clone(),
+                    pid,
+                    enabled,
+                    names: Vec::new(),
+                    restore: cgroup::Restore::default(),
+                };
+                let parent = cg;
+                let cg = cgroup::create_threaded(&parent, &prefix, &controllers)?;
+                fs::write(cg.join("cgroup.procs"), pid.to_string())
+                    .await
+                    .with_context(|| format!("failed to add PID to `{prefix}` cgroup"))?;
+                let stale = cgroup::find_stale(&cg).with_context(|| {
+                    format!("failed to find stale cgroups in `{}`", cg.display())
+                })?;
+                if clean_stale_cgroups {
+                    for path in &stale {
+                        eprintln!("removing stale cgroup `{}`", path.display());
+                        cgroup::remove(path);
+                    }
+                } else if !stale.is_empty() {
+                    eprintln!(
+                    "found {} stale cgroups in `{}`, use `--clean-stale-cgroups` to remove them",
+                    stale.len(),
+                    cg.display()
+                );
+                }
+                if let Some(n) = reserve_cpus {
+                    let cpus = cgroup::reserve_cpus(&cg, n.into())
+                        .context("failed to reserve CPUs")?;
+                    eprintln!("reserved {n} CPUs, sandboxes run on CPUs {cpus}");
+                }
+                if let Some(mount::Size(bytes)) = reserve_memory {
+                    let max = cgroup::reserve_memory(&parent, bytes, &mut setup.restore)
+                        .context("failed to reserve memory")?;
+                    eprintln!(
+                        "reserved {bytes} bytes of memory, `memory.max` of `{}`: {max}",
+                        parent.display()
+                    );
+                }
+                if !io_max.is_empty() {
+                    cgroup::apply_io_max(&parent, &io_max, &mut setup.restore)
+                        .context("failed to apply `--io-max`")?;
+                }
+                cgroup::isolate_control(&cg, control_cpu_weight)
+                    .context("failed to move supervisor threads to control cgroup")?;
+                (cg, controllers, Some(setup))
+            } else {
+                eprintln!("per-instance cgroups disabled, resource limits are not applied");
+                (cg, String::new(), None)
+            };
+
+            let kv = <dyn keyvalue::Backend>::new(&kv_backend)?;
+            let broker = Arc::new(pubsub::Broker::new(pubsub_capacity.into()));
+            let blobs = blobstore_backend
+                .as_ref()
+                .map(blobstore::Store::new)
+                .transpose()?
+                .map(Arc::new);
+            #[cfg(feature = "wasi-nn")]
+            let graphs = nn::Graphs::load(&nn_model, nn_device)?;
+            let nats = if let Some(config) = &messaging_backend {
+                Some(messaging::connect(config).await?)
+            } else {
+                None
+            };
+            let mut config = if let Some(path) = guest_config_file {
+                config::load(&path).await?
+            } else {
+                BTreeMap::default()
+            };
+            config.extend(guest_config);
+            let config = ConfigCtx::new(config);
+            let (turn_tx, _) = watch::channel(Turns::default());
+            let http_policy = Arc::new(HttpPolicy {
+                allow: allow_http_host,
+                deny: deny_http_host,
+                timeout: http_timeout,
+                max_body_size: http_max_body_size,
+                proxy: proxy::Proxy {
+                    http: http_proxy,
+                    https: https_proxy,
+                    no_proxy,
+                }
+                .with_env()?,
+                rt: tokio::runtime::Handle::current(),
+            });
+            let net_policy = NetPolicy { allow: allow_net };
+            let no_http_policy = Arc::new(HttpPolicy::deny_all());
+            let audit_log = audit_log.as_deref().map(audit::Log::create).transpose()?;
+            let chaos: Arc<[chaos::Rule]> = chaos.into();
+            let mut trace = match (record, replay) {
+                (Some(file), _) => Some((file.index, trace::Trace::record(&file)?)),
+                (_, Some(file)) => Some((file.index, trace::Trace::replay(&file)?)),
+                (None, None) => None,
+            };
+            let resolver = dns::Resolver::load(&dns, &dns_hosts).await?;
+            let profiles = if let Some(path) = permission_profiles {
+                permissions::Profiles::load(&path).await?
+            } else {
+                permissions::Profiles::default()
+            };
+            let limits = Arc::new(cgroup::Limits {
+                cpu_tiers: cgroup::CpuTiers {
+                    tiers: cpu_weight,
+                    policy: cpu_tier_policy,
+                    count,
+                },
+                numa: numa
+                    .map(|config| numa::Numa::new(config, &cg))
+                    .transpose()
+                    .context("failed to resolve NUMA placement")?
+                    .flatten(),
+            });
+            let instantiate = Instantiate {
+                timeout: instantiate_timeout,
+                retries: instantiate_retries,
+                backoff: instantiate_backoff,
+            };
+            let (throttle_tx, throttle_rx) = watch::channel(false);
+            let stdin = match stdin {
+                // host stdin is consumed by calls
+                stdin::StdinConfig::Inherit if invoke_stdin || wasm_path.is_stdin() => {
+                    stdin::StdinConfig::Null
+                }
+                stdin => stdin,
+            };
+            let stdin = stdin::Stdin::new(stdin).await?;
+            let requests = requests.or_else(|| invoke_stdin.then(|| invoke::Requests::stdin(count)));
+            let guest_profile = if let Some(interval) = profile_interval {
+                fs::create_dir_all(&profile_dir)
+                    .await
+                    .with_context(|| format!("failed to create `{}`", profile_dir.display()))?;
+                Some(profile::GuestProfile {
+                    interval,
+                    dir: profile_dir.into_boxed_path().into(),
+                })
+            } else {
+                None
+            };
+            let coredump_dir: Option<Arc<Path>> = if let Some(dir) = coredump_dir {
+                fs::create_dir_all(&dir)
+                    .await
+                    .with_context(|| format!("failed to create `{}`", dir.display()))?;
+                Some(dir.into_boxed_path().into())
+            } else {
+                None
+            };
+            let control = if let Some(path) = control {
+                let listener = control::Control::bind(&path)?;
+                Some((path, listener))
+            } else {
+                None
+            };
+            // progress of instances is tracked for both health checks and the watchdog
+            let health = (health_addr.is_some() || watchdog_timeout.is_some())
+                .then(|| Arc::new(health::Health::new(count, health_wedged_after)));
+            let health_server = if let (Some(addr), Some(health)) = (health_addr, &health) {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("failed to bind health check server to `{addr}`"))?;
+                Some(rt.spawn(Arc::clone(health).serve(listener)))
+            } else {
+                None
+            };
+
+            let cg: Arc<Path> = cg.into_boxed_path().into();
+            let oom_kill = report::read_oom_kill(&cg);
+            let top = Arc::new(top::Top::new(count));
+            let usage = Arc::new(usage::Usage::new(count));
+            let (wasm_tx, _) = broadcast::channel(1);
+            let cancel = Arc::new(cancel::Cancel::new(engine.clone(), count));
+            let keep_caps: Arc<[caps::Cap]> = keep_cap.into();
+            let mounts = Arc::new(mount::Mounts::new(
+                tmpfs.map(|size| (size, tmpfs_dir)),
+                ro_dir,
+            )?);
+            let mut tasks = Vec::with_capacity(count);
+            let started = Instant::now();
+            for i in 0..count {
+                let name = cgroup_name.render(component_name, i);
+                if let Some((dir, _)) = name.rsplit_once('/').filter(|_| cgroup_enabled) {
+                    if let Err(err) = cgroup::create_threaded(&cg, dir, &controllers) {
+                        eprintln!("failed to create cgroup for instance {i}, stop: {err:#}");
+                        break;
+                    }
+                }
+                let engine = engine.clone();
+                let wasm_rx = wasm_tx.subscribe();
+                let shutdown_rx = cancel.subscribe(i);
+                // sandboxes are interrupted once the grace period after shutdown request elapsed
+                let cancel_rx = if let Some(timeout) = shutdown_timeout {
+                    let (interrupt_tx, interrupt_rx) = watch::channel(false);
+                    let mut shutdown_rx = shutdown_rx.clone();
+                    let engine = engine.clone();
+                    rt.spawn(async move {
+                        if shutdown_rx.wait_for(|v| *v).await.is_ok() {
+                            tokio::time::sleep(timeout).await;
+                            interrupt_tx.send_replace(true);
+                            engine.increment_epoch();
+                        }
+                    });
+                    interrupt_rx
+                } else {
+                    shutdown_rx.clone()
+                };
+                let profile = profiles.get(i);
+                let mut wasi = WasiCtxBuilder::new();
+                if profile.is_none_or(|profile| profile.inherit_env) {
+                    wasi.inherit_env();
+                }
+                wasi.inherit_stdout()
+                    .inherit_stderr()
+                    .allow_ip_name_lookup(true)
+                    .args(&["main.wasm".to_string()]);
+                let audit = audit_log
+                    .as_ref()
+                    .map(|log| audit::Audit::new(Arc::clone(log), i));
+                let net_limiter = (net_rate.is_some() || net_max_concurrent.is_some())
+                    .then(|| Arc::new(ratelimit::NetLimiter::new(net_rate, net_max_concurrent)));
+                let chaos = (!chaos.is_empty()).then(|| {
+                    Arc::new(chaos::Chaos::new(Arc::clone(&chaos), random.seed(i), i))
+                });
+                let profile_net_policy = profile
+                    .and_then(|profile| profile.allow_net.clone())
+                    .map(|allow| NetPolicy { allow });
+                profile_net_policy.as_ref().unwrap_or(&net_policy).configure(
+                    i,
+                    &mut wasi,
+                    audit.clone(),
+                    net_limiter.clone(),
+                    chaos.clone(),
+                );
+                // guests size thread pools by the CPUs of their sandbox rather than of the host,
+                // its cgroup does not exist yet, so the limits applied to it are considered
+                // in addition to ones of the domain cgroup
+                let cpus = cgroup::cpu_limit(&cg)
+                    .into_iter()
+                    .chain(limits.cpus(i))
+                    .chain(thread::available_parallelism().ok().map(usize::from))
+                    .min()
+                    .unwrap_or(1);
+                wasi.env("CGWASM_CPUS", cpus.to_string());
+                if let Err(err) = profile.map(|profile| profile.configure(&mut wasi)).transpose() {
+                    eprintln!("failed to apply permission profile of instance {i}, stop: {err:#}");
+                    break;
+                }
+                if let Err(err) = stdin.configure(i, &mut wasi).await {
+                    eprintln!("failed to configure stdin for instance {i}, stop: {err:#}");
+                    break;
+                }
+                clock.configure(clock_epoch, &mut wasi);
+                random.configure(i, &mut wasi);
+                let trace = trace.take_if(|(index, _)| *index == i).map(|(_, trace)| {
+                    trace.configure(clock, clock_epoch, random.seed(i), &mut wasi);
+                    trace
+                });
+                let turn = deterministic.then(|| Turn::new(turn_tx.clone(), i));
+                let http_policy = if profile.is_some_and(|profile| !profile.network) {
+                    Arc::clone(&no_http_policy)
+                } else {
+                    Arc::clone(&http_policy)
+                };
+                let keyvalue = KeyValueCtx::new(Arc::clone(&kv), kv_namespace, i);
+                let config = config.clone();
+                let hostname = hostname.render(component_name, i);
+                let instance = InstanceCtx::new(
+                    i,
+                    count,
+                    cgroup_enabled.then(|| cg.join(&name).display().to_string()),
+                    hostname.clone(),
+                    cpus,
+                    shutdown_rx,
+                );
+                let pubsub = PubsubCtx::new(Arc::clone(&broker), i);
+                let messaging = MessagingCtx::new(nats.clone(), messaging_queue_group.clone());
+                let blobstore = BlobstoreCtx::new(
+                    blobs.clone(),
+                    usize::try_from(blobstore_max_object_size).unwrap_or(usize::MAX),
+                );
+                #[cfg(feature = "wasi-nn")]
+                let nn = graphs.ctx();
+                let dns = dns::Lookup::new(Arc::clone(&resolver), i);
+                let limiter = usage::Limiter::new(Arc::clone(&usage), i);
+                let ctx = move |wasi| Ctx {
+                    wasi,
+                    http: WasiHttpCtx::new(),
+                    http_policy,
+                    keyvalue,
+                    config,
+                    logging: LoggingCtx::new(i),
+                    instance,
+                    pubsub,
+                    messaging,
+                    blobstore,
+                    #[cfg(feature = "wasi-nn")]
+                    nn,
+                    audit,
+                    chaos,
+                    trace,
+                    dns,
+                    net_limiter,
+                    limiter,
+                    table: ResourceTable::new(),
+                };
+                let (done_tx, done_rx) = oneshot::channel();
+                let sandbox = Sandbox {
+                    index: i,
+                    name: name.clone(),
+                    engine: engine.clone(),
+                    wasm_rx,
+                    invoke: invoke.clone(),
+                    requests: requests.clone(),
+                    cancel_rx,
+                    restart_rx: cancel.subscribe_restart(i),
+                    max_restarts,
+                    restart_on_panic,
+                    turn,
+                    throttle_rx: throttle_rx.clone(),
+                    limits: Arc::clone(&limits),
+                    health: health.clone(),
+                    top: Arc::clone(&top),
+                    instantiate,
+                    start_at: ramp_up_interval.and_then(|interval| {
+                        started
+                            .checked_add(interval.saturating_mul(i.try_into().unwrap_or(u32::MAX)))
+                    }),
+                    coredump_dir: coredump_dir.clone(),
+                    debug,
+                    guest_profile: guest_profile.clone(),
+                    max_memory_size,
+                    instances: count,
+                    hostname,
+                    keep_caps: Arc::clone(&keep_caps),
+                    cgroup: cgroup_enabled,
+                    mounts: Arc::clone(&mounts),
+                    sched: sched::Sched {
+                        nice,
+                        policy: sched_policy,
+                    },
+                    span: info_span!(
+                        "instance",
+                        index = i,
+                        name = name.as_str(),
+                        outcome = tracing::field::Empty
+                    ),
+                };
+                let mut thread = thread::Builder::new().name(name.clone());
+                if let Some(size) = thread_stack_size {
+                    thread = thread.stack_size(size);
+                }
+                let Ok(task) = thread.spawn({
+                    let cg = cg.join(&name);
+                    move || {
+                        let mut stats = Stats::new(cg);
+                        // a panic in host code must not take down the statistics of the instance
+                        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                            run_sandbox(sandbox, wasi, ctx, &mut stats)
+                        }))
+                        .unwrap_or_else(|payload| Ok(Outcome::Panicked(panic_message(&*payload))));
+                        stats.finish();
+                        _ = done_tx.send(());
+                        (res, stats)
+                    }
+                }) else {
+                    eprintln!("failed to create thread {i}, stop");
+                    break;
+                };
+                let cancel = Arc::clone(&cancel);
+                let cg = Arc::clone(&cg);
+                let top = Arc::clone(&top);
+                let health = health.clone();
+                tasks.push(rt.spawn(async move {
+                    _ = done_rx.await;
+                    eprintln!("joining thread...");
+                    let (outcome, stats) = match task.join() {
+                        Ok((Ok(outcome), stats)) => (outcome, stats),
+                        Ok((Err(err), stats)) => {
+                            (Outcome::Error(err.context("thread failed")), stats)
+                        }
+                        Err(payload) => (
+                            Outcome::Panicked(panic_message(&*payload)),
+                            Stats::new(cg.join(&name)),
+                        ),
+                    };
+                    let outcome = if matches!(outcome, Outcome::Cancelled)
+                        && health.is_some_and(|health| health.is_interrupted(i))
+                    {
+                        Outcome::Wedged
+                    } else {
+                        outcome
+                    };
+                    eprintln!("instance {i} completed: {outcome}");
+                    top.set_done(i, &outcome);
+                    if fail_fast && !outcome.is_success() && cancel.cancel_all() {
+                        eprintln!("instance {i} failed, cancel remaining instances");
+                    }
+                    (outcome, stats)
+                }));
+            }
+            let names: Vec<_> = (0..tasks.len())
+                .map(|i| cgroup_name.render(component_name, i))
+                .collect();
+            if let Some(setup) = &mut setup {
+                setup.names.clone_from(&names);
+            }
+            let monitor =
+                (pressure_interval.is_some() || pressure_threshold.is_some()).then(|| {
+                    let monitor = pressure::Monitor {
+                        interval: pressure_interval.unwrap_or(Duration::from_secs(1)),
+                        threshold: pressure_threshold,
+                    };
+                    rt.spawn(monitor.run(Arc::clone(&cg), names.clone(), throttle_tx))
+                });
+            let tui = tui.then(|| {
+                rt.spawn(Arc::clone(&top).run(
+                    Arc::clone(&cg),
+                    names.clone(),
+                    Arc::clone(&usage),
+                    cgroup_enabled,
+                    Duration::from_secs(1),
+                ))
+            });
+            let server = (control.is_some() || control_file.is_some()).then(|| {
+                Arc::new(control::Control::new(
+                    Arc::clone(&cg),
+                    names.clone(),
+                    Arc::clone(&cancel),
+                ))
+            });
+            let control = control.zip(server.clone()).map(|((path, listener), server)| {
+                (path, rt.spawn(server.serve(listener)))
+            });
+            let control_file = control_file
+                .zip(server)
+                .map(|(path, server)| rt.spawn(server.on_signal(path)));
+            let signals = rt.spawn(cancel_on_signal(Arc::clone(&cancel)));
+            let dump = rt.spawn(
+                top::Dump {
+                    top: Arc::clone(&top),
+                    cg: Arc::clone(&cg),
+                    names: names.clone(),
+                    usage: Arc::clone(&usage),
+                    cgroups: cgroup_enabled,
+                    pool_slots,
+                    path: state_dump,
+                }
+                .run(),
+            );
+            let interrupter = watchdog_timeout.zip(health.as_ref()).map(|(timeout, health)| {
+                rt.spawn(Arc::clone(health).watchdog(timeout, Arc::clone(&cancel)))
+            });
+            let ticker = profile_interval.map(|interval| {
+                let engine = engine.clone();
+                rt.spawn(async move {
+                    let mut interval = tokio::time::interval(interval);
+                    loop {
+                        interval.tick().await;
+                        engine.increment_epoch();
+                    }
+                })
+            });
+            wasm_tx
+                .send(pre)
+                .map_err(|_| anyhow!("Wasm receiver closed"))?;
+            if let Some(health) = &health {
+                health.set_compiled();
+            }
+            systemd::notify("READY=1");
+            let watchdog =
+                systemd::watchdog_interval().map(|interval| rt.spawn(systemd::watchdog(interval)));
+            let mut outcomes = Vec::with_capacity(tasks.len());
+            for (i, task) in tasks.into_iter().enumerate() {
+                eprintln!("joining task...");
+                outcomes.push(task.await.unwrap_or_else(|_| {
+                    (
+                        Outcome::Error(anyhow!("task panicked")),
+                        Stats::new(cg.join(&names[i])),
+                    )
+                }));
+            }
+            systemd::notify("STOPPING=1");
+            let oom_kill =
+                oom_kill.and_then(|start| Some(report::read_oom_kill(&cg)?.saturating_sub(start)));
+            if let Some(n) = oom_kill.filter(|n| *n > 0) {
+                eprintln!("{n} tasks of the cgroup of the process were OOM-killed during the run");
+            }
+            if let Some(watchdog) = watchdog {
+                watchdog.abort();
+            }
+            if let Some(tui) = tui {
+                tui.abort();
+            }
+            if let Some(monitor) = monitor {
+                monitor.abort();
+            }
+            if let Some(ticker) = ticker {
+                ticker.abort();
+            }
+            signals.abort();
+            dump.abort();
+            if let Some(interrupter) = interrupter {
+                interrupter.abort();
+            }
+            if let Some(server) = health_server {
+                server.abort();
+            }
+            if let Some(control_file) = control_file {
+                control_file.abort();
+            }
+            if let Some((path, server)) = control {
+                server.abort();
+                if let Err(err) = fs::remove_file(&path).await {
+                    eprintln!("failed to remove `{}`: {err}", path.display());
+                }
+            }
+
+            drop(setup);
+
+            eprintln!("{:<10}OUTCOME", "INSTANCE");
+            for (i, (outcome, _)) in outcomes.iter().enumerate() {
+                eprintln!("{i:<10}{outcome}");
+            }
+            if let Some(report) = report {
+                report::write(&report, provenance.as_ref(), oom_kill, &outcomes).await?;
+            }
+            Ok(outcomes)
         }
-        anyhow::Ok(())
-    })
+        .instrument(span),
+    )
 }
@@ -0,0 +1,262 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use core::time::Duration;
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context as _};
+use async_nats::{HeaderMap, Subscriber};
+use futures::StreamExt as _;
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+use bindings::wasi::messaging::types::{Error, FormatSpec, GuestConfiguration, Message};
+use bindings::wasi::messaging::{consumer, producer, types};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "wasi:messaging/imports",
+        trappable_imports: true,
+        async: true,
+        with: {
+            "wasi:messaging/types/client": super::Client,
+        },
+    });
+}
+
+/// `wasi:messaging` backend selection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendConfig {
+    /// NATS server at the address, like `nats:127.0.0.1:4222`
+    Nats(String),
+}
+
+impl FromStr for BackendConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("nats", addr)) if !addr.is_empty() => Ok(Self::Nats(addr.into())),
+            _ => bail!("invalid messaging backend `{s}`, expected `nats:ADDR`"),
+        }
+    }
+}
+
+impl Display for BackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nats(addr) => write!(f, "nats:{addr}"),
+        }
+    }
+}
+
+/// Connects to the backend, the connection is shared by all instances
+pub async fn connect(config: &BackendConfig) -> anyhow::Result<async_nats::Client> {
+    match config {
+        BackendConfig::Nats(addr) => async_nats::connect(addr)
+            .await
+            .with_context(|| format!("failed to connect to NATS at `{addr}`")),
+    }
+}
+
+/// `wasi:messaging` client resource
+pub struct Client(async_nats::Client);
+
+/// Per-instance `wasi:messaging` state
+pub struct MessagingCtx {
+    nats: Option<async_nats::Client>,
+    queue_group: Option<String>,
+    subscriptions: HashMap<String, Subscriber>,
+}
+
+impl MessagingCtx {
+    /// Creates the state of an instance, which joins `queue_group` on all subscriptions if set,
+    /// so each message is only delivered to one member of the group
+    pub fn new(nats: Option<async_nats::Client>, queue_group: Option<String>) -> Self {
+        Self {
+            nats,
+            queue_group,
+            subscriptions: HashMap::default(),
+        }
+    }
+}
+
+/// A view into the `wasi:messaging` state of an instance
+pub struct Messaging<'a> {
+    ctx: &'a mut MessagingCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> Messaging<'a> {
+    pub fn new(ctx: &'a mut MessagingCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+
+    /// Returns the subscription to `ch`, subscribing if necessary
+    async fn subscription(
+        &mut self,
+        client: &Resource<Client>,
+        ch: String,
+    ) -> wasmtime::Result<Result<&mut Subscriber, Error>> {
+        let Client(client) = self.table.get(client)?;
+        let client = client.clone();
+        let sub = match self.ctx.subscriptions.entry(ch) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let ch = entry.key().clone();
+                let sub = if let Some(group) = &self.ctx.queue_group {
+                    client.queue_subscribe(ch, group.clone()).await
+                } else {
+                    client.subscribe(ch).await
+                };
+                match sub {
+                    Ok(sub) => entry.insert(sub),
+                    Err(err) => return Ok(Err(other(err))),
+                }
+            }
+        };
+        Ok(Ok(sub))
+    }
+}
+
+fn other(err: impl Display) -> Error {
+    Error::Other(err.to_string())
+}
+
+fn message(msg: async_nats::Message) -> Message {
+    let metadata = msg.headers.map(|headers| {
+        headers
+            .iter()
+            .flat_map(|(k, vs)| vs.iter().map(move |v| (k.to_string(), v.to_string())))
+            .collect()
+    });
+    Message {
+        data: msg.payload.into(),
+        format: FormatSpec::Raw,
+        metadata,
+    }
+}
+
+#[async_trait::async_trait]
+impl types::HostClient for Messaging<'_> {
+    async fn connect(
+        &mut self,
+        _name: String,
+    ) -> wasmtime::Result<Result<Resource<Client>, Error>> {
+        let Some(nats) = &self.ctx.nats else {
+            return Ok(Err(Error::Other(
+                "no messaging backend configured".to_string(),
+            )));
+        };
+        let client = self.table.push(Client(nats.clone()))?;
+        Ok(Ok(client))
+    }
+
+    async fn disconnect(
+        &mut self,
+        _client: Resource<Client>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        // the connection is shared by all instances
+        Ok(Ok(()))
+    }
+
+    async fn drop(&mut self, client: Resource<Client>) -> wasmtime::Result<()> {
+        self.table.delete(client)?;
+        Ok(())
+    }
+}
+
+impl types::Host for Messaging<'_> {}
+
+#[async_trait::async_trait]
+impl producer::Host for Messaging<'_> {
+    async fn send(
+        &mut self,
+        client: Resource<Client>,
+        ch: String,
+        msgs: Vec<Message>,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        let Client(client) = self.table.get(&client)?;
+        for Message { data, metadata, .. } in msgs {
+            let mut headers = HeaderMap::new();
+            for (k, v) in metadata.unwrap_or_default() {
+                headers.append(k, v);
+            }
+            if let Err(err) = client
+                .publish_with_headers(ch.clone(), headers, data.into())
+                .await
+            {
+                return Ok(Err(other(err)));
+            }
+        }
+        Ok(client.flush().await.map_err(other))
+    }
+}
+
+#[async_trait::async_trait]
+impl consumer::Host for Messaging<'_> {
+    async fn subscribe_try_receive(
+        &mut self,
+        client: Resource<Client>,
+        ch: String,
+        t_milliseconds: u32,
+    ) -> wasmtime::Result<Result<Option<Vec<Message>>, Error>> {
+        let sub = match self.subscription(&client, ch).await? {
+            Ok(sub) => sub,
+            Err(err) => return Ok(Err(err)),
+        };
+        let timeout = Duration::from_millis(t_milliseconds.into());
+        match tokio::time::timeout(timeout, sub.next()).await {
+            Ok(Some(msg)) => Ok(Ok(Some(vec![message(msg)]))),
+            Ok(None) => Ok(Err(Error::Connection)),
+            Err(..) => Ok(Ok(None)),
+        }
+    }
+
+    async fn subscribe_receive(
+        &mut self,
+        client: Resource<Client>,
+        ch: String,
+    ) -> wasmtime::Result<Result<Vec<Message>, Error>> {
+        let sub = match self.subscription(&client, ch).await? {
+            Ok(sub) => sub,
+            Err(err) => return Ok(Err(err)),
+        };
+        match sub.next().await {
+            Some(msg) => Ok(Ok(vec![message(msg)])),
+            None => Ok(Err(Error::Connection)),
+        }
+    }
+
+    async fn update_guest_configuration(
+        &mut self,
+        gc: GuestConfiguration,
+    ) -> wasmtime::Result<Result<(), Error>> {
+        // subscriptions are created on first receive, so only those no longer requested
+        // need to be dropped here
+        self.ctx
+            .subscriptions
+            .retain(|ch, _| gc.channels.contains(ch));
+        Ok(Ok(()))
+    }
+
+    async fn complete_message(&mut self, _m: Message) -> wasmtime::Result<Result<(), Error>> {
+        // NATS core messages are not acknowledged
+        Ok(Ok(()))
+    }
+
+    async fn abandon_message(&mut self, _m: Message) -> wasmtime::Result<Result<(), Error>> {
+        Ok(Ok(()))
+    }
+}
+
+/// Adds `wasi:messaging` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> Messaging<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    types::add_to_linker_get_host(linker, f)?;
+    producer::add_to_linker_get_host(linker, f)?;
+    consumer::add_to_linker_get_host(linker, f)?;
+    Ok(())
+}
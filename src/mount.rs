@@ -0,0 +1,225 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _};
+use nix::mount::{mount, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
+
+/// Size in bytes with an optional binary `k`, `m` or `g` suffix, like `64m`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (n, shift) = match s.as_bytes().last() {
+            Some(b'k' | b'K') => (&s[..s.len() - 1], 10),
+            Some(b'm' | b'M') => (&s[..s.len() - 1], 20),
+            Some(b'g' | b'G') => (&s[..s.len() - 1], 30),
+            _ => (s, 0),
+        };
+        let n: u64 = n.parse().with_context(|| format!("invalid size `{s}`"))?;
+        let Some(n) = n.checked_mul(1 << shift) else {
+            bail!("size `{s}` is too large");
+        };
+        Ok(Self(n))
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Host directory exposed read-only to guests, of the form `HOST::GUEST`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoDir {
+    pub host: PathBuf,
+    pub guest: String,
+}
+
+impl FromStr for RoDir {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((host, guest)) = s.split_once("::") else {
+            bail!("invalid directory `{s}`, expected `HOST::GUEST`");
+        };
+        if host.is_empty() || guest.is_empty() {
+            bail!("invalid directory `{s}`, expected `HOST::GUEST`");
+        }
+        Ok(Self {
+            host: host.into(),
+            guest: guest.into(),
+        })
+    }
+}
+
+impl Display for RoDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.host.display(), self.guest)
+    }
+}
+
+/// Filesystems mounted in the mount namespace of each sandbox and preopened for the guest
+#[derive(Debug)]
+pub struct Mounts {
+    /// Size and guest path of a private tmpfs
+    tmpfs: Option<(Size, String)>,
+    /// Empty host directory the tmpfs is mounted over, created and removed by us
+    tmpfs_host: PathBuf,
+    /// Host directories bind-mounted read-only
+    ro_dirs: Vec<RoDir>,
+}
+
+impl Mounts {
+    pub fn new(tmpfs: Option<(Size, String)>, ro_dirs: Vec<RoDir>) -> anyhow::Result<Self> {
+        let tmpfs_host = std::env::temp_dir().join(format!("cgwasm-{}-tmpfs", std::process::id()));
+        if tmpfs.is_some() {
+            std::fs::create_dir(&tmpfs_host)
+                .with_context(|| format!("failed to create `{}`", tmpfs_host.display()))?;
+        }
+        for RoDir { host, .. } in &ro_dirs {
+            if !host.is_dir() {
+                bail!("`{}` is not a directory", host.display());
+            }
+        }
+        Ok(Self {
+            tmpfs,
+            tmpfs_host,
+            ro_dirs,
+        })
+    }
+
+    /// Mounts filesystems in the mount namespace of the current thread and preopens them
+    /// on the builder.
+    ///
+    /// The current thread must have unshared its mount namespace
+    pub fn apply(&self, builder: &mut WasiCtxBuilder) -> anyhow::Result<()> {
+        if self.tmpfs.is_none() && self.ro_dirs.is_empty() {
+            return Ok(());
+        }
+        // keep mounts from propagating to the host
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+            None::<&str>,
+        )
+        .context("failed to make mounts private")?;
+        if let Some((size, guest)) = &self.tmpfs {
+            mount(
+                Some("tmpfs"),
+                &self.tmpfs_host,
+                Some("tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                Some(format!("size={size},mode=0700").as_str()),
+            )
+            .with_context(|| format!("failed to mount tmpfs at `{}`", self.tmpfs_host.display()))?;
+            builder
+                .preopened_dir(&self.tmpfs_host, guest, DirPerms::all(), FilePerms::all())
+                .with_context(|| format!("failed to preopen tmpfs at `{guest}`"))?;
+        }
+        for dir @ RoDir { host, guest } in &self.ro_dirs {
+            bind_ro(host).with_context(|| format!("failed to bind-mount `{dir}` read-only"))?;
+            builder
+                .preopened_dir(host, guest, DirPerms::READ, FilePerms::READ)
+                .with_context(|| format!("failed to preopen `{dir}`"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Bind-mounts `path` read-only over itself, including all mounts below it
+fn bind_ro(path: &Path) -> anyhow::Result<()> {
+    mount(
+        Some(path),
+        path,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .context("failed to bind-mount")?;
+    // `MS_REC` is ignored on remount, so each submount is remounted separately
+    for path in submounts(path)? {
+        remount_ro(&path)
+            .with_context(|| format!("failed to remount `{}` read-only", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Returns mount points of the calling thread at `path` and below it, parents first
+fn submounts(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("failed to canonicalize `{}`", path.display()))?;
+    let mountinfo = std::fs::read_to_string("/proc/thread-self/mountinfo")
+        .context("failed to read `/proc/thread-self/mountinfo`")?;
+    let mut mounts: Vec<_> = mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|dir| PathBuf::from(unescape(dir)))
+        .filter(|dir| dir.starts_with(&path))
+        .collect();
+    mounts.sort();
+    mounts.dedup();
+    Ok(mounts)
+}
+
+/// Decodes octal escapes of whitespace and backslashes in `/proc/self/mountinfo` paths
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let code = rest
+            .get(i + 1..i + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+        if let Some(code) = code {
+            out.push(char::from(code));
+            rest = &rest[i + 4..];
+        } else {
+            out.push('\\');
+            rest = &rest[i + 1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Remounts the bind mount at `path` read-only
+fn remount_ro(path: &Path) -> anyhow::Result<()> {
+    // flags locked by the owning user namespace must be kept on remount
+    let locked = statvfs(path).context("failed to stat filesystem")?.flags();
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    for (fs, ms) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if locked.contains(fs) {
+            flags |= ms;
+        }
+    }
+    mount(None::<&str>, path, None::<&str>, flags, None::<&str>).context("failed to remount")
+}
+
+impl Drop for Mounts {
+    fn drop(&mut self) {
+        if self.tmpfs.is_none() {
+            return;
+        }
+        if let Err(err) = std::fs::remove_dir(&self.tmpfs_host) {
+            eprintln!("failed to remove `{}`: {err}", self.tmpfs_host.display());
+        }
+    }
+}
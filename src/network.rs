@@ -0,0 +1,249 @@
+use core::fmt::{self, Display};
+use core::net::{IpAddr, SocketAddr};
+use core::str::FromStr;
+
+use std::io;
+use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd};
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context as _};
+use serde::Deserialize;
+use wasmtime_wasi::{SocketAddrUse, WasiCtxBuilder};
+
+use crate::audit::Audit;
+use crate::chaos::{Chaos, Target};
+use crate::ratelimit::NetLimiter;
+
+/// Outbound socket destination rule of the form `[INDEX@]CIDR[:PORT]`.
+///
+/// IPv6 CIDRs must be enclosed in brackets if a port is specified, e.g. `[fd00::/8]:443`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct NetRule {
+    instance: Option<usize>,
+    addr: IpAddr,
+    prefix: u8,
+    port: Option<u16>,
+}
+
+fn parse_cidr(s: &str) -> anyhow::Result<(IpAddr, u8)> {
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (s, None),
+    };
+    let addr = addr
+        .parse::<IpAddr>()
+        .with_context(|| format!("invalid IP address `{addr}`"))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix
+        .map(|prefix| {
+            prefix
+                .parse::<u8>()
+                .with_context(|| format!("invalid prefix length `{prefix}`"))
+        })
+        .transpose()?
+        .unwrap_or(max);
+    ensure!(prefix <= max, "prefix length `{prefix}` exceeds {max}");
+    Ok((addr, prefix))
+}
+
+impl FromStr for NetRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (instance, rest) = match s.split_once('@') {
+            Some((instance, rest)) => {
+                let instance = instance
+                    .parse()
+                    .with_context(|| format!("invalid instance index `{instance}` in `{s}`"))?;
+                (Some(instance), rest)
+            }
+            None => (None, s),
+        };
+        let (cidr, port) = if let Some(rest) = rest.strip_prefix('[') {
+            let (cidr, port) = rest
+                .split_once(']')
+                .with_context(|| format!("unterminated IPv6 CIDR in `{s}`"))?;
+            let port = match port {
+                "" => None,
+                port => Some(
+                    port.strip_prefix(':')
+                        .with_context(|| format!("invalid port separator in `{s}`"))?,
+                ),
+            };
+            (cidr, port)
+        } else if let Some((cidr, port)) =
+            rest.split_once(':').filter(|(_, port)| !port.contains(':'))
+        {
+            (cidr, Some(port))
+        } else {
+            (rest, None)
+        };
+        if cidr.is_empty() {
+            bail!("empty CIDR in `{s}`");
+        }
+        let (addr, prefix) = parse_cidr(cidr).with_context(|| format!("invalid CIDR in `{s}`"))?;
+        let port = port
+            .map(|port| {
+                port.parse()
+                    .with_context(|| format!("invalid port `{port}` in `{s}`"))
+            })
+            .transpose()?;
+        Ok(Self {
+            instance,
+            addr,
+            prefix,
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for NetRule {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for NetRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(instance) = self.instance {
+            write!(f, "{instance}@")?;
+        }
+        match (self.addr, self.port) {
+            (IpAddr::V6(addr), Some(port)) => write!(f, "[{addr}/{}]:{port}", self.prefix),
+            (addr, Some(port)) => write!(f, "{addr}/{}:{port}", self.prefix),
+            (addr, None) => write!(f, "{addr}/{}", self.prefix),
+        }
+    }
+}
+
+impl NetRule {
+    /// Whether the rule applies to instance at `index`
+    fn applies_to(&self, index: usize) -> bool {
+        self.instance.is_none_or(|instance| instance == index)
+    }
+
+    fn matches(&self, addr: SocketAddr) -> bool {
+        if self.port.is_some_and(|port| port != addr.port()) {
+            return false;
+        }
+        match (self.addr, addr.ip().to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Brings up the loopback interface of the network namespace of the calling thread,
+/// which is down in a newly created namespace
+pub fn loopback_up() -> io::Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut req: libc::ifreq = unsafe { core::mem::zeroed() };
+    for (dst, src) in req.ifr_name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCGIFFLAGS, &mut req) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe {
+        req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+    }
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCSIFFLAGS, &req) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Policy applied to guest outbound `wasi:sockets` connections.
+///
+/// Sandboxes have a network namespace of their own with only a loopback interface,
+/// so connections to other addresses are denied, as they could never succeed
+#[derive(Clone, Debug, Default)]
+pub struct NetPolicy {
+    /// If not empty, only destinations matching a rule applicable to the instance are allowed
+    pub allow: Vec<NetRule>,
+}
+
+impl NetPolicy {
+    /// Configures socket address checks of instance at `index` on the builder,
+    /// recording destinations to `audit`, delaying connects by `limiter`
+    /// and injecting faults by `chaos`, if set
+    pub fn configure(
+        &self,
+        index: usize,
+        builder: &mut WasiCtxBuilder,
+        audit: Option<Audit>,
+        limiter: Option<Arc<NetLimiter>>,
+        chaos: Option<Arc<Chaos>>,
+    ) {
+        builder.allow_tcp(true).allow_udp(true);
+        let allow: Option<Arc<[NetRule]>> = (!self.allow.is_empty()).then(|| {
+            self.allow
+                .iter()
+                .filter(|rule| rule.applies_to(index))
+                .cloned()
+                .collect()
+        });
+        builder.socket_addr_check(move |addr, usage| {
+            let ip = addr.ip().to_canonical();
+            let allowed = match (usage, &allow) {
+                _ if !ip.is_loopback() && !ip.is_unspecified() => {
+                    eprintln!("instance {index} denied `{addr}`, sandboxes only have loopback");
+                    false
+                }
+                (_, None) | (SocketAddrUse::TcpBind | SocketAddrUse::UdpBind, _) => true,
+                (
+                    SocketAddrUse::TcpConnect
+                    | SocketAddrUse::UdpConnect
+                    | SocketAddrUse::UdpOutgoingDatagram,
+                    Some(allow),
+                ) => {
+                    let allowed = allow.iter().any(|rule| rule.matches(addr));
+                    if !allowed {
+                        eprintln!("instance {index} denied outgoing connection to `{addr}`");
+                    }
+                    allowed
+                }
+            };
+            // datagrams are not connections, only connects count towards the rate
+            let limiter = limiter.clone().filter(|_| {
+                allowed && matches!(usage, SocketAddrUse::TcpConnect | SocketAddrUse::UdpConnect)
+            });
+            let chaos = chaos.clone().filter(|_| {
+                allowed && !matches!(usage, SocketAddrUse::TcpBind | SocketAddrUse::UdpBind)
+            });
+            let audit = audit.clone();
+            Box::pin(async move {
+                if let Some(limiter) = limiter {
+                    limiter.wait().await;
+                }
+                let allowed = match chaos {
+                    Some(chaos) => !chaos.inject(Target::Net).await,
+                    None => allowed,
+                };
+                // the outcome is recorded as seen by the guest, including injected faults
+                if let Some(audit) = audit {
+                    audit.connect(addr, usage, allowed);
+                }
+                allowed
+            })
+        });
+    }
+}
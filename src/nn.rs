@@ -0,0 +1,102 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context as _};
+use wasmtime_wasi_nn::wit::{ExecutionTarget, GraphEncoding, WasiNnCtx};
+use wasmtime_wasi_nn::{backend, Graph, GraphRegistry, Registry};
+
+/// Model loaded before instances start, of the form `ENCODING::DIR`, like `openvino::/models/mobilenet`.
+///
+/// Guests load it by the name of the directory, `mobilenet` in the example
+#[derive(Clone, Debug)]
+pub struct Preload {
+    pub encoding: GraphEncoding,
+    pub dir: PathBuf,
+}
+
+impl FromStr for Preload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((encoding, dir)) = s.split_once("::") else {
+            bail!("invalid model `{s}`, expected `ENCODING::DIR`");
+        };
+        if dir.is_empty() {
+            bail!("invalid model `{s}`, expected `ENCODING::DIR`");
+        }
+        Ok(Self {
+            encoding: encoding.parse()?,
+            dir: dir.into(),
+        })
+    }
+}
+
+impl Display for Preload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.encoding, self.dir.display())
+    }
+}
+
+/// Device `wasi-nn` models are loaded on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Device {
+    #[default]
+    Cpu,
+    Gpu,
+    Tpu,
+}
+
+impl From<Device> for ExecutionTarget {
+    fn from(device: Device) -> Self {
+        match device {
+            Device::Cpu => Self::Cpu,
+            Device::Gpu => Self::Gpu,
+            Device::Tpu => Self::Tpu,
+        }
+    }
+}
+
+/// Preloaded models, shared by all instances
+#[derive(Clone, Default)]
+pub struct Graphs(HashMap<String, Graph>);
+
+impl Graphs {
+    pub fn load(preloads: &[Preload], device: Device) -> anyhow::Result<Self> {
+        let mut backends = backend::list();
+        let mut graphs = HashMap::with_capacity(preloads.len());
+        for preload @ Preload { encoding, dir } in preloads {
+            let Some(name) = dir.file_name() else {
+                bail!("model directory `{}` has no name", dir.display());
+            };
+            let Some(backend) = backends.iter_mut().find(|b| b.encoding() == *encoding) else {
+                bail!("`{encoding}` backend is not supported by this build");
+            };
+            let Some(backend) = backend.as_dir_loadable() else {
+                bail!("`{encoding}` backend cannot load models from a directory");
+            };
+            let graph = backend
+                .load_from_dir(dir, device.into())
+                .with_context(|| format!("failed to load model `{preload}`"))?;
+            graphs.insert(name.to_string_lossy().into_owned(), graph);
+        }
+        Ok(Self(graphs))
+    }
+
+    /// Creates the `wasi-nn` state of an instance, with all models preloaded
+    pub fn ctx(&self) -> WasiNnCtx {
+        WasiNnCtx::new(backend::list(), Registry::from(self.clone()))
+    }
+}
+
+impl GraphRegistry for Graphs {
+    fn get(&self, name: &str) -> Option<&Graph> {
+        self.0.get(name)
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Graph> {
+        self.0.get_mut(name)
+    }
+}
@@ -0,0 +1,195 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::path::Path;
+
+use anyhow::{bail, Context as _};
+
+use crate::cgroup::parse_cpus;
+
+/// NUMA placement of sandboxes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumaConfig {
+    /// Spread instances across nodes, binding each one to the CPUs and memory of a single node
+    Auto,
+    /// Bind all instances to the CPUs and memory of the node
+    Node(usize),
+    /// Interleave memory of all instances across all nodes
+    Interleave,
+}
+
+impl FromStr for NumaConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "auto" => Ok(Self::Auto),
+            None if s == "interleave" => Ok(Self::Interleave),
+            Some(("node", node)) => node
+                .parse()
+                .map(Self::Node)
+                .with_context(|| format!("invalid NUMA node `{node}`")),
+            _ => bail!("invalid NUMA placement `{s}`, expected `auto`, `node:N` or `interleave`"),
+        }
+    }
+}
+
+impl Display for NumaConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Node(node) => write!(f, "node:{node}"),
+            Self::Interleave => write!(f, "interleave"),
+        }
+    }
+}
+
+/// NUMA node with the CPUs of it available to sandboxes
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Node {
+    id: usize,
+    cpus: Vec<usize>,
+}
+
+/// Returns online NUMA nodes with memory and their CPUs within `available`
+fn read_nodes(available: &[usize]) -> anyhow::Result<Vec<Node>> {
+    const NODES: &str = "/sys/devices/system/node/has_memory";
+    let nodes =
+        std::fs::read_to_string(NODES).with_context(|| format!("failed to read `{NODES}`"))?;
+    let nodes =
+        parse_cpus(&nodes).with_context(|| format!("failed to parse `{NODES}` contents"))?;
+    nodes
+        .into_iter()
+        .map(|id| {
+            let path = format!("/sys/devices/system/node/node{id}/cpulist");
+            let cpus = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read `{path}`"))?;
+            let cpus = parse_cpus(&cpus)
+                .with_context(|| format!("failed to parse `{path}` contents"))?
+                .into_iter()
+                .filter(|cpu| available.contains(cpu))
+                .collect();
+            Ok(Node { id, cpus })
+        })
+        .collect()
+}
+
+fn join(ids: impl IntoIterator<Item = usize>) -> String {
+    ids.into_iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// NUMA placement resolved against the topology of the host
+#[derive(Clone, Debug)]
+pub struct Numa {
+    config: NumaConfig,
+    /// Nodes instances are bound to or memory is interleaved across
+    nodes: Vec<Node>,
+}
+
+impl Numa {
+    /// Resolves `config` against nodes with CPUs available to the cgroup at `cg`.
+    ///
+    /// Returns `None` on hosts with a single node, where placement has no effect
+    pub fn new(config: NumaConfig, cg: &Path) -> anyhow::Result<Option<Self>> {
+        let effective_path = cg.join("cpuset.cpus.effective");
+        let available = std::fs::read_to_string(&effective_path)
+            .with_context(|| format!("failed to read `{}`", effective_path.display()))?;
+        let available = parse_cpus(&available)
+            .with_context(|| format!("failed to parse `{}` contents", effective_path.display()))?;
+        let mut nodes = read_nodes(&available)?;
+        if nodes.len() <= 1 {
+            eprintln!("single NUMA node, `--numa={config}` has no effect");
+            return Ok(None);
+        }
+        match config {
+            NumaConfig::Auto => {
+                nodes.retain(|node| !node.cpus.is_empty());
+                if nodes.is_empty() {
+                    bail!("no NUMA node has CPUs available to sandboxes")
+                }
+            }
+            NumaConfig::Node(id) => {
+                nodes.retain(|node| node.id == id);
+                match nodes.first() {
+                    None => bail!("NUMA node {id} does not exist or has no memory"),
+                    Some(node) if node.cpus.is_empty() => {
+                        bail!("NUMA node {id} has no CPUs available to sandboxes")
+                    }
+                    Some(..) => {}
+                }
+            }
+            NumaConfig::Interleave => {}
+        }
+        for node in &nodes {
+            eprintln!(
+                "NUMA node {}: CPUs {}",
+                node.id,
+                join(node.cpus.iter().copied())
+            );
+        }
+        Ok(Some(Self { config, nodes }))
+    }
+
+    /// Returns the node instance at `index` is bound to, if any
+    fn node(&self, index: usize) -> Option<&Node> {
+        match self.config {
+            NumaConfig::Auto => self.nodes.get(index % self.nodes.len()),
+            NumaConfig::Node(..) => self.nodes.first(),
+            NumaConfig::Interleave => None,
+        }
+    }
+
+    /// Returns the number of CPUs of the node instance at `index` is bound to, if any
+    pub fn cpus(&self, index: usize) -> Option<usize> {
+        self.node(index).map(|node| node.cpus.len())
+    }
+
+    /// Binds `cpuset.cpus` and `cpuset.mems` of the cgroup at `path` of instance at `index`
+    /// to the node of the instance or, for [`NumaConfig::Interleave`], interleaves memory
+    /// of the calling thread across all nodes.
+    ///
+    /// Pages are placed on first touch, so pooling allocator slots are not node-local:
+    /// pages kept resident by a slot stay on the node of the instance which faulted them in
+    pub fn apply(&self, index: usize, path: &Path) -> anyhow::Result<()> {
+        let Some(node) = self.node(index) else {
+            let mems = join(self.nodes.iter().map(|node| node.id));
+            return interleave(self.nodes.iter().map(|node| node.id))
+                .with_context(|| format!("failed to interleave memory across nodes {mems}"));
+        };
+        for (file, list) in [
+            ("cpuset.cpus", join(node.cpus.iter().copied())),
+            ("cpuset.mems", node.id.to_string()),
+        ] {
+            let path = path.join(file);
+            std::fs::write(&path, &list)
+                .with_context(|| format!("failed to write `{list}` to `{}`", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets the memory policy of the calling thread to interleave allocations across `nodes`
+fn interleave(nodes: impl IntoIterator<Item = usize>) -> std::io::Result<()> {
+    const BITS: usize = libc::c_ulong::BITS as usize;
+    let mut mask = [0 as libc::c_ulong; 1024 / BITS];
+    for node in nodes {
+        if let Some(word) = mask.get_mut(node / BITS) {
+            *word |= 1 << (node % BITS);
+        }
+    }
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            libc::MPOL_INTERLEAVE,
+            mask.as_ptr(),
+            mask.len() * BITS,
+        )
+    };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
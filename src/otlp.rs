@@ -0,0 +1,51 @@
+use anyhow::Context as _;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig as _};
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+
+/// OTLP/HTTP span exporter.
+///
+/// Spans are exported in batches by a dedicated runtime and flushed on drop
+pub struct Otlp {
+    provider: TracerProvider,
+    // owns the worker driving the batch exporter, must outlive `provider`
+    _rt: tokio::runtime::Runtime,
+}
+
+impl Otlp {
+    /// Creates an exporter sending spans to `/v1/traces` of the collector at `endpoint`
+    pub fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_io()
+            .enable_time()
+            .thread_name("cgwasm-otlp")
+            .build()
+            .context("failed to build OTLP Tokio runtime")?;
+        let _guard = rt.enter();
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .context("failed to build OTLP span exporter")?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", "cgwasm")]))
+            .build();
+        Ok(Self { provider, _rt: rt })
+    }
+
+    pub fn tracer(&self) -> Tracer {
+        self.provider.tracer("cgwasm")
+    }
+}
+
+impl Drop for Otlp {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            eprintln!("failed to flush OTLP spans: {err}");
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use core::time::Duration;
+
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _};
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt as _, Limited};
+use opentelemetry::propagation::TextMapPropagator as _;
+use opentelemetry::trace::TraceContextExt as _;
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tokio::runtime::Handle;
+use tracing::{info_span, Instrument as _, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    default_send_request_handler, HostFutureIncomingResponse, OutgoingRequestConfig,
+};
+use wasmtime_wasi_http::HttpResult;
+
+use crate::chaos::{Chaos, Target};
+use crate::proxy::{self, Proxy};
+use crate::ratelimit::NetLimiter;
+use crate::trace::Trace;
+
+/// Outbound HTTP destination rule of the form `[SCHEME://]HOST[:PORT]`.
+///
+/// IPv6 addresses must be enclosed in brackets, like `[::1]:443`.
+/// `HOST` may be `*` to match any host or start with `*.` to match any subdomain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostRule {
+    scheme: Option<String>,
+    host: String,
+    port: Option<u16>,
+}
+
+impl FromStr for HostRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = match s.split_once("://") {
+            Some((scheme @ ("http" | "https"), rest)) => (Some(scheme), rest),
+            Some((scheme, _)) => bail!("unsupported scheme `{scheme}` in `{s}`"),
+            None => (None, s),
+        };
+        let (host, port) = if let Some(rest) = rest.strip_prefix('[') {
+            let (host, port) = rest
+                .split_once(']')
+                .with_context(|| format!("unterminated IPv6 address in `{s}`"))?;
+            let port =
+                match port {
+                    "" => None,
+                    rest => Some(rest.strip_prefix(':').with_context(|| {
+                        format!("unexpected `{rest}` after IPv6 address in `{s}`")
+                    })?),
+                };
+            (host, port)
+        } else if rest.matches(':').count() > 1 {
+            bail!("IPv6 address in `{s}` must be enclosed in brackets, like `[::1]:443`");
+        } else if let Some((host, port)) = rest.split_once(':') {
+            (host, Some(port))
+        } else {
+            (rest, None)
+        };
+        if host.is_empty() {
+            bail!("empty host in `{s}`");
+        }
+        let port = port
+            .map(|port| {
+                port.parse()
+                    .with_context(|| format!("invalid port `{port}` in `{s}`"))
+            })
+            .transpose()?;
+        Ok(Self {
+            scheme: scheme.map(str::to_string),
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl Display for HostRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}://")?;
+        }
+        if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            write!(f, "{}", self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        Ok(())
+    }
+}
+
+impl HostRule {
+    fn matches(&self, scheme: &str, host: &str, port: u16) -> bool {
+        if self.scheme.as_deref().is_some_and(|s| s != scheme) {
+            return false;
+        }
+        if self.port.is_some_and(|p| p != port) {
+            return false;
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match self.host.strip_prefix("*.") {
+            _ if self.host == "*" => true,
+            Some(suffix) => host
+                .strip_suffix(suffix)
+                .is_some_and(|sub| sub.ends_with('.')),
+            None => self.host == host,
+        }
+    }
+}
+
+/// Policy applied to guest outbound `wasi:http` requests
+#[derive(Clone, Debug)]
+pub struct HttpPolicy {
+    /// If not empty, only destinations matching one of these rules are allowed
+    pub allow: Vec<HostRule>,
+    /// Destinations matching any of these rules are denied, takes precedence over `allow`
+    pub deny: Vec<HostRule>,
+    /// Maximum duration of a request until response headers are received
+    pub timeout: Option<Duration>,
+    /// Maximum size of request and response bodies in bytes
+    pub max_body_size: Option<u64>,
+    pub proxy: Proxy,
+    /// Root runtime requests are sent on, within the host network namespace,
+    /// since sandboxes have no network of their own
+    pub rt: Handle,
+}
+
+impl HttpPolicy {
+    /// Returns a policy denying all requests, must be called from within the root runtime
+    pub fn deny_all() -> Self {
+        Self {
+            allow: Vec::new(),
+            deny: vec![HostRule {
+                scheme: None,
+                host: "*".into(),
+                port: None,
+            }],
+            timeout: None,
+            max_body_size: None,
+            proxy: Proxy::default(),
+            rt: Handle::current(),
+        }
+    }
+
+    /// Whether requests to a lowercase `host` are allowed
+    pub fn is_allowed(&self, scheme: &str, host: &str, port: u16) -> bool {
+        if self.deny.iter().any(|r| r.matches(scheme, host, port)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|r| r.matches(scheme, host, port))
+    }
+
+    /// Sends an outgoing request, if allowed, enforcing the configured limits
+    /// and the connection rate and concurrency of `limiter`, if set
+    pub fn send_request(
+        &self,
+        mut request: hyper::Request<HyperOutgoingBody>,
+        mut config: OutgoingRequestConfig,
+        limiter: Option<Arc<NetLimiter>>,
+        chaos: Option<Arc<Chaos>>,
+        trace: Option<Arc<Trace>>,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let (scheme, default_port) = if config.use_tls {
+            ("https", 443)
+        } else {
+            ("http", 80)
+        };
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or(ErrorCode::HttpRequestUriInvalid)?
+            .to_ascii_lowercase();
+        let port = uri.port_u16().unwrap_or(default_port);
+        if !self.is_allowed(scheme, &host, port) {
+            eprintln!("denied outgoing HTTP request to `{scheme}://{host}:{port}`");
+            return Err(ErrorCode::HttpRequestDenied.into());
+        }
+        if let Some(res) = trace
+            .as_ref()
+            .and_then(|trace| trace.replay_http(config.between_bytes_timeout))
+        {
+            return Ok(HostFutureIncomingResponse::ready(Ok(res)));
+        }
+        let proxy = self.proxy.get(config.use_tls, &host).cloned();
+        let rt = self.rt.clone();
+        let timeout = self.timeout;
+        if let Some(timeout) = timeout {
+            config.connect_timeout = config.connect_timeout.min(timeout);
+            config.first_byte_timeout = config.first_byte_timeout.min(timeout);
+            config.between_bytes_timeout = config.between_bytes_timeout.min(timeout);
+        }
+        let max_body_size = self.max_body_size;
+        if let Some(max) = max_body_size {
+            let len = request
+                .headers()
+                .get(hyper::header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            if let Some(len) = len.filter(|len| *len > max) {
+                return Err(ErrorCode::HttpRequestBodySize(Some(len)).into());
+            }
+            request = request.map(|body| limit(body, max, ErrorCode::HttpRequestBodySize(None)));
+        }
+        let span = info_span!(
+            "http_request",
+            method = %request.method(),
+            uri = %request.uri(),
+            status = tracing::field::Empty,
+            error = tracing::field::Empty,
+        );
+        // link requests into the trace of the instance if spans are exported
+        let cx = span.context();
+        if cx.span().span_context().is_valid() {
+            TraceContextPropagator::new()
+                .inject_context(&cx, &mut HeaderInjector(request.headers_mut()));
+        }
+        let handle = self.rt.spawn(
+            async move {
+                // held until response headers are received, the timeout does not include the wait
+                let _permit = match &limiter {
+                    Some(limiter) => limiter.acquire().await,
+                    None => None,
+                };
+                if let Some(chaos) = &chaos {
+                    if chaos.inject(Target::Http).await {
+                        return Ok(Err(ErrorCode::ConnectionRefused));
+                    }
+                }
+                let res = async {
+                    match &proxy {
+                        Some(proxy) => proxy::send_request(&rt, proxy, request, config).await,
+                        None => default_send_request_handler(request, config).await,
+                    }
+                };
+                let res = if let Some(timeout) = timeout {
+                    tokio::time::timeout(timeout, res)
+                        .await
+                        .unwrap_or(Err(ErrorCode::HttpResponseTimeout))
+                } else {
+                    res.await
+                };
+                let res = res.map(|mut res| {
+                    if let Some(max) = max_body_size {
+                        res.resp = res
+                            .resp
+                            .map(|body| limit(body, max, ErrorCode::HttpResponseBodySize(None)));
+                    }
+                    res
+                });
+                let res = match &trace {
+                    Some(trace) => trace.record_http(res).await,
+                    None => res,
+                };
+                match &res {
+                    Ok(res) => Span::current().record("status", res.resp.status().as_u16()),
+                    Err(err) => Span::current().record("error", tracing::field::display(err)),
+                };
+                Ok(res)
+            }
+            .instrument(span),
+        );
+        Ok(HostFutureIncomingResponse::pending(handle.into()))
+    }
+}
+
+/// Limits `body` to `max` bytes, returning `err` if the limit is exceeded
+fn limit(body: BoxBody<Bytes, ErrorCode>, max: u64, err: ErrorCode) -> BoxBody<Bytes, ErrorCode> {
+    Limited::new(body, max.try_into().unwrap_or(usize::MAX))
+        .map_err(move |e| match e.downcast::<ErrorCode>() {
+            Ok(e) => *e,
+            Err(_) => err.clone(),
+        })
+        .boxed()
+}
@@ -0,0 +1,152 @@
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _};
+use serde::Deserialize;
+use tokio::fs;
+use wasmtime_wasi::{DirPerms, FilePerms, WasiCtxBuilder};
+
+use crate::network::NetRule;
+
+/// Instance index range of the form `INDEX`, `START-END` or `START-`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IndexRange(RangeInclusive<usize>);
+
+impl FromStr for IndexRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<usize>()
+                .with_context(|| format!("invalid instance index `{v}` in `{s}`"))
+        };
+        let range = match s.split_once('-') {
+            Some((start, "")) => parse(start)?..=usize::MAX,
+            Some((start, end)) => parse(start)?..=parse(end)?,
+            None => parse(s)?..=parse(s)?,
+        };
+        ensure!(!range.is_empty(), "empty instance range `{s}`");
+        Ok(Self(range))
+    }
+}
+
+impl TryFrom<String> for IndexRange {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Host directory preopened for the guest
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preopen {
+    pub host: PathBuf,
+    pub guest: String,
+    /// Whether the guest may modify the directory
+    #[serde(default)]
+    pub writable: bool,
+}
+
+/// WASI capabilities and arguments of the instances in a range
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub instances: IndexRange,
+    /// Whether `wasi:sockets` and outgoing `wasi:http` requests are available,
+    /// subject to `--allow-net` and `--allow-http-host` respectively
+    #[serde(default = "enabled")]
+    pub network: bool,
+    /// Outbound socket destination rules replacing `--allow-net`, if set
+    pub allow_net: Option<Vec<NetRule>>,
+    /// Guest arguments following the program name
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Whether host environment variables are inherited
+    #[serde(default = "enabled")]
+    pub inherit_env: bool,
+    /// Environment variables set in addition to the inherited ones
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub preopens: Vec<Preopen>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Profile {
+    /// Configures capabilities on the builder, after the defaults shared by all instances
+    pub fn configure(&self, builder: &mut WasiCtxBuilder) -> anyhow::Result<()> {
+        if !self.network {
+            builder
+                .allow_tcp(false)
+                .allow_udp(false)
+                .allow_ip_name_lookup(false);
+        }
+        builder.args(&self.args);
+        for (k, v) in &self.env {
+            builder.env(k, v);
+        }
+        for Preopen {
+            host,
+            guest,
+            writable,
+        } in &self.preopens
+        {
+            let (dir_perms, file_perms) = if *writable {
+                (DirPerms::all(), FilePerms::all())
+            } else {
+                (DirPerms::READ, FilePerms::READ)
+            };
+            builder
+                .preopened_dir(host, guest, dir_perms, file_perms)
+                .with_context(|| format!("failed to preopen `{}`", host.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default, alias = "instance")]
+    profile: Vec<Profile>,
+}
+
+/// Per-instance WASI permission profiles, the first profile matching an instance applies
+#[derive(Clone, Debug, Default)]
+pub struct Profiles(Vec<Profile>);
+
+impl Profiles {
+    /// Loads `[[profile]]`, or equivalently `[[instance]]`, tables from a TOML file at `path`
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let buf = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        let File { profile } = toml::from_str(&buf)
+            .with_context(|| format!("failed to parse `{}`", path.display()))?;
+        for Profile { preopens, .. } in &profile {
+            for Preopen { host, .. } in preopens {
+                if !host.is_dir() {
+                    bail!("`{}` is not a directory", host.display());
+                }
+            }
+        }
+        Ok(Self(profile))
+    }
+
+    /// Returns the profile of instance at `index`, if any
+    pub fn get(&self, index: usize) -> Option<&Profile> {
+        self.0
+            .iter()
+            .find(|Profile { instances, .. }| instances.0.contains(&index))
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use wasmtime::PoolingAllocationConfig;
+
+use crate::caps::Cap;
+use crate::cgroup::{self, NameTemplate};
+use crate::mount::{RoDir, Size};
+use crate::network::NetRule;
+use crate::outgoing::HostRule;
+use crate::source::WasmSource;
+use crate::stdin::StdinConfig;
+
+/// cgroup tree to be created for the sandboxes
+pub struct CgroupPlan<'a> {
+    /// cgroup of the process, sandbox cgroups are created within
+    pub parent: &'a Path,
+    /// Prefix cgroup within `parent`
+    pub prefix: &'a str,
+    /// Threaded controllers, in `+controller` form, enabled in the prefix and sandbox cgroups
+    pub controllers: &'a str,
+    /// Controllers, in `+controller` form, to be enabled in `subtree_control` of `parent`
+    pub enabled: &'a str,
+    /// `cpu.weight` of the [`cgroup::CONTROL`] cgroup
+    pub control_cpu_weight: u16,
+    /// Template of the sandbox cgroup names within the prefix cgroup
+    pub name: &'a NameTemplate,
+}
+
+/// WASI capabilities granted to guests
+pub struct Capabilities<'a> {
+    pub allow_http_host: &'a [HostRule],
+    pub deny_http_host: &'a [HostRule],
+    pub allow_net: &'a [NetRule],
+    pub permission_profiles: Option<&'a Path>,
+    pub stdin: &'a StdinConfig,
+    pub tmpfs: Option<(&'a Size, &'a str)>,
+    pub ro_dir: &'a [RoDir],
+    pub keep_cap: &'a [Cap],
+}
+
+/// Computed plan of a run, printed by `--dry-run` instead of executing it
+pub struct Plan<'a> {
+    pub wasm: &'a WasmSource,
+    pub component: &'a str,
+    pub count: usize,
+    /// Pooling allocator configuration after environment overrides, if pooling is used
+    pub pooling: Option<&'a PoolingAllocationConfig>,
+    /// Name of the transient systemd scope the process would be moved into, if any
+    pub scope: Option<&'a str>,
+    pub cgroup: Option<CgroupPlan<'a>>,
+    pub capabilities: Capabilities<'a>,
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Plan<'_> {
+    /// Prints the plan to stdout
+    pub fn print(&self) {
+        println!("component: {}", self.wasm);
+        println!("count: {}", self.count);
+        if let Some(config) = self.pooling {
+            println!("allocator: pooling");
+            println!("{config:#?}");
+        } else {
+            println!("allocator: on-demand");
+        }
+        if let Some(scope) = self.scope {
+            println!("systemd scope: {scope}");
+        }
+        if let Some(CgroupPlan {
+            parent,
+            prefix,
+            controllers,
+            enabled,
+            control_cpu_weight,
+            name,
+        }) = &self.cgroup
+        {
+            if enabled.is_empty() {
+                println!("enable controllers in `{}`: none", parent.display());
+            } else {
+                println!("enable controllers in `{}`: {enabled}", parent.display());
+            }
+            println!("threaded controllers: {controllers}");
+            let prefix = parent.join(prefix);
+            println!("cgroup: {}", prefix.display());
+            println!(
+                "cgroup: {} (cpu.weight {control_cpu_weight})",
+                prefix.join(cgroup::CONTROL).display()
+            );
+            for i in 0..self.count {
+                println!(
+                    "cgroup: {}",
+                    prefix.join(name.render(self.component, i)).display()
+                );
+            }
+        } else {
+            println!("cgroups: off");
+        }
+
+        let Capabilities {
+            allow_http_host,
+            deny_http_host,
+            allow_net,
+            permission_profiles,
+            stdin,
+            tmpfs,
+            ro_dir,
+            keep_cap,
+        } = &self.capabilities;
+        if allow_http_host.is_empty() {
+            println!("wasi:http allow: all");
+        } else {
+            println!("wasi:http allow: {}", join(allow_http_host));
+        }
+        if !deny_http_host.is_empty() {
+            println!("wasi:http deny: {}", join(deny_http_host));
+        }
+        if allow_net.is_empty() {
+            println!("wasi:sockets allow: all");
+        } else {
+            println!("wasi:sockets allow: {}", join(allow_net));
+        }
+        if let Some(path) = permission_profiles {
+            println!("permission profiles: {}", path.display());
+        }
+        println!("stdin: {stdin}");
+        if let Some((size, dir)) = tmpfs {
+            println!("preopen: tmpfs of {size} bytes at `{dir}`");
+        }
+        for dir in *ro_dir {
+            println!("preopen: {dir} (read-only)");
+        }
+        if keep_cap.is_empty() {
+            println!("capabilities: none");
+        } else {
+            println!("capabilities: {}", join(keep_cap));
+        }
+    }
+}
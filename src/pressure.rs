@@ -0,0 +1,66 @@
+use core::time::Duration;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::cgroup;
+
+/// Pressure stall monitoring configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Monitor {
+    /// How often PSI files are polled
+    pub interval: Duration,
+    /// `some avg10` percentage above which instantiation of new instances is paused
+    pub threshold: Option<f64>,
+}
+
+/// Returns the highest `some avg10` CPU or memory pressure of the cgroup at `path`
+fn read_avg10(path: &Path) -> Option<f64> {
+    let cpu = cgroup::read_pressure(path.join("cpu.pressure")).ok();
+    let memory = cgroup::read_pressure(path.join("memory.pressure")).ok();
+    match (cpu, memory) {
+        (Some(cpu), Some(memory)) => Some(cpu.some.avg10.max(memory.some.avg10)),
+        (Some(pressure), None) | (None, Some(pressure)) => Some(pressure.some.avg10),
+        (None, None) => None,
+    }
+}
+
+impl Monitor {
+    /// Polls PSI files of the `cgwasm` cgroup at `cg` and the sandbox cgroups within it,
+    /// setting `throttle` while aggregate pressure stays above the threshold
+    pub async fn run(self, cg: Arc<Path>, names: Vec<String>, throttle: watch::Sender<bool>) {
+        let mut interval = tokio::time::interval(self.interval);
+        let mut above = vec![false; names.len()];
+        loop {
+            interval.tick().await;
+            for (index, name) in names.iter().enumerate() {
+                let Some(avg10) = read_avg10(&cg.join(name)) else {
+                    continue;
+                };
+                debug!(target: "pressure", instance = index, avg10);
+                let is_above = self.threshold.is_some_and(|threshold| avg10 >= threshold);
+                if is_above && !above[index] {
+                    warn!(target: "pressure", instance = index, avg10, "instance under pressure");
+                }
+                above[index] = is_above;
+            }
+            let Some(threshold) = self.threshold else {
+                continue;
+            };
+            let Some(avg10) = read_avg10(&cg) else {
+                continue;
+            };
+            let throttled = avg10 >= threshold;
+            if throttle.send_replace(throttled) != throttled {
+                if throttled {
+                    info!(target: "pressure", avg10, "pause instantiation");
+                } else {
+                    info!(target: "pressure", avg10, "resume instantiation");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use core::time::Duration;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+use anyhow::{bail, ensure, Context as _};
+use fxprof_processed_profile::{
+    CategoryHandle, Frame, FrameFlags, FrameInfo, ProcessHandle, Profile, ReferenceTimestamp,
+    SamplingInterval, ThreadHandle, Timestamp,
+};
+use nix::time::{clock_gettime, ClockId};
+use wasmtime::{ProfilingStrategy, WasmBacktrace};
+
+/// Profiling mode, one of `guest[,INTERVAL]`, `perfmap`, `jitdump` or `vtune`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileConfig {
+    /// Sample guest stacks of each instance at the interval
+    Guest(Duration),
+    /// Write a `/tmp/perf-PID.map` symbol map for `perf`
+    PerfMap,
+    /// Write a `jit-PID.dump` file for `perf inject`
+    JitDump,
+    /// Register generated code with VTune
+    VTune,
+}
+
+impl ProfileConfig {
+    /// Returns the profiling agent of the engine for native profilers
+    pub fn strategy(&self) -> ProfilingStrategy {
+        match self {
+            Self::Guest(..) => ProfilingStrategy::None,
+            Self::PerfMap => ProfilingStrategy::PerfMap,
+            Self::JitDump => ProfilingStrategy::JitDump,
+            Self::VTune => ProfilingStrategy::VTune,
+        }
+    }
+}
+
+impl FromStr for ProfileConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, interval) = match s.split_once(',') {
+            Some((mode, interval)) => (mode, Some(interval)),
+            None => (s, None),
+        };
+        match mode {
+            "guest" => {
+                let interval = interval
+                    .map(|interval| {
+                        humantime::parse_duration(interval)
+                            .with_context(|| format!("invalid interval `{interval}`"))
+                    })
+                    .transpose()?
+                    .unwrap_or(Duration::from_millis(10));
+                ensure!(!interval.is_zero(), "interval must not be zero");
+                Ok(Self::Guest(interval))
+            }
+            _ if interval.is_some() => bail!("profiling mode `{mode}` does not take an interval"),
+            "perfmap" => Ok(Self::PerfMap),
+            "jitdump" => Ok(Self::JitDump),
+            "vtune" => Ok(Self::VTune),
+            _ => bail!("unknown profiling mode `{mode}`"),
+        }
+    }
+}
+
+impl Display for ProfileConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Guest(interval) => {
+                write!(f, "guest,{}", humantime::format_duration(*interval))
+            }
+            Self::PerfMap => write!(f, "perfmap"),
+            Self::JitDump => write!(f, "jitdump"),
+            Self::VTune => write!(f, "vtune"),
+        }
+    }
+}
+
+/// Guest profiling configuration of sandboxes
+#[derive(Clone, Debug)]
+pub struct GuestProfile {
+    pub interval: Duration,
+    /// Directory to write profiles to as `INDEX.json`
+    pub dir: Arc<Path>,
+}
+
+/// Sampling profiler of a single instance, producing profiles in the Firefox profiler format.
+///
+/// [`wasmtime::GuestProfiler`] only resolves frames of core modules it is given, which cannot
+/// be obtained from a [`wasmtime::component::Component`], so frames are taken from
+/// [`WasmBacktrace`] instead. Samples are driven by the epoch ticks of the engine
+pub struct GuestProfiler {
+    profile: Profile,
+    process: ProcessHandle,
+    thread: ThreadHandle,
+    start: Instant,
+    /// CPU time of the sandbox thread at the previous sample
+    cpu: Duration,
+}
+
+/// Returns the CPU time consumed by the calling thread
+fn thread_cpu_time() -> Duration {
+    clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID)
+        .map(Duration::from)
+        .unwrap_or_default()
+}
+
+impl GuestProfiler {
+    pub fn new(name: &str, tid: u32, interval: Duration) -> Self {
+        let mut profile = Profile::new(
+            name,
+            ReferenceTimestamp::from_system_time(SystemTime::now()),
+            SamplingInterval::from(interval),
+        );
+        let zero = Timestamp::from_nanos_since_reference(0);
+        let process = profile.add_process(name, std::process::id(), zero);
+        let thread = profile.add_thread(process, tid, zero, true);
+        profile.set_thread_name(thread, name);
+        Self {
+            profile,
+            process,
+            thread,
+            start: Instant::now(),
+            cpu: thread_cpu_time(),
+        }
+    }
+
+    fn now(&self) -> Timestamp {
+        Timestamp::from_nanos_since_reference(
+            self.start
+                .elapsed()
+                .as_nanos()
+                .try_into()
+                .unwrap_or(u64::MAX),
+        )
+    }
+
+    /// Records the guest stack in `backtrace` as a sample with the CPU time consumed
+    /// since the previous one, must be called on the sandbox thread
+    pub fn sample(&mut self, backtrace: &WasmBacktrace) {
+        let now = self.now();
+        let cpu = thread_cpu_time();
+        let delta = cpu.saturating_sub(self.cpu);
+        self.cpu = cpu;
+        // the profile lists the oldest frame first
+        let frames: Vec<_> = backtrace
+            .frames()
+            .iter()
+            .rev()
+            .map(|frame| {
+                let module = frame.module().name().unwrap_or("<unknown>");
+                let label = if let Some(func) = frame.func_name() {
+                    format!("{module}!{func}")
+                } else {
+                    format!("{module}!wasm-function[{}]", frame.func_index())
+                };
+                FrameInfo {
+                    frame: Frame::Label(self.profile.intern_string(&label)),
+                    category_pair: CategoryHandle::OTHER.into(),
+                    flags: FrameFlags::empty(),
+                }
+            })
+            .collect();
+        self.profile
+            .add_sample(self.thread, now, frames.into_iter(), delta.into(), 1);
+    }
+
+    /// Writes the profile as JSON to `path`
+    pub fn finish(mut self, path: &Path) -> anyhow::Result<()> {
+        let now = self.now();
+        self.profile.set_thread_end_time(self.thread, now);
+        self.profile.set_process_end_time(self.process, now);
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create `{}`", path.display()))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), &self.profile)
+            .with_context(|| format!("failed to write profile to `{}`", path.display()))
+    }
+}
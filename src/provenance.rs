@@ -0,0 +1,101 @@
+use core::fmt::Write as _;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use sha2::{Digest as _, Sha256};
+use wasmparser::{KnownCustom, Parser, Payload};
+
+/// Custom sections of OCI image annotations, as embedded by `wasm-tools metadata add`
+const ANNOTATIONS: [(&str, &str); 7] = [
+    ("authors", "org.opencontainers.image.authors"),
+    ("description", "org.opencontainers.image.description"),
+    ("licenses", "org.opencontainers.image.licenses"),
+    ("source", "org.opencontainers.image.source"),
+    ("homepage", "org.opencontainers.image.url"),
+    ("revision", "org.opencontainers.image.revision"),
+    ("version", "org.opencontainers.image.version"),
+];
+
+/// Build metadata of a component, identifying exactly which build ran
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Provenance {
+    /// Digest of the component bytes, as read before composition, of the form `sha256:HEX`
+    pub digest: String,
+    /// Values of `producers` fields, like `language`, `processed-by` and `sdk`,
+    /// of the component and its nested modules, of the form `NAME VERSION`
+    pub producers: BTreeMap<String, BTreeSet<String>>,
+    /// OCI image annotations of the outermost component
+    pub annotations: BTreeMap<&'static str, String>,
+    /// Digests of components composed with it, by `--compose` path
+    /// or `--compose-config` package name
+    pub composed: BTreeMap<String, String>,
+}
+
+/// Returns the digest of `wasm` of the form `sha256:HEX`
+pub fn digest(wasm: &[u8]) -> String {
+    let mut digest = String::from("sha256:");
+    for b in Sha256::digest(wasm) {
+        _ = write!(digest, "{b:02x}");
+    }
+    digest
+}
+
+impl Provenance {
+    /// Extracts provenance metadata of the component in `wasm`, skipping malformed sections
+    pub fn read(wasm: &[u8]) -> Self {
+        let mut provenance = Self {
+            digest: digest(wasm),
+            ..Self::default()
+        };
+        let mut depth = 0usize;
+        for payload in Parser::new(0).parse_all(wasm) {
+            let Ok(payload) = payload else {
+                break;
+            };
+            match payload {
+                Payload::ModuleSection { .. } | Payload::ComponentSection { .. } => depth += 1,
+                Payload::End(..) => depth = depth.saturating_sub(1),
+                Payload::CustomSection(section) => {
+                    if let KnownCustom::Producers(reader) = section.as_known() {
+                        for field in reader.into_iter().flatten() {
+                            let values = provenance
+                                .producers
+                                .entry(field.name.to_string())
+                                .or_default();
+                            for value in field.values.into_iter().flatten() {
+                                values.insert(format!("{} {}", value.name, value.version));
+                            }
+                        }
+                    } else if depth == 0 {
+                        let annotation =
+                            ANNOTATIONS.iter().find(|(name, _)| *name == section.name());
+                        if let (Some((_, key)), Ok(value)) =
+                            (annotation, core::str::from_utf8(section.data()))
+                        {
+                            provenance.annotations.insert(key, value.to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        provenance
+    }
+
+    /// Prints the provenance to stdout
+    pub fn print(&self) {
+        println!("digest: {}", self.digest);
+        for (field, values) in &self.producers {
+            for value in values {
+                println!("producers: {field}: {value}");
+            }
+        }
+        for (key, value) in &self.annotations {
+            println!("annotation: {key}={value}");
+        }
+        for (name, digest) in &self.composed {
+            println!("composed: {name}: {digest}");
+        }
+    }
+}
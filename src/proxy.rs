@@ -0,0 +1,294 @@
+use core::str::FromStr;
+use core::time::Duration;
+
+use std::env;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context as _};
+use base64::Engine as _;
+use http_body_util::BodyExt as _;
+use hyper::client::conn::http1::{self, SendRequest};
+use hyper::header::{HeaderValue, PROXY_AUTHORIZATION};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+use tokio::time::timeout;
+use wasmtime_wasi::runtime::AbortOnDropJoinHandle;
+use wasmtime_wasi_http::bindings::http::types::{DnsErrorPayload, ErrorCode, FieldSizePayload};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::hyper_request_error;
+use wasmtime_wasi_http::types::{IncomingResponse, OutgoingRequestConfig};
+
+/// Maximum size of the response head to a `CONNECT` request
+const MAX_CONNECT_RESPONSE_SIZE: usize = 8192;
+
+/// HTTP proxy of the form `[http://][USER:PASSWORD@]HOST[:PORT]`, port 80 by default
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyUrl {
+    /// `HOST:PORT` to connect to
+    authority: String,
+    /// `Proxy-Authorization` header value, if credentials are set
+    authorization: Option<HeaderValue>,
+}
+
+impl FromStr for ProxyUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some((scheme, _)) => bail!("unsupported proxy scheme `{scheme}` in `{s}`"),
+            None => s,
+        };
+        let rest = rest.trim_end_matches('/');
+        let (userinfo, authority) = match rest.rsplit_once('@') {
+            Some((userinfo, authority)) => (Some(userinfo), authority),
+            None => (None, rest),
+        };
+        let uri = authority
+            .parse::<hyper::http::uri::Authority>()
+            .with_context(|| format!("invalid proxy address in `{s}`"))?;
+        ensure!(!uri.host().is_empty(), "empty proxy host in `{s}`");
+        let authority = format!("{}:{}", uri.host(), uri.port_u16().unwrap_or(80));
+        let authorization = userinfo
+            .map(|userinfo| {
+                let credentials = base64::engine::general_purpose::STANDARD.encode(userinfo);
+                HeaderValue::from_str(&format!("Basic {credentials}"))
+                    .with_context(|| format!("invalid proxy credentials in `{s}`"))
+            })
+            .transpose()?;
+        Ok(Self {
+            authority,
+            authorization,
+        })
+    }
+}
+
+/// Proxies used for guest outbound `wasi:http` requests
+#[derive(Clone, Debug, Default)]
+pub struct Proxy {
+    /// Proxy for `http` requests
+    pub http: Option<ProxyUrl>,
+    /// Proxy for `https` requests, tunneled using `CONNECT`
+    pub https: Option<ProxyUrl>,
+    /// Hosts and domains requests to which are sent directly, `*` matches all hosts
+    pub no_proxy: Vec<String>,
+}
+
+/// Returns the value of environment variable `name`, preferring the lowercase variant
+fn var(name: &str) -> Option<String> {
+    env::var(name.to_ascii_lowercase())
+        .or_else(|_| env::var(name))
+        .ok()
+        .filter(|v| !v.is_empty())
+}
+
+impl Proxy {
+    /// Fills settings not set from the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+    /// environment variables or their lowercase variants
+    pub fn with_env(mut self) -> anyhow::Result<Self> {
+        for (name, proxy) in [
+            ("HTTP_PROXY", &mut self.http),
+            ("HTTPS_PROXY", &mut self.https),
+        ] {
+            if let (None, Some(url)) = (&proxy, var(name)) {
+                *proxy = Some(url.parse().with_context(|| format!("invalid `{name}`"))?);
+            }
+        }
+        if let (true, Some(hosts)) = (self.no_proxy.is_empty(), var("NO_PROXY")) {
+            self.no_proxy = hosts.split(',').map(str::to_string).collect();
+        }
+        self.no_proxy = self
+            .no_proxy
+            .into_iter()
+            .map(|host| {
+                host.trim()
+                    .trim_start_matches("*.")
+                    .trim_start_matches('.')
+                    .to_ascii_lowercase()
+            })
+            .filter(|host| !host.is_empty())
+            .collect();
+        Ok(self)
+    }
+
+    /// Returns the proxy to send a request to lowercase `host` through, if any
+    pub fn get(&self, use_tls: bool, host: &str) -> Option<&ProxyUrl> {
+        let proxy = if use_tls { &self.https } else { &self.http };
+        let proxy = proxy.as_ref()?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let direct = self.no_proxy.iter().any(|no_proxy| {
+            no_proxy == "*"
+                || host
+                    .strip_suffix(no_proxy.as_str())
+                    .is_some_and(|sub| sub.is_empty() || sub.ends_with('.'))
+        });
+        (!direct).then_some(proxy)
+    }
+}
+
+/// Sends `request` through `proxy`, tunneling TLS connections using `CONNECT`.
+///
+/// The proxy is connected to and the connection is driven on the root runtime `rt`,
+/// within the host network namespace, since sandboxes have no network of their own
+pub async fn send_request(
+    rt: &Handle,
+    proxy: &ProxyUrl,
+    mut request: hyper::Request<HyperOutgoingBody>,
+    OutgoingRequestConfig {
+        use_tls,
+        connect_timeout,
+        first_byte_timeout,
+        between_bytes_timeout,
+    }: OutgoingRequestConfig,
+) -> Result<IncomingResponse, ErrorCode> {
+    let uri = request.uri();
+    let host = uri
+        .host()
+        .ok_or(ErrorCode::HttpRequestUriInvalid)?
+        .to_string();
+    let port = uri.port_u16().unwrap_or(if use_tls { 443 } else { 80 });
+    let authority = proxy.authority.clone();
+    let mut stream = timeout(
+        connect_timeout,
+        AbortOnDropJoinHandle::from(rt.spawn(TcpStream::connect(authority))),
+    )
+    .await
+    .map_err(|_| ErrorCode::ConnectionTimeout)?
+    .map_err(|_| ErrorCode::ConnectionRefused)?;
+    let (sender, worker) = if use_tls {
+        let authority = format!("{host}:{port}");
+        timeout(connect_timeout, connect(&mut stream, &authority, proxy))
+            .await
+            .map_err(|_| ErrorCode::ConnectionTimeout)??;
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+            })
+            .with_no_client_auth();
+        let domain = ServerName::try_from(host.trim_start_matches('[').trim_end_matches(']'))
+            .map_err(|_| {
+                ErrorCode::DnsError(DnsErrorPayload {
+                    rcode: Some("invalid dns name".to_string()),
+                    info_code: Some(0),
+                })
+            })?
+            .to_owned();
+        let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+            .connect(domain, stream)
+            .await
+            .map_err(|_| ErrorCode::TlsProtocolError)?;
+        // the request is sent through the tunnel, so only the path is included
+        *request.uri_mut() = hyper::Uri::builder()
+            .path_and_query(
+                request
+                    .uri()
+                    .path_and_query()
+                    .map_or("/", |path| path.as_str()),
+            )
+            .build()
+            .map_err(|_| ErrorCode::HttpRequestUriInvalid)?;
+        handshake(rt, stream, connect_timeout).await?
+    } else {
+        // plain requests are forwarded by the proxy using their absolute URI
+        if let Some(authorization) = &proxy.authorization {
+            request
+                .headers_mut()
+                .insert(PROXY_AUTHORIZATION, authorization.clone());
+        }
+        handshake(rt, stream, connect_timeout).await?
+    };
+    let resp = send(sender, request, first_byte_timeout).await?;
+    Ok(IncomingResponse {
+        resp,
+        worker: Some(worker),
+        between_bytes_timeout,
+    })
+}
+
+/// Establishes a tunnel to `authority` through `proxy` on `stream`
+async fn connect(
+    stream: &mut TcpStream,
+    authority: &str,
+    proxy: &ProxyUrl,
+) -> Result<(), ErrorCode> {
+    let mut req = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some(authorization) = proxy.authorization.as_ref().and_then(|v| v.to_str().ok()) {
+        req.push_str(&format!("Proxy-Authorization: {authorization}\r\n"));
+    }
+    req.push_str("\r\n");
+    stream
+        .write_all(req.as_bytes())
+        .await
+        .map_err(|_| ErrorCode::ConnectionTerminated)?;
+    // read byte by byte to not consume any of the tunneled data
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_CONNECT_RESPONSE_SIZE {
+            return Err(ErrorCode::HttpResponseHeaderSize(FieldSizePayload {
+                field_name: None,
+                field_size: Some(MAX_CONNECT_RESPONSE_SIZE as u32),
+            }));
+        }
+        let b = stream
+            .read_u8()
+            .await
+            .map_err(|_| ErrorCode::ConnectionTerminated)?;
+        head.push(b);
+    }
+    let status = head
+        .split(|b| *b == b' ')
+        .nth(1)
+        .and_then(|status| std::str::from_utf8(status).ok())
+        .and_then(|status| status.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(407) => {
+            eprintln!("proxy `{}` requires authentication", proxy.authority);
+            Err(ErrorCode::HttpRequestDenied)
+        }
+        Some(status) => {
+            eprintln!(
+                "proxy `{}` refused tunnel to `{authority}` with status {status}",
+                proxy.authority
+            );
+            Err(ErrorCode::DestinationUnavailable)
+        }
+        None => Err(ErrorCode::HttpProtocolError),
+    }
+}
+
+async fn handshake<S>(
+    rt: &Handle,
+    stream: S,
+    connect_timeout: Duration,
+) -> Result<(SendRequest<HyperOutgoingBody>, AbortOnDropJoinHandle<()>), ErrorCode>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (sender, conn) = timeout(connect_timeout, http1::handshake(TokioIo::new(stream)))
+        .await
+        .map_err(|_| ErrorCode::ConnectionTimeout)?
+        .map_err(hyper_request_error)?;
+    // connection errors surface as errors of the response body
+    let worker = rt
+        .spawn(async move {
+            _ = conn.await;
+        })
+        .into();
+    Ok((sender, worker))
+}
+
+async fn send(
+    mut sender: SendRequest<HyperOutgoingBody>,
+    request: hyper::Request<HyperOutgoingBody>,
+    first_byte_timeout: Duration,
+) -> Result<hyper::Response<wasmtime_wasi_http::body::HyperIncomingBody>, ErrorCode> {
+    let resp = timeout(first_byte_timeout, sender.send_request(request))
+        .await
+        .map_err(|_| ErrorCode::ConnectionReadTimeout)?
+        .map_err(hyper_request_error)?;
+    Ok(resp.map(|body| body.map_err(hyper_request_error).boxed()))
+}
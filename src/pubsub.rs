@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+use wasmtime::component::{Linker, Resource, ResourceTable};
+
+use bindings::cgwasm::cluster::pubsub::{self, Message};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit",
+        world: "cgwasm:cluster/imports",
+        trappable_imports: true,
+        async: {
+            only_imports: ["[method]subscription.next"],
+        },
+        with: {
+            "cgwasm:cluster/pubsub/subscription": super::Subscription,
+        },
+    });
+}
+
+/// In-process message broker shared by all instances
+pub struct Broker {
+    capacity: usize,
+    topics: Mutex<HashMap<String, broadcast::Sender<Message>>>,
+}
+
+impl Broker {
+    /// Creates a broker buffering up to `capacity` messages per topic
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            topics: Mutex::default(),
+        }
+    }
+
+    fn topic(&self, topic: String) -> broadcast::Sender<Message> {
+        let mut topics = self.topics.lock().unwrap_or_else(|err| err.into_inner());
+        topics
+            .entry(topic)
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .clone()
+    }
+}
+
+/// `cgwasm:cluster/pubsub` subscription resource
+pub struct Subscription(broadcast::Receiver<Message>);
+
+/// Per-instance `cgwasm:cluster` state
+#[derive(Clone)]
+pub struct PubsubCtx {
+    broker: Arc<Broker>,
+    index: usize,
+}
+
+impl PubsubCtx {
+    pub fn new(broker: Arc<Broker>, index: usize) -> Self {
+        Self { broker, index }
+    }
+}
+
+/// A view into the `cgwasm:cluster` state of an instance
+pub struct Pubsub<'a> {
+    ctx: &'a PubsubCtx,
+    table: &'a mut ResourceTable,
+}
+
+impl<'a> Pubsub<'a> {
+    pub fn new(ctx: &'a PubsubCtx, table: &'a mut ResourceTable) -> Self {
+        Self { ctx, table }
+    }
+}
+
+impl pubsub::Host for Pubsub<'_> {
+    fn subscribe(&mut self, topic: String) -> wasmtime::Result<Resource<Subscription>> {
+        let rx = self.ctx.broker.topic(topic).subscribe();
+        Ok(self.table.push(Subscription(rx))?)
+    }
+
+    fn publish(&mut self, topic: String, payload: Vec<u8>) -> wasmtime::Result<u32> {
+        let tx = self.ctx.broker.topic(topic.clone());
+        let msg = Message {
+            topic,
+            sender: self.ctx.index.try_into()?,
+            payload,
+        };
+        // sending only fails if there are no subscriptions
+        let n = tx.send(msg).unwrap_or_default();
+        Ok(n.try_into().unwrap_or(u32::MAX))
+    }
+}
+
+#[async_trait::async_trait]
+impl pubsub::HostSubscription for Pubsub<'_> {
+    async fn next(&mut self, sub: Resource<Subscription>) -> wasmtime::Result<Message> {
+        let Subscription(rx) = self.table.get_mut(&sub)?;
+        loop {
+            match rx.recv().await {
+                Ok(msg) => return Ok(msg),
+                Err(RecvError::Lagged(..)) => continue,
+                // the broker keeps senders of all topics alive
+                Err(RecvError::Closed) => return Ok(std::future::pending().await),
+            }
+        }
+    }
+
+    fn try_next(&mut self, sub: Resource<Subscription>) -> wasmtime::Result<Option<Message>> {
+        let Subscription(rx) = self.table.get_mut(&sub)?;
+        loop {
+            match rx.try_recv() {
+                Ok(msg) => return Ok(Some(msg)),
+                Err(TryRecvError::Lagged(..)) => continue,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return Ok(None),
+            }
+        }
+    }
+
+    fn drop(&mut self, sub: Resource<Subscription>) -> wasmtime::Result<()> {
+        self.table.delete(sub)?;
+        Ok(())
+    }
+}
+
+/// Adds `cgwasm:cluster` interfaces to the linker
+pub fn add_to_linker<T: Send>(
+    linker: &mut Linker<T>,
+    f: impl Fn(&mut T) -> Pubsub<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    pubsub::add_to_linker_get_host(linker, f)
+}
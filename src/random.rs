@@ -0,0 +1,79 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use anyhow::{bail, Context as _};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use wasmtime_wasi::WasiCtxBuilder;
+
+/// Source of guest `wasi:random`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RandomConfig {
+    /// Host entropy
+    #[default]
+    System,
+    /// Generators seeded by the same seed in all instances
+    Seed(u64),
+    /// Generators seeded by the seed plus the instance index
+    PerInstanceSeed(u64),
+}
+
+fn parse_seed(seed: &str) -> anyhow::Result<u64> {
+    let hex = seed.strip_prefix("0x").unwrap_or(seed);
+    u64::from_str_radix(hex, 16).with_context(|| format!("invalid hexadecimal seed `{seed}`"))
+}
+
+impl FromStr for RandomConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "system" => Ok(Self::System),
+            None if s == "per-instance-seed" => Ok(Self::PerInstanceSeed(0)),
+            Some(("seed", seed)) => parse_seed(seed).map(Self::Seed),
+            Some(("per-instance-seed", seed)) => parse_seed(seed).map(Self::PerInstanceSeed),
+            _ => bail!(
+                "invalid random `{s}`, expected `system`, `seed:HEX` or `per-instance-seed[:HEX]`"
+            ),
+        }
+    }
+}
+
+impl Display for RandomConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::System => write!(f, "system"),
+            Self::Seed(seed) => write!(f, "seed:{seed:x}"),
+            Self::PerInstanceSeed(seed) => write!(f, "per-instance-seed:{seed:x}"),
+        }
+    }
+}
+
+impl RandomConfig {
+    /// Whether guest randomness is reproducible across runs
+    pub fn is_deterministic(self) -> bool {
+        self != Self::System
+    }
+
+    /// Returns the seed of instance at `index`, if any
+    pub fn seed(self, index: usize) -> Option<u64> {
+        match self {
+            Self::System => None,
+            Self::Seed(seed) => Some(seed),
+            Self::PerInstanceSeed(seed) => Some(seed.wrapping_add(index as u64)),
+        }
+    }
+
+    /// Configures random generators of instance at `index` on the builder.
+    ///
+    /// Host entropy is left untouched for [`RandomConfig::System`]
+    pub fn configure(self, index: usize, builder: &mut WasiCtxBuilder) {
+        let Some(seed) = self.seed(index) else {
+            return;
+        };
+        builder
+            .secure_random(StdRng::seed_from_u64(seed))
+            .insecure_random(StdRng::seed_from_u64(seed))
+            .insecure_random_seed(seed.into());
+    }
+}
@@ -0,0 +1,308 @@
+use core::num::NonZeroUsize;
+use core::time::Duration;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{ensure, Context as _};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use wasmtime::component::{Linker, Resource};
+use wasmtime_wasi::bindings::io::streams::{InputStream, OutputStream};
+use wasmtime_wasi::bindings::sockets::network::{IpAddressFamily, IpSocketAddress, Network};
+use wasmtime_wasi::bindings::sockets::tcp::{self, HostTcpSocket, ShutdownType, TcpSocket};
+use wasmtime_wasi::{Pollable, SocketResult, WasiImpl};
+
+use crate::Ctx;
+
+/// Parses a positive number of connections per second
+pub fn parse_rate(s: &str) -> anyhow::Result<f64> {
+    let rate = s
+        .parse::<f64>()
+        .with_context(|| format!("invalid rate `{s}`"))?;
+    ensure!(
+        rate.is_finite() && rate > 0.,
+        "rate `{s}` must be a positive number"
+    );
+    // the interval must be representable and must not overflow once added to the current time
+    let interval = Duration::try_from_secs_f64(1. / rate).ok();
+    ensure!(
+        interval.is_some_and(|interval| Instant::now().checked_add(interval).is_some()),
+        "rate `{s}` is too low"
+    );
+    Ok(rate)
+}
+
+/// Outbound connection limits of a single instance
+#[derive(Debug)]
+pub struct NetLimiter {
+    /// Minimum interval between connections
+    interval: Option<Duration>,
+    /// Point in time the next connection may be made at
+    next: Mutex<Instant>,
+    /// Permits for concurrent outgoing HTTP requests and TCP connections
+    concurrent: Option<Arc<Semaphore>>,
+    /// Permits held by TCP sockets by resource representation, until the socket is dropped
+    sockets: Mutex<HashMap<u32, OwnedSemaphorePermit>>,
+}
+
+impl NetLimiter {
+    /// Creates limits allowing `rate` connections per second, which must have been
+    /// validated by [`parse_rate`], and `max_concurrent` connections in flight, if set
+    pub fn new(rate: Option<f64>, max_concurrent: Option<NonZeroUsize>) -> Self {
+        Self {
+            interval: rate.map(|rate| Duration::from_secs_f64(1. / rate)),
+            next: Mutex::new(Instant::now()),
+            concurrent: max_concurrent.map(|max| Arc::new(Semaphore::new(max.get()))),
+            sockets: Mutex::default(),
+        }
+    }
+
+    /// Waits until the rate allows another connection
+    pub async fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let at = {
+            let mut next = self.next.lock().unwrap_or_else(|err| err.into_inner());
+            let at = (*next).max(Instant::now());
+            *next = at + interval;
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+    }
+
+    /// Waits for a free concurrency slot, which is held until the permit is dropped
+    async fn permit(&self) -> Option<OwnedSemaphorePermit> {
+        let concurrent = Arc::clone(self.concurrent.as_ref()?);
+        // the semaphore is never closed
+        concurrent.acquire_owned().await.ok()
+    }
+
+    /// Waits for the rate and a free concurrency slot, which is held until the permit is dropped
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.wait().await;
+        self.permit().await
+    }
+
+    /// Holds `permit` until the TCP socket `rep` is [released](Self::release)
+    fn hold(&self, rep: u32, permit: OwnedSemaphorePermit) {
+        let mut sockets = self.sockets.lock().unwrap_or_else(|err| err.into_inner());
+        sockets.insert(rep, permit);
+    }
+
+    /// Releases the permit held by the TCP socket `rep`, if any
+    fn release(&self, rep: u32) {
+        let mut sockets = self.sockets.lock().unwrap_or_else(|err| err.into_inner());
+        sockets.remove(&rep);
+    }
+}
+
+/// `wasi:sockets/tcp` implementation holding a concurrency slot of the [`NetLimiter`]
+/// of the instance for each connecting or connected socket until it is dropped.
+///
+/// The rate of connects is limited by the socket address check of the instance
+pub struct Tcp<'a> {
+    wasi: WasiImpl<&'a mut Ctx>,
+    limiter: Option<Arc<NetLimiter>>,
+}
+
+impl<'a> Tcp<'a> {
+    pub fn new(ctx: &'a mut Ctx) -> Self {
+        let limiter = ctx.net_limiter.clone();
+        Self {
+            wasi: WasiImpl(ctx),
+            limiter,
+        }
+    }
+}
+
+/// Replaces `wasi:sockets/tcp` in the linker with one limiting concurrent connections
+pub fn add_to_linker(
+    linker: &mut Linker<Ctx>,
+    f: impl Fn(&mut Ctx) -> Tcp<'_> + Send + Sync + Copy + 'static,
+) -> anyhow::Result<()> {
+    linker.allow_shadowing(true);
+    let res = tcp::add_to_linker_get_host(linker, f);
+    linker.allow_shadowing(false);
+    res
+}
+
+impl tcp::Host for Tcp<'_> {}
+
+#[async_trait::async_trait]
+impl HostTcpSocket for Tcp<'_> {
+    async fn start_bind(
+        &mut self,
+        this: Resource<TcpSocket>,
+        network: Resource<Network>,
+        local_address: IpSocketAddress,
+    ) -> SocketResult<()> {
+        self.wasi.start_bind(this, network, local_address).await
+    }
+
+    fn finish_bind(&mut self, this: Resource<TcpSocket>) -> SocketResult<()> {
+        self.wasi.finish_bind(this)
+    }
+
+    async fn start_connect(
+        &mut self,
+        this: Resource<TcpSocket>,
+        network: Resource<Network>,
+        remote_address: IpSocketAddress,
+    ) -> SocketResult<()> {
+        let Some(limiter) = self.limiter.clone() else {
+            return self.wasi.start_connect(this, network, remote_address).await;
+        };
+        let rep = this.rep();
+        let permit = limiter.permit().await;
+        self.wasi
+            .start_connect(this, network, remote_address)
+            .await?;
+        if let Some(permit) = permit {
+            limiter.hold(rep, permit);
+        }
+        Ok(())
+    }
+
+    fn finish_connect(
+        &mut self,
+        this: Resource<TcpSocket>,
+    ) -> SocketResult<(Resource<InputStream>, Resource<OutputStream>)> {
+        self.wasi.finish_connect(this)
+    }
+
+    fn start_listen(&mut self, this: Resource<TcpSocket>) -> SocketResult<()> {
+        self.wasi.start_listen(this)
+    }
+
+    fn finish_listen(&mut self, this: Resource<TcpSocket>) -> SocketResult<()> {
+        self.wasi.finish_listen(this)
+    }
+
+    fn accept(
+        &mut self,
+        this: Resource<TcpSocket>,
+    ) -> SocketResult<(
+        Resource<TcpSocket>,
+        Resource<InputStream>,
+        Resource<OutputStream>,
+    )> {
+        self.wasi.accept(this)
+    }
+
+    fn local_address(&mut self, this: Resource<TcpSocket>) -> SocketResult<IpSocketAddress> {
+        self.wasi.local_address(this)
+    }
+
+    fn remote_address(&mut self, this: Resource<TcpSocket>) -> SocketResult<IpSocketAddress> {
+        self.wasi.remote_address(this)
+    }
+
+    fn is_listening(&mut self, this: Resource<TcpSocket>) -> anyhow::Result<bool> {
+        self.wasi.is_listening(this)
+    }
+
+    fn address_family(&mut self, this: Resource<TcpSocket>) -> anyhow::Result<IpAddressFamily> {
+        self.wasi.address_family(this)
+    }
+
+    fn set_listen_backlog_size(
+        &mut self,
+        this: Resource<TcpSocket>,
+        value: u64,
+    ) -> SocketResult<()> {
+        self.wasi.set_listen_backlog_size(this, value)
+    }
+
+    fn keep_alive_enabled(&mut self, this: Resource<TcpSocket>) -> SocketResult<bool> {
+        self.wasi.keep_alive_enabled(this)
+    }
+
+    fn set_keep_alive_enabled(
+        &mut self,
+        this: Resource<TcpSocket>,
+        value: bool,
+    ) -> SocketResult<()> {
+        self.wasi.set_keep_alive_enabled(this, value)
+    }
+
+    fn keep_alive_idle_time(&mut self, this: Resource<TcpSocket>) -> SocketResult<u64> {
+        self.wasi.keep_alive_idle_time(this)
+    }
+
+    fn set_keep_alive_idle_time(
+        &mut self,
+        this: Resource<TcpSocket>,
+        value: u64,
+    ) -> SocketResult<()> {
+        self.wasi.set_keep_alive_idle_time(this, value)
+    }
+
+    fn keep_alive_interval(&mut self, this: Resource<TcpSocket>) -> SocketResult<u64> {
+        self.wasi.keep_alive_interval(this)
+    }
+
+    fn set_keep_alive_interval(
+        &mut self,
+        this: Resource<TcpSocket>,
+        value: u64,
+    ) -> SocketResult<()> {
+        self.wasi.set_keep_alive_interval(this, value)
+    }
+
+    fn keep_alive_count(&mut self, this: Resource<TcpSocket>) -> SocketResult<u32> {
+        self.wasi.keep_alive_count(this)
+    }
+
+    fn set_keep_alive_count(&mut self, this: Resource<TcpSocket>, value: u32) -> SocketResult<()> {
+        self.wasi.set_keep_alive_count(this, value)
+    }
+
+    fn hop_limit(&mut self, this: Resource<TcpSocket>) -> SocketResult<u8> {
+        self.wasi.hop_limit(this)
+    }
+
+    fn set_hop_limit(&mut self, this: Resource<TcpSocket>, value: u8) -> SocketResult<()> {
+        self.wasi.set_hop_limit(this, value)
+    }
+
+    fn receive_buffer_size(&mut self, this: Resource<TcpSocket>) -> SocketResult<u64> {
+        self.wasi.receive_buffer_size(this)
+    }
+
+    fn set_receive_buffer_size(
+        &mut self,
+        this: Resource<TcpSocket>,
+        value: u64,
+    ) -> SocketResult<()> {
+        self.wasi.set_receive_buffer_size(this, value)
+    }
+
+    fn send_buffer_size(&mut self, this: Resource<TcpSocket>) -> SocketResult<u64> {
+        self.wasi.send_buffer_size(this)
+    }
+
+    fn set_send_buffer_size(&mut self, this: Resource<TcpSocket>, value: u64) -> SocketResult<()> {
+        self.wasi.set_send_buffer_size(this, value)
+    }
+
+    fn subscribe(&mut self, this: Resource<TcpSocket>) -> anyhow::Result<Resource<Pollable>> {
+        self.wasi.subscribe(this)
+    }
+
+    fn shutdown(
+        &mut self,
+        this: Resource<TcpSocket>,
+        shutdown_type: ShutdownType,
+    ) -> SocketResult<()> {
+        self.wasi.shutdown(this, shutdown_type)
+    }
+
+    fn drop(&mut self, this: Resource<TcpSocket>) -> anyhow::Result<()> {
+        if let Some(limiter) = &self.limiter {
+            limiter.release(this.rep());
+        }
+        HostTcpSocket::drop(&mut self.wasi, this)
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::provenance::Provenance;
+use crate::usage::StoreUsage;
+use crate::{cgroup, Outcome};
+
+/// Memory usage of the whole process, which is shared by all sandboxes.
+///
+/// It is not attributable to any single instance, so it is only reported once per run,
+/// per-instance usage is the [`StoreUsage`] of each instance
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ProcessMemory {
+    pub rss: u64,
+    /// Resident memory shared with other mappings, like copy-on-write memory images
+    pub shared: u64,
+    /// Resident memory private to the process
+    pub private: u64,
+}
+
+impl ProcessMemory {
+    /// Reads memory usage of the process from `/proc/self/smaps_rollup`
+    pub fn read() -> std::io::Result<Self> {
+        let s = std::fs::read_to_string("/proc/self/smaps_rollup")?;
+        let mut memory = Self::default();
+        for line in s.lines() {
+            let Some((k, v)) = line.split_once(':') else {
+                continue;
+            };
+            let Ok(kib) = v.trim().trim_end_matches("kB").trim().parse::<u64>() else {
+                continue;
+            };
+            let bytes = kib.saturating_mul(1024);
+            match k {
+                "Rss" => memory.rss = bytes,
+                "Shared_Clean" | "Shared_Dirty" => memory.shared += bytes,
+                "Private_Clean" | "Private_Dirty" => memory.private += bytes,
+                _ => {}
+            }
+        }
+        Ok(memory)
+    }
+
+    /// Returns the sample with the largest RSS taken when `instances` completed
+    pub fn max(instances: &[(Outcome, Stats)]) -> Option<Self> {
+        instances
+            .iter()
+            .filter_map(|(_, stats)| stats.process_sample)
+            .max_by_key(|memory| memory.rss)
+    }
+}
+
+/// Statistics collected for a single sandbox instance
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub cgroup: PathBuf,
+    pub tid: Option<libc::pid_t>,
+    pub instantiate: Option<Duration>,
+    pub run: Option<Duration>,
+    pub cpu_stat: Option<BTreeMap<String, u64>>,
+    pub memory_peak: Option<u64>,
+    pub cpu_pressure: Option<cgroup::Pressure>,
+    pub memory_pressure: Option<cgroup::Pressure>,
+    /// Memory usage of the process sampled when the instance completed, while its store was alive,
+    /// only aggregated across instances by [`ProcessMemory::max`]
+    process_sample: Option<ProcessMemory>,
+    /// Linear memory and table sizes of the store when the instance completed,
+    /// to compare with the cgroup memory usage
+    pub store: Option<StoreUsage>,
+    /// Number of times the instance was restarted within its sandbox
+    pub restarts: u32,
+}
+
+impl Stats {
+    pub fn new(cgroup: PathBuf) -> Self {
+        Self {
+            cgroup,
+            ..Self::default()
+        }
+    }
+
+    /// Records the `cpu.stat` snapshot to compute deltas against
+    pub fn start(&mut self) {
+        self.cpu_stat = cgroup::read_flat_keyed(self.cgroup.join("cpu.stat")).ok();
+    }
+
+    /// Samples memory usage of the process
+    pub fn sample_memory(&mut self) {
+        self.process_sample = ProcessMemory::read().ok();
+    }
+
+    /// Replaces the snapshots by deltas and samples peak memory usage and pressure
+    pub fn finish(&mut self) {
+        self.cpu_stat = self.cpu_stat.take().and_then(|start| {
+            let end = cgroup::read_flat_keyed(self.cgroup.join("cpu.stat")).ok()?;
+            Some(
+                end.into_iter()
+                    .map(|(k, v)| {
+                        let v = v.saturating_sub(start.get(&k).copied().unwrap_or_default());
+                        (k, v)
+                    })
+                    .collect(),
+            )
+        });
+        // `memory` is not a threaded controller, so these are usually only available
+        // if the sandbox cgroups are domain cgroups
+        self.memory_peak = cgroup::read_u64(self.cgroup.join("memory.peak"))
+            .or_else(|_| cgroup::read_u64(self.cgroup.join("memory.current")))
+            .ok();
+        self.cpu_pressure = cgroup::read_pressure(self.cgroup.join("cpu.pressure")).ok();
+        self.memory_pressure = cgroup::read_pressure(self.cgroup.join("memory.pressure")).ok();
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Instance<'a> {
+    index: usize,
+    tid: Option<libc::pid_t>,
+    cgroup: &'a Path,
+    instantiate_usec: Option<u128>,
+    run_usec: Option<u128>,
+    status: &'static str,
+    exit_code: Option<i32>,
+    error: Option<String>,
+    memory_peak: Option<u64>,
+    cpu_stat: Option<&'a BTreeMap<String, u64>>,
+    cpu_pressure: Option<cgroup::Pressure>,
+    memory_pressure: Option<cgroup::Pressure>,
+    store: Option<StoreUsage>,
+    restarts: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    /// Build metadata of the component all instances ran
+    provenance: Option<&'a Provenance>,
+    /// Memory usage of the whole process, shared by all instances
+    process_memory: Option<ProcessMemory>,
+    /// OOM kills in the domain cgroup of the process during the run, see [`read_oom_kill`]
+    oom_kill: Option<u64>,
+    instances: Vec<Instance<'a>>,
+}
+
+/// Reads `oom_kill` of `memory.events` of `cg` or its closest ancestor with the `memory`
+/// controller.
+///
+/// `memory` is not a threaded controller, so OOM kills are counted in the domain cgroup
+/// holding the whole process. They are process-level events: an OOM kill of a sandbox thread
+/// takes down the whole process, other kills cannot be attributed to any instance
+pub fn read_oom_kill(cg: &Path) -> Option<u64> {
+    let events = cg
+        .ancestors()
+        .find_map(|dir| cgroup::read_flat_keyed(dir.join("memory.events")).ok())?;
+    events.get("oom_kill").copied()
+}
+
+/// Writes a JSON report of the component `provenance`, OOM kills during the run
+/// and instance outcomes to `path`
+pub async fn write(
+    path: &Path,
+    provenance: Option<&Provenance>,
+    oom_kill: Option<u64>,
+    instances: &[(Outcome, Stats)],
+) -> anyhow::Result<()> {
+    let instances = instances
+        .iter()
+        .enumerate()
+        .map(|(index, (outcome, stats))| Instance {
+            index,
+            tid: stats.tid,
+            cgroup: &stats.cgroup,
+            instantiate_usec: stats.instantiate.as_ref().map(Duration::as_micros),
+            run_usec: stats.run.as_ref().map(Duration::as_micros),
+            status: outcome.status(),
+            exit_code: if let Outcome::Exit(code) = outcome {
+                Some(*code)
+            } else {
+                None
+            },
+            error: match outcome {
+                Outcome::Error(err) | Outcome::PoolExhausted(err) => Some(format!("{err:#}")),
+                Outcome::Panicked(msg) => Some(msg.clone()),
+                _ => None,
+            },
+            memory_peak: stats.memory_peak,
+            cpu_stat: stats.cpu_stat.as_ref(),
+            cpu_pressure: stats.cpu_pressure,
+            memory_pressure: stats.memory_pressure,
+            store: stats.store,
+            restarts: stats.restarts,
+        })
+        .collect();
+    let buf = serde_json::to_vec_pretty(&Report {
+        provenance,
+        process_memory: ProcessMemory::max(instances),
+        oom_kill,
+        instances,
+    })
+    .context("failed to encode report")?;
+    fs::write(path, buf)
+        .await
+        .with_context(|| format!("failed to write report to `{}`", path.display()))
+}
@@ -0,0 +1,48 @@
+use anyhow::Context as _;
+
+/// Scheduling policy of sandbox threads
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Policy {
+    /// Default time-sharing policy
+    #[default]
+    Other,
+    /// CPU-bound batch work, never preempting other threads on wakeup
+    Batch,
+    /// Only runs when the CPU would otherwise be idle
+    Idle,
+}
+
+/// Scheduling attributes applied to each sandbox thread
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sched {
+    /// Nice value from -20 to 19, unchanged if not set
+    pub nice: Option<i8>,
+    pub policy: Policy,
+}
+
+impl Sched {
+    /// Applies the attributes to the calling thread, inherited by threads it spawns.
+    ///
+    /// Negative nice values require `CAP_SYS_NICE`
+    pub fn apply(&self) -> anyhow::Result<()> {
+        if let Some(nice) = self.nice {
+            // on Linux, a TID passed as the PID sets the nice value of that thread only
+            let tid = unsafe { libc::gettid() };
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as _, nice.into()) } == -1 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("failed to set nice value `{nice}`"));
+            }
+        }
+        let (policy, name) = match self.policy {
+            Policy::Other => return Ok(()),
+            Policy::Batch => (libc::SCHED_BATCH, "batch"),
+            Policy::Idle => (libc::SCHED_IDLE, "idle"),
+        };
+        let param = libc::sched_param { sched_priority: 0 };
+        if unsafe { libc::sched_setscheduler(0, policy, &param) } == -1 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to set scheduling policy to `{name}`"));
+        }
+        Ok(())
+    }
+}
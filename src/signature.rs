@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context as _};
+use base64::Engine as _;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use tokio::fs;
+
+/// Reads a file at `path` containing `N` bytes, either raw or base64-encoded
+async fn read_bytes<const N: usize>(path: &Path) -> anyhow::Result<[u8; N]> {
+    let buf = fs::read(path)
+        .await
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    let buf = if buf.len() == N {
+        buf
+    } else {
+        base64::engine::general_purpose::STANDARD
+            .decode(buf.trim_ascii())
+            .with_context(|| format!("`{}` is neither {N} raw bytes nor base64", path.display()))?
+    };
+    let len = buf.len();
+    buf.try_into()
+        .map_err(|_| anyhow::anyhow!("`{}` contains {len} bytes, expected {N}", path.display()))
+}
+
+/// Returns the path of the detached signature of the file at `path`, `PATH.sig`
+pub fn default_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".sig");
+    path.into()
+}
+
+/// Verifier of detached Ed25519 signatures over component bytes
+pub struct Verifier {
+    key: VerifyingKey,
+}
+
+impl Verifier {
+    /// Loads the trusted public key from `path`
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let key = read_bytes::<PUBLIC_KEY_LENGTH>(path).await?;
+        let key = VerifyingKey::from_bytes(&key)
+            .with_context(|| format!("`{}` is not a valid Ed25519 key", path.display()))?;
+        Ok(Self { key })
+    }
+
+    /// Verifies the signature at `signature` over `wasm`, refusing weak keys and
+    /// non-canonical signatures
+    pub async fn verify(&self, wasm: &[u8], signature: &Path) -> anyhow::Result<()> {
+        let sig = read_bytes::<SIGNATURE_LENGTH>(signature).await?;
+        let sig = Signature::from_bytes(&sig);
+        ensure!(
+            self.key.verify_strict(wasm, &sig).is_ok(),
+            "signature `{}` does not match the trusted key",
+            signature.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::os::fd::{BorrowedFd, RawFd};
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use tokio::fs;
+use tokio::io::{self, AsyncReadExt as _};
+
+use crate::signature;
+
+/// Source of the component bytes, a path, `-` for stdin or `fd:N` for an inherited descriptor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WasmSource {
+    Path(PathBuf),
+    Stdin,
+    Fd(RawFd),
+}
+
+impl FromStr for WasmSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "-" => Ok(Self::Stdin),
+            _ => match s.strip_prefix("fd:") {
+                Some(fd) => {
+                    let fd = fd
+                        .parse::<RawFd>()
+                        .ok()
+                        // borrowing a negative descriptor is undefined behavior
+                        .filter(|fd| *fd >= 0)
+                        .with_context(|| format!("invalid file descriptor `{fd}`"))?;
+                    Ok(Self::Fd(fd))
+                }
+                None => Ok(Self::Path(s.into())),
+            },
+        }
+    }
+}
+
+impl Display for WasmSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Stdin => write!(f, "-"),
+            Self::Fd(fd) => write!(f, "fd:{fd}"),
+        }
+    }
+}
+
+impl WasmSource {
+    /// Whether the component is read from host stdin
+    pub fn is_stdin(&self) -> bool {
+        matches!(self, Self::Stdin)
+    }
+
+    /// Returns the name of the component, the file stem of paths
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Path(path) => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("component"),
+            Self::Stdin | Self::Fd(..) => "component",
+        }
+    }
+
+    /// Returns the path of the detached signature of the component, if it is read from a path
+    pub fn signature_path(&self) -> Option<PathBuf> {
+        match self {
+            Self::Path(path) => Some(signature::default_path(path)),
+            Self::Stdin | Self::Fd(..) => None,
+        }
+    }
+
+    /// Reads the component bytes until EOF
+    pub async fn read(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self {
+            Self::Path(path) => {
+                return fs::read(path)
+                    .await
+                    .with_context(|| format!("failed to read `{}`", path.display()));
+            }
+            Self::Stdin => {
+                io::stdin()
+                    .read_to_end(&mut buf)
+                    .await
+                    .context("failed to read stdin")?;
+            }
+            Self::Fd(fd) => {
+                // the inherited descriptor is duplicated, so it is never closed by the reader
+                let fd = unsafe { BorrowedFd::borrow_raw(*fd) }
+                    .try_clone_to_owned()
+                    .with_context(|| format!("failed to duplicate `{self}`"))?;
+                fs::File::from_std(fd.into())
+                    .read_to_end(&mut buf)
+                    .await
+                    .with_context(|| format!("failed to read `{self}`"))?;
+            }
+        }
+        Ok(buf)
+    }
+}
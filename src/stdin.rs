@@ -0,0 +1,105 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context as _};
+use bytes::Bytes;
+use tokio::fs;
+use wasmtime_wasi::pipe::MemoryInputPipe;
+use wasmtime_wasi::WasiCtxBuilder;
+
+/// Source of guest stdin
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StdinConfig {
+    /// Inherit host stdin
+    #[default]
+    Inherit,
+    /// Empty stdin
+    Null,
+    /// Contents of a file, shared by all instances
+    File(PathBuf),
+    /// Contents of `{index}` file within a directory, empty if the file does not exist
+    PerInstanceDir(PathBuf),
+}
+
+impl FromStr for StdinConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "inherit" => Ok(Self::Inherit),
+            None if s == "null" => Ok(Self::Null),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(path.into())),
+            Some(("per-instance-dir", path)) if !path.is_empty() => {
+                Ok(Self::PerInstanceDir(path.into()))
+            }
+            _ => bail!(
+                "invalid stdin `{s}`, expected `inherit`, `null`, `file:PATH` or `per-instance-dir:PATH`"
+            ),
+        }
+    }
+}
+
+impl Display for StdinConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inherit => write!(f, "inherit"),
+            Self::Null => write!(f, "null"),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::PerInstanceDir(path) => write!(f, "per-instance-dir:{}", path.display()),
+        }
+    }
+}
+
+/// Guest stdin source with shared contents loaded
+pub struct Stdin {
+    config: StdinConfig,
+    contents: Bytes,
+}
+
+impl Stdin {
+    /// Loads contents of [`StdinConfig::File`], if used
+    pub async fn new(config: StdinConfig) -> anyhow::Result<Self> {
+        let contents = if let StdinConfig::File(path) = &config {
+            fs::read(path)
+                .await
+                .with_context(|| format!("failed to read `{}`", path.display()))?
+                .into()
+        } else {
+            Bytes::new()
+        };
+        Ok(Self { config, contents })
+    }
+
+    /// Configures stdin of instance at `index` on the builder
+    pub async fn configure(
+        &self,
+        index: usize,
+        builder: &mut WasiCtxBuilder,
+    ) -> anyhow::Result<()> {
+        match &self.config {
+            StdinConfig::Inherit => {
+                builder.inherit_stdin();
+            }
+            StdinConfig::Null => {}
+            StdinConfig::File(..) => {
+                builder.stdin(MemoryInputPipe::new(self.contents.clone()));
+            }
+            StdinConfig::PerInstanceDir(dir) => {
+                let path = dir.join(index.to_string());
+                let contents = match fs::read(&path).await {
+                    Ok(contents) => contents,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::default(),
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("failed to read `{}`", path.display()))
+                    }
+                };
+                builder.stdin(MemoryInputPipe::new(contents));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use core::time::Duration;
+
+use std::env;
+use std::os::linux::net::SocketAddrExt as _;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+use anyhow::{bail, Context as _};
+use clap::ValueEnum;
+use tokio::fs;
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+/// Way sandbox cgroups are created
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CgroupDriver {
+    /// Write cgroupfs directly within the cgroup of the process
+    #[default]
+    Cgroupfs,
+    /// Move the process into a transient systemd scope with delegation enabled
+    /// and write cgroupfs within it
+    Systemd,
+}
+
+/// Moves the process into a new transient systemd scope `unit` with delegation enabled.
+///
+/// The system manager is used if running as root and the user manager otherwise
+pub async fn start_transient_scope(unit: &str, pid: u32) -> anyhow::Result<()> {
+    let conn = if unsafe { libc::geteuid() } == 0 {
+        Connection::system().await
+    } else {
+        Connection::session().await
+    }
+    .context("failed to connect to D-Bus")?;
+    let properties = vec![
+        ("Description", Value::from("cgwasm")),
+        ("PIDs", Value::from(vec![pid])),
+        ("Delegate", Value::from(true)),
+    ];
+    let aux: Vec<(&str, Vec<(&str, Value)>)> = Vec::new();
+    conn.call_method(
+        Some("org.freedesktop.systemd1"),
+        "/org/freedesktop/systemd1",
+        Some("org.freedesktop.systemd1.Manager"),
+        "StartTransientUnit",
+        &(unit, "fail", properties, aux),
+    )
+    .await
+    .with_context(|| format!("failed to start `{unit}` transient unit"))?;
+
+    // the process is moved asynchronously once the job completes
+    let suffix = format!("/{unit}");
+    for _ in 0..500 {
+        let cg = fs::read_to_string("/proc/self/cgroup")
+            .await
+            .context("failed to read `/proc/self/cgroup`")?;
+        if cg.trim().ends_with(&suffix) {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    bail!("process was not moved into `{unit}` in time")
+}
+
+/// Sends `state` to the service manager, if `NOTIFY_SOCKET` is set
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let res = (|| {
+        let addr = if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+            SocketAddr::from_abstract_name(name)?
+        } else {
+            SocketAddr::from_pathname(&path)?
+        };
+        let sock = UnixDatagram::unbound()?;
+        sock.send_to_addr(state.as_bytes(), &addr)
+    })();
+    if let Err(err) = res {
+        eprintln!("failed to notify service manager of `{state}`: {err}");
+    }
+}
+
+/// Returns the interval watchdog keep-alive notifications must be sent at,
+/// if the service manager expects them from this process
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// Sends watchdog keep-alive notifications every `interval` until the task is aborted
+pub async fn watchdog(interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
@@ -0,0 +1,283 @@
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use core::time::Duration;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::fs;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::usage::Usage;
+use crate::{cgroup, Outcome};
+
+const INSTANTIATING: u8 = 1;
+const RUNNING: u8 = 2;
+const FINISHED: u8 = 3;
+const CANCELLED: u8 = 4;
+const TRAPPED: u8 = 5;
+
+const STATES: [&str; 6] = [
+    "pending",
+    "instantiating",
+    "running",
+    "finished",
+    "cancelled",
+    "trapped",
+];
+
+#[derive(Debug, Default)]
+struct Instance {
+    /// Index into [`STATES`], pending by default
+    state: AtomicU8,
+    /// Number of instantiation retries on instance pool exhaustion
+    retries: AtomicU32,
+    /// Number of restarts within the sandbox
+    restarts: AtomicU32,
+}
+
+/// Lifecycle of all instances, updated by sandboxes, rendered by [`Top::run`]
+/// and reported by [`Dump::run`]
+#[derive(Debug)]
+pub struct Top {
+    instances: Box<[Instance]>,
+}
+
+impl Top {
+    pub fn new(count: usize) -> Self {
+        Self {
+            instances: (0..count).map(|_| Instance::default()).collect(),
+        }
+    }
+
+    fn set(&self, index: usize, state: u8) {
+        if let Some(instance) = self.instances.get(index) {
+            instance.state.store(state, Ordering::Relaxed);
+        }
+    }
+
+    pub fn set_instantiating(&self, index: usize) {
+        self.set(index, INSTANTIATING);
+    }
+
+    pub fn set_running(&self, index: usize) {
+        self.set(index, RUNNING);
+    }
+
+    /// Records a retry of the instantiation of instance at `index`
+    pub fn retried(&self, index: usize) {
+        if let Some(instance) = self.instances.get(index) {
+            instance.retries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a restart of instance at `index`
+    pub fn restarted(&self, index: usize) {
+        if let Some(instance) = self.instances.get(index) {
+            instance.restarts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks instance at `index` as done with `outcome`
+    pub fn set_done(&self, index: usize, outcome: &Outcome) {
+        let state = match outcome {
+            Outcome::Success | Outcome::Failure | Outcome::Exit(..) => FINISHED,
+            Outcome::Cancelled => CANCELLED,
+            Outcome::PoolExhausted(..)
+            | Outcome::Error(..)
+            | Outcome::Panicked(..)
+            | Outcome::Wedged => TRAPPED,
+        };
+        self.set(index, state);
+    }
+
+    /// Samples all instances, reading CPU usage from the sandbox cgroups within `cg`
+    /// if `cgroups` is set and store memory from `usage`
+    fn sample(&self, cg: &Path, names: &[String], usage: &Usage, cgroups: bool) -> Samples {
+        let instances = names
+            .iter()
+            .zip(self.instances.iter())
+            .enumerate()
+            .map(|(index, (name, instance))| {
+                let path = cg.join(name);
+                let cpu_usage = cgroups
+                    .then(|| cgroup::read_flat_keyed(path.join("cpu.stat")).ok())
+                    .flatten()
+                    .and_then(|stat| stat.get("usage_usec").copied());
+                Sample {
+                    state: usize::from(instance.state.load(Ordering::Relaxed)),
+                    cpu_usage,
+                    store_memory: usage.get(index).map(|usage| usage.memory),
+                    retries: instance.retries.load(Ordering::Relaxed),
+                    restarts: instance.restarts.load(Ordering::Relaxed),
+                    path,
+                }
+            })
+            .collect();
+        // threaded sandbox cgroups have no `memory` controller, so memory is only accounted
+        // for all of them combined in the domain cgroup
+        let memory = cgroups.then(|| cgroup::memory_current(cg)).flatten();
+        Samples { instances, memory }
+    }
+
+    /// Renders a table of all instances to stderr at `interval` until the task is aborted.
+    ///
+    /// CPU usage is read from the sandbox cgroups within `cg`, if `cgroups` is set, store memory
+    /// from `usage`
+    pub async fn run(
+        self: Arc<Self>,
+        cg: Arc<Path>,
+        names: Vec<String>,
+        usage: Arc<Usage>,
+        cgroups: bool,
+        interval: Duration,
+    ) {
+        let mut interval = tokio::time::interval(interval);
+        let mut last = Instant::now();
+        let mut cpu_usage = vec![None; names.len()];
+        loop {
+            interval.tick().await;
+            let elapsed = last.elapsed().as_micros().max(1) as f64;
+            last = Instant::now();
+            let samples = self.sample(&cg, &names, &usage, cgroups);
+            let mut table = String::from(
+                "\x1b[H\x1b[2J    INSTANCE  STATE          CPU%  STORE MEM  RETRIES  RESTARTS\n",
+            );
+            for (index, sample) in samples.instances.iter().enumerate() {
+                let cpu = match (sample.cpu_usage, cpu_usage[index].replace(sample.cpu_usage)) {
+                    (Some(cpu), Some(Some(prev))) => {
+                        format!("{:.1}", cpu.saturating_sub(prev) as f64 / elapsed * 100.)
+                    }
+                    _ => "-".to_string(),
+                };
+                _ = writeln!(
+                    table,
+                    "{index:>12}  {:<13} {cpu:>6} {:>10} {:>8} {:>9}",
+                    STATES[sample.state],
+                    or_dash(sample.store_memory),
+                    sample.retries,
+                    sample.restarts,
+                );
+            }
+            samples.summarize(&mut table);
+            eprintln!("{table}");
+        }
+    }
+}
+
+/// Sampled state and stats of an instance
+struct Sample {
+    /// Index into [`STATES`]
+    state: usize,
+    /// `usage_usec` of `cpu.stat` of the sandbox cgroup
+    cpu_usage: Option<u64>,
+    store_memory: Option<u64>,
+    retries: u32,
+    restarts: u32,
+    /// Path of the sandbox cgroup
+    path: PathBuf,
+}
+
+/// Samples of all instances taken by [`Top::sample`]
+struct Samples {
+    instances: Vec<Sample>,
+    /// `memory.current` of the domain cgroup of all sandboxes
+    memory: Option<u64>,
+}
+
+impl Samples {
+    /// Returns the number of instances in each state
+    fn counts(&self) -> [usize; STATES.len()] {
+        let mut counts = [0; STATES.len()];
+        for Sample { state, .. } in &self.instances {
+            counts[*state] += 1;
+        }
+        counts
+    }
+
+    /// Writes the number of instances in each state and memory of all sandboxes to `out`
+    fn summarize(&self, out: &mut String) {
+        for (state, count) in STATES.iter().zip(self.counts()) {
+            if count > 0 {
+                _ = write!(out, "{state}: {count}  ");
+            }
+        }
+        if let Some(memory) = self.memory {
+            _ = write!(out, "\nmemory: {memory} bytes");
+        }
+    }
+}
+
+fn or_dash(v: Option<impl ToString>) -> String {
+    v.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+/// State report of all instances, written on `SIGUSR1`
+pub struct Dump {
+    pub top: Arc<Top>,
+    pub cg: Arc<Path>,
+    pub names: Vec<String>,
+    pub usage: Arc<Usage>,
+    /// Whether stats are read from the sandbox cgroups within `cg`
+    pub cgroups: bool,
+    /// Component instance slots of the pooling allocator, if it is used
+    pub pool_slots: Option<u32>,
+    /// File to write the report to, stderr if not set
+    pub path: Option<PathBuf>,
+}
+
+impl Dump {
+    fn render(&self) -> String {
+        let samples = self
+            .top
+            .sample(&self.cg, &self.names, &self.usage, self.cgroups);
+        let mut report = String::from(
+            "    INSTANCE  STATE            CPU USEC  STORE MEM  RETRIES  RESTARTS  CGROUP\n",
+        );
+        for (index, sample) in samples.instances.iter().enumerate() {
+            _ = writeln!(
+                report,
+                "{index:>12}  {:<13} {:>11} {:>10} {:>8} {:>9}  {}",
+                STATES[sample.state],
+                or_dash(sample.cpu_usage),
+                or_dash(sample.store_memory),
+                sample.retries,
+                sample.restarts,
+                sample.path.display(),
+            );
+        }
+        samples.summarize(&mut report);
+        // each live instance occupies a component instance slot of the pool
+        let counts = samples.counts();
+        let live = counts[usize::from(INSTANTIATING)] + counts[usize::from(RUNNING)];
+        if let Some(slots) = self.pool_slots {
+            _ = write!(report, "\npool: {live}/{slots} slots in use");
+        } else {
+            _ = write!(report, "\npool: on-demand allocator, {live} live instances");
+        }
+        report
+    }
+
+    /// Writes the report on every `SIGUSR1` until the task is aborted
+    pub async fn run(self) {
+        let mut user = match signal(SignalKind::user_defined1()) {
+            Ok(user) => user,
+            Err(err) => {
+                eprintln!("failed to install `SIGUSR1` handler: {err}");
+                return;
+            }
+        };
+        while user.recv().await.is_some() {
+            let report = self.render();
+            let Some(path) = &self.path else {
+                eprintln!("{report}");
+                continue;
+            };
+            match fs::write(path, format!("{report}\n")).await {
+                Ok(()) => eprintln!("received SIGUSR1, dumped state to `{}`", path.display()),
+                Err(err) => eprintln!("failed to dump state to `{}`: {err}", path.display()),
+            }
+        }
+    }
+}
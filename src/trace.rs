@@ -0,0 +1,447 @@
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead as _, BufReader, LineWriter, Write as _};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use bytes::{Bytes, BytesMut};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt as _, Full};
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use serde::{Deserialize, Serialize};
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, RngCore, WasiCtxBuilder};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::types::IncomingResponse;
+
+use crate::clocks::ClockConfig;
+
+/// Trace file of the instance with index `INDEX`, of the form `[INDEX@]PATH`, 0 by default
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceFile {
+    pub index: usize,
+    pub path: PathBuf,
+}
+
+impl FromStr for TraceFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('@') {
+            Some((index, path)) if index.parse::<usize>().is_ok() => Ok(Self {
+                index: index.parse()?,
+                path: path.into(),
+            }),
+            _ => Ok(Self {
+                index: 0,
+                path: s.into(),
+            }),
+        }
+    }
+}
+
+impl Display for TraceFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.index, self.path.display())
+    }
+}
+
+/// Host interaction observed by the guest, one JSON object per line of a trace file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    WallClock {
+        secs: u64,
+        nanos: u32,
+    },
+    MonotonicClock {
+        nanos: u64,
+    },
+    /// Base64-encoded bytes of `wasi:random/random`
+    Random {
+        bytes: String,
+    },
+    /// Base64-encoded bytes of `wasi:random/insecure`
+    InsecureRandom {
+        bytes: String,
+    },
+    /// Hexadecimal seed of `wasi:random/insecure-seed`
+    InsecureRandomSeed {
+        seed: String,
+    },
+    /// Response of an outgoing `wasi:http` request with a base64-encoded body
+    HttpResponse {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+    /// Error of an outgoing `wasi:http` request
+    HttpError {
+        error: String,
+    },
+}
+
+/// Queues of events replayed independently of each other, in the order of [`Event::queue`]
+const QUEUES: [&str; 6] = [
+    "wall clock",
+    "monotonic clock",
+    "random",
+    "insecure random",
+    "insecure random seed",
+    "HTTP",
+];
+
+impl Event {
+    fn queue(&self) -> usize {
+        match self {
+            Self::WallClock { .. } => 0,
+            Self::MonotonicClock { .. } => 1,
+            Self::Random { .. } => 2,
+            Self::InsecureRandom { .. } => 3,
+            Self::InsecureRandomSeed { .. } => 4,
+            Self::HttpResponse { .. } | Self::HttpError { .. } => 5,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queues {
+    events: [VecDeque<Event>; QUEUES.len()],
+    exhausted: [bool; QUEUES.len()],
+}
+
+enum Mode {
+    Record(Mutex<LineWriter<File>>),
+    Replay(Mutex<Queues>),
+}
+
+/// Recording or replay of host interactions of a single instance
+pub struct Trace {
+    mode: Mode,
+    index: usize,
+}
+
+impl Trace {
+    /// Creates or truncates the trace file to record interactions to
+    pub fn record(file: &TraceFile) -> anyhow::Result<Arc<Self>> {
+        let f = File::create(&file.path)
+            .with_context(|| format!("failed to create `{}`", file.path.display()))?;
+        Ok(Arc::new(Self {
+            mode: Mode::Record(Mutex::new(LineWriter::new(f))),
+            index: file.index,
+        }))
+    }
+
+    /// Loads the trace file to replay interactions from
+    pub fn replay(file: &TraceFile) -> anyhow::Result<Arc<Self>> {
+        let f = File::open(&file.path)
+            .with_context(|| format!("failed to open `{}`", file.path.display()))?;
+        let mut queues = Queues::default();
+        for (i, line) in BufReader::new(f).lines().enumerate() {
+            let line = line.with_context(|| format!("failed to read `{}`", file.path.display()))?;
+            let event: Event = serde_json::from_str(&line).with_context(|| {
+                format!(
+                    "failed to parse line {} of `{}`",
+                    i + 1,
+                    file.path.display()
+                )
+            })?;
+            queues.events[event.queue()].push_back(event);
+        }
+        Ok(Arc::new(Self {
+            mode: Mode::Replay(Mutex::new(queues)),
+            index: file.index,
+        }))
+    }
+
+    fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay(..))
+    }
+
+    /// Appends `event` to the trace file, failures are reported but do not affect the guest
+    fn write(&self, event: &Event) {
+        let Mode::Record(file) = &self.mode else {
+            return;
+        };
+        let mut buf = match serde_json::to_vec(event) {
+            Ok(buf) => buf,
+            Err(err) => {
+                eprintln!("failed to encode trace event: {err}");
+                return;
+            }
+        };
+        buf.push(b'\n');
+        let mut file = file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = file.write_all(&buf) {
+            eprintln!("failed to write trace event: {err}");
+        }
+    }
+
+    /// Returns the next replayed event of `queue`, reporting exhaustion once
+    fn pop(&self, queue: usize) -> Option<Event> {
+        let Mode::Replay(queues) = &self.mode else {
+            return None;
+        };
+        let mut queues = queues.lock().unwrap_or_else(|err| err.into_inner());
+        let event = queues.events[queue].pop_front();
+        if event.is_none() && !queues.exhausted[queue] {
+            queues.exhausted[queue] = true;
+            eprintln!(
+                "instance {} exhausted replayed {} events",
+                self.index, QUEUES[queue]
+            );
+        }
+        event
+    }
+
+    /// Configures recording or replay of `clock` clocks starting at `epoch`
+    /// and of randomness seeded by `seed`, if set, on the builder
+    pub fn configure(
+        self: &Arc<Self>,
+        clock: ClockConfig,
+        epoch: Duration,
+        seed: Option<u64>,
+        builder: &mut WasiCtxBuilder,
+    ) {
+        builder
+            .wall_clock(WallClock {
+                clock: clock.wall_clock(epoch),
+                trace: Arc::clone(self),
+                last: Mutex::new(epoch),
+            })
+            .monotonic_clock(MonotonicClock {
+                clock: clock.monotonic_clock(),
+                trace: Arc::clone(self),
+                last: AtomicU64::default(),
+            })
+            .secure_random(Random {
+                rng: seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+                trace: Arc::clone(self),
+                insecure: false,
+            })
+            .insecure_random(Random {
+                rng: seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+                trace: Arc::clone(self),
+                insecure: true,
+            });
+        let seed = match self.pop(4) {
+            Some(Event::InsecureRandomSeed { seed }) => u128::from_str_radix(&seed, 16).ok(),
+            _ => None,
+        };
+        let seed = seed.unwrap_or_else(|| {
+            let seed = rand::random();
+            self.write(&Event::InsecureRandomSeed {
+                seed: format!("{seed:x}"),
+            });
+            seed
+        });
+        builder.insecure_random_seed(seed);
+    }
+
+    /// Returns the replayed result of an outgoing HTTP request, if replaying
+    pub fn replay_http(
+        &self,
+        between_bytes_timeout: Duration,
+    ) -> Option<Result<IncomingResponse, ErrorCode>> {
+        if !self.is_replay() {
+            return None;
+        }
+        let (status, headers, body) = match self.pop(5) {
+            Some(Event::HttpResponse {
+                status,
+                headers,
+                body,
+            }) => (status, headers, body),
+            Some(Event::HttpError { error }) => {
+                return Some(Err(ErrorCode::InternalError(Some(error))))
+            }
+            _ => {
+                return Some(Err(ErrorCode::InternalError(Some(
+                    "trace exhausted".into(),
+                ))))
+            }
+        };
+        let body = BASE64.decode(body).unwrap_or_default();
+        let mut resp = hyper::Response::builder().status(status);
+        for (name, value) in headers {
+            resp = resp.header(name, value);
+        }
+        let resp = resp
+            .body(Full::new(Bytes::from(body)).map_err(|e| match e {}).boxed())
+            .map_err(|err| ErrorCode::InternalError(Some(err.to_string())));
+        Some(resp.map(|resp| IncomingResponse {
+            resp,
+            worker: None,
+            between_bytes_timeout,
+        }))
+    }
+
+    /// Buffers the body of the response `res` and records it, failing if the body stalls
+    /// for longer than the between-bytes timeout of the response
+    pub async fn record_http(
+        &self,
+        res: Result<IncomingResponse, ErrorCode>,
+    ) -> Result<IncomingResponse, ErrorCode> {
+        let res = match res {
+            Ok(mut res) => {
+                let (parts, body) = res.resp.into_parts();
+                collect(body, res.between_bytes_timeout).await.map(|body| {
+                    self.write(&Event::HttpResponse {
+                        status: parts.status.as_u16(),
+                        headers: parts
+                            .headers
+                            .iter()
+                            .map(|(name, value)| {
+                                let value = String::from_utf8_lossy(value.as_bytes());
+                                (name.to_string(), value.into_owned())
+                            })
+                            .collect(),
+                        body: BASE64.encode(&body),
+                    });
+                    res.resp = hyper::Response::from_parts(
+                        parts,
+                        Full::new(body).map_err(|e| match e {}).boxed(),
+                    );
+                    res
+                })
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &res {
+            self.write(&Event::HttpError {
+                error: err.to_string(),
+            });
+        }
+        res
+    }
+}
+
+/// Collects the data of `body`, failing if no frame is received within `timeout`
+async fn collect(
+    mut body: BoxBody<Bytes, ErrorCode>,
+    timeout: Duration,
+) -> Result<Bytes, ErrorCode> {
+    let mut buf = BytesMut::new();
+    loop {
+        match tokio::time::timeout(timeout, body.frame()).await {
+            Ok(Some(Ok(frame))) => {
+                if let Ok(data) = frame.into_data() {
+                    buf.extend_from_slice(&data);
+                }
+            }
+            Ok(Some(Err(err))) => return Err(err),
+            Ok(None) => return Ok(buf.freeze()),
+            Err(..) => return Err(ErrorCode::ConnectionReadTimeout),
+        }
+    }
+}
+
+struct WallClock<C> {
+    clock: C,
+    trace: Arc<Trace>,
+    /// Last replayed time, repeated once the trace is exhausted
+    last: Mutex<Duration>,
+}
+
+impl<C: HostWallClock> HostWallClock for WallClock<C> {
+    fn resolution(&self) -> Duration {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> Duration {
+        let mut last = self.last.lock().unwrap_or_else(|err| err.into_inner());
+        if self.trace.is_replay() {
+            if let Some(Event::WallClock { secs, nanos }) = self.trace.pop(0) {
+                *last = Duration::new(secs, nanos);
+            }
+            return *last;
+        }
+        let now = self.clock.now();
+        self.trace.write(&Event::WallClock {
+            secs: now.as_secs(),
+            nanos: now.subsec_nanos(),
+        });
+        now
+    }
+}
+
+struct MonotonicClock<C> {
+    clock: C,
+    trace: Arc<Trace>,
+    /// Last replayed time, repeated once the trace is exhausted
+    last: AtomicU64,
+}
+
+impl<C: HostMonotonicClock> HostMonotonicClock for MonotonicClock<C> {
+    fn resolution(&self) -> u64 {
+        self.clock.resolution()
+    }
+
+    fn now(&self) -> u64 {
+        if self.trace.is_replay() {
+            if let Some(Event::MonotonicClock { nanos }) = self.trace.pop(1) {
+                self.last.store(nanos, Ordering::Relaxed);
+            }
+            return self.last.load(Ordering::Relaxed);
+        }
+        let nanos = self.clock.now();
+        self.trace.write(&Event::MonotonicClock { nanos });
+        nanos
+    }
+}
+
+struct Random {
+    rng: StdRng,
+    trace: Arc<Trace>,
+    insecure: bool,
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    // bytes missing from the trace are zero
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if self.trace.is_replay() {
+            dest.fill(0);
+            let bytes = match self.trace.pop(if self.insecure { 3 } else { 2 }) {
+                Some(Event::Random { bytes } | Event::InsecureRandom { bytes }) => {
+                    BASE64.decode(bytes).unwrap_or_default()
+                }
+                _ => return,
+            };
+            let n = bytes.len().min(dest.len());
+            dest[..n].copy_from_slice(&bytes[..n]);
+            return;
+        }
+        self.rng.fill_bytes(dest);
+        let bytes = BASE64.encode(&*dest);
+        self.trace.write(&if self.insecure {
+            Event::InsecureRandom { bytes }
+        } else {
+            Event::Random { bytes }
+        });
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use wasmtime::{ResourceLimiter, StoreLimits};
+
+/// Sizes of the linear memories and tables of a store, as granted by its resource limiter.
+///
+/// Memories and tables never shrink, so the sizes are also the peak of the store
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct StoreUsage {
+    /// Total size of linear memories in bytes
+    pub memory: u64,
+    /// Total number of table elements
+    pub table_elements: u64,
+}
+
+#[derive(Debug, Default)]
+struct Instance {
+    memory: AtomicU64,
+    table_elements: AtomicU64,
+}
+
+/// Store usage of all instances, updated by their [`Limiter`]s and sampled by `--tui`
+#[derive(Debug)]
+pub struct Usage {
+    instances: Box<[Instance]>,
+}
+
+impl Usage {
+    pub fn new(count: usize) -> Self {
+        Self {
+            instances: (0..count).map(|_| Instance::default()).collect(),
+        }
+    }
+
+    /// Returns the store usage of instance at `index`
+    pub fn get(&self, index: usize) -> Option<StoreUsage> {
+        let instance = self.instances.get(index)?;
+        Some(StoreUsage {
+            memory: instance.memory.load(Ordering::Relaxed),
+            table_elements: instance.table_elements.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Resource limiter of the store of instance at `index`, enforcing `limits`
+/// and recording granted growth in [`Usage`]
+pub struct Limiter {
+    pub limits: StoreLimits,
+    /// Total size in bytes all linear memories of the store may grow to, if limited
+    pub memory_budget: Option<u64>,
+    usage: Arc<Usage>,
+    index: usize,
+    /// Memory growth in bytes of the last granted `memory.grow`, reverted if it fails
+    memory_growth: u64,
+    /// Table growth in elements of the last granted `table.grow`, reverted if it fails
+    table_growth: u64,
+}
+
+impl Limiter {
+    pub fn new(usage: Arc<Usage>, index: usize) -> Self {
+        Self {
+            limits: StoreLimits::default(),
+            memory_budget: None,
+            usage,
+            index,
+            memory_growth: 0,
+            table_growth: 0,
+        }
+    }
+
+    /// Clears the usage recorded for the store, once it is dropped on restart
+    pub fn reset(&mut self) {
+        if let Some(instance) = self.instance() {
+            instance.memory.store(0, Ordering::Relaxed);
+            instance.table_elements.store(0, Ordering::Relaxed);
+        }
+        self.memory_growth = 0;
+        self.table_growth = 0;
+    }
+
+    /// Returns the store usage recorded so far
+    pub fn usage(&self) -> StoreUsage {
+        self.usage.get(self.index).unwrap_or_default()
+    }
+
+    fn instance(&self) -> Option<&Instance> {
+        self.usage.instances.get(self.index)
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let delta = desired
+            .saturating_sub(current)
+            .try_into()
+            .unwrap_or(u64::MAX);
+        let within_budget = self
+            .memory_budget
+            .is_none_or(|budget| self.usage().memory.saturating_add(delta) <= budget);
+        let allow = self.limits.memory_growing(current, desired, maximum)? && within_budget;
+        self.memory_growth = 0;
+        if let (true, Some(instance)) = (allow, self.instance()) {
+            instance.memory.fetch_add(delta, Ordering::Relaxed);
+            self.memory_growth = delta;
+        }
+        Ok(allow)
+    }
+
+    // failures are reported right after the growth granted by `memory_growing`
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        let delta = core::mem::take(&mut self.memory_growth);
+        if let Some(instance) = self.instance() {
+            instance.memory.fetch_sub(delta, Ordering::Relaxed);
+        }
+        self.limits.memory_grow_failed(error)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let allow = self.limits.table_growing(current, desired, maximum)?;
+        self.table_growth = 0;
+        if let (true, Some(instance)) = (allow, self.instance()) {
+            let delta = desired
+                .saturating_sub(current)
+                .try_into()
+                .unwrap_or(u64::MAX);
+            instance.table_elements.fetch_add(delta, Ordering::Relaxed);
+            self.table_growth = delta;
+        }
+        Ok(allow)
+    }
+
+    // failures are reported right after the growth granted by `table_growing`, except for
+    // overflows of the table size, which elements bounded by the limits cannot reach
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        let delta = core::mem::take(&mut self.table_growth);
+        if let Some(instance) = self.instance() {
+            instance.table_elements.fetch_sub(delta, Ordering::Relaxed);
+        }
+        self.limits.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
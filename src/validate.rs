@@ -0,0 +1,159 @@
+use core::fmt::Write as _;
+
+use anyhow::{bail, Context as _};
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::{Component, Linker, LinkerInstance, ResourceType};
+use wasmtime::Engine;
+
+/// Behavior of stubs of imports not provided by the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Stub {
+    /// Calls of stubbed functions trap
+    Trap,
+    /// Calls of stubbed functions without results return, others trap
+    Noop,
+}
+
+/// Defines `item` as `name` in `linker` with `mode` stubs
+fn stub<T>(
+    engine: &Engine,
+    linker: &mut LinkerInstance<'_, T>,
+    name: &str,
+    item: &ComponentItem,
+    mode: Stub,
+) -> anyhow::Result<()> {
+    match item {
+        ComponentItem::ComponentFunc(..) => {
+            let msg = format!("`{name}` is not provided by the host");
+            linker.func_new(name, move |_, _, results| {
+                if mode == Stub::Noop && results.is_empty() {
+                    Ok(())
+                } else {
+                    bail!("{msg}")
+                }
+            })
+        }
+        ComponentItem::Resource(..) => {
+            linker.resource(name, ResourceType::host::<()>(), |_, _| Ok(()))
+        }
+        ComponentItem::ComponentInstance(ty) => {
+            let mut linker = linker.instance(name)?;
+            for (name, item) in ty.exports(engine) {
+                stub(engine, &mut linker, name, &item, mode)?;
+            }
+            Ok(())
+        }
+        ComponentItem::CoreFunc(..)
+        | ComponentItem::Module(..)
+        | ComponentItem::Component(..)
+        | ComponentItem::Type(..) => Ok(()),
+    }
+}
+
+/// Whether `name` is defined in `linker`, though possibly with a mismatching type
+pub fn is_defined<T>(linker: &Linker<T>, name: &str) -> bool {
+    let mut probe = linker.clone();
+    probe.allow_shadowing(false);
+    probe.root().instance(name).is_err()
+}
+
+/// Returns imports of `component` not satisfied by `linker` with the reasons.
+///
+/// Imports are type-checked in order, so each one is checked with preceding unsatisfied
+/// imports and all following imports stubbed, which leaves it the only one to fail.
+/// Following imports may only use resources of preceding ones, so stubbing them does not
+/// affect the checked import
+fn unsatisfied<T>(
+    linker: &Linker<T>,
+    component: &Component,
+) -> anyhow::Result<Vec<(String, ComponentItem, String)>> {
+    if linker.instantiate_pre(component).is_ok() {
+        return Ok(Vec::new());
+    }
+    let engine = linker.engine().clone();
+    let ty = component.component_type();
+    let imports: Vec<_> = ty.imports(&engine).collect();
+
+    let mut probe = linker.clone();
+    probe.allow_shadowing(true);
+    let mut unsatisfied = Vec::new();
+    for (i, (name, item)) in imports.iter().enumerate() {
+        let mut check = probe.clone();
+        for (name, item) in &imports[i + 1..] {
+            stub(&engine, &mut check.root(), name, item, Stub::Trap)?;
+        }
+        let Err(err) = check.instantiate_pre(component) else {
+            continue;
+        };
+        let reason = err
+            .chain()
+            .skip(1)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(": ");
+        unsatisfied.push((name.to_string(), item.clone(), reason));
+        stub(&engine, &mut probe.root(), name, item, Stub::Trap)?;
+    }
+    Ok(unsatisfied)
+}
+
+/// Stubs imports of `component` not provided by `linker` with `mode` stubs,
+/// returning their names.
+///
+/// Imports provided by `linker` with mismatching types are refused,
+/// since stubs would replace the host implementation
+pub fn stub_missing<T>(
+    linker: &mut Linker<T>,
+    component: &Component,
+    mode: Stub,
+) -> anyhow::Result<Vec<String>> {
+    let engine = linker.engine().clone();
+    let unsatisfied =
+        unsatisfied(linker, component).context("failed to find unsatisfied imports")?;
+    let mut stubbed = Vec::with_capacity(unsatisfied.len());
+    for (name, item, reason) in unsatisfied {
+        if is_defined(linker, &name) {
+            bail!("component import `{name}` does not match the host: {reason}")
+        }
+        stub(&engine, &mut linker.root(), &name, &item, mode)?;
+        stubbed.push(name);
+    }
+    Ok(stubbed)
+}
+
+/// Validates that all imports of `component` are satisfied by `linker`
+/// and, if `run` is set, that it exports `wasi:cli/run`, describing all mismatches
+/// as a diff on failure
+pub fn validate<T>(linker: &Linker<T>, component: &Component, run: bool) -> anyhow::Result<()> {
+    let engine = linker.engine();
+    let ty = component.component_type();
+    let mut diff = String::new();
+    for (name, _, reason) in unsatisfied(linker, component)? {
+        if is_defined(linker, &name) {
+            _ = write!(diff, "\n  ~ import `{name}`: {reason}");
+        } else {
+            _ = write!(diff, "\n  - import `{name}`: not provided by the host");
+        }
+    }
+    let exports: Vec<_> = ty.exports(engine).map(|(name, _)| name).collect();
+    if run
+        && !exports
+            .iter()
+            .any(|name| name.starts_with("wasi:cli/run@0.2."))
+    {
+        _ = write!(
+            diff,
+            "\n  - export `wasi:cli/run@0.2`: required by `wasi:cli/command`"
+        );
+        for name in exports {
+            _ = write!(
+                diff,
+                "\n  + export `{name}`: not part of `wasi:cli/command`"
+            );
+        }
+    }
+    if !diff.is_empty() {
+        bail!("component does not match the host (`-` missing, `~` mismatching, `+` extra):{diff}")
+    }
+    Ok(())
+}
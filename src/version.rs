@@ -0,0 +1,170 @@
+use std::process::ExitCode;
+
+use anyhow::Context as _;
+use clap::ValueEnum as _;
+use serde::Serialize;
+
+use crate::{
+    new_linker, use_pooling_allocator_by_default, validate, WasmProposal, DEFAULT_MAX_MEMORY_SIZE,
+    MULTI_MEMORY_MAX_MEMORIES, POOLING_SLOTS_PER_INSTANCE,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Print versions, WebAssembly proposals, host interfaces and pooling allocator defaults as JSON
+    #[clap(long)]
+    features: bool,
+}
+
+/// Host interfaces [`crate::new_linker`] is expected to link with their WIT versions,
+/// only ones actually defined by the linker are reported
+const INTERFACES: &[&str] = &[
+    "wasi:cli/environment@0.2.2",
+    "wasi:cli/exit@0.2.2",
+    "wasi:cli/stdin@0.2.2",
+    "wasi:cli/stdout@0.2.2",
+    "wasi:cli/stderr@0.2.2",
+    "wasi:cli/terminal-input@0.2.2",
+    "wasi:cli/terminal-output@0.2.2",
+    "wasi:cli/terminal-stdin@0.2.2",
+    "wasi:cli/terminal-stdout@0.2.2",
+    "wasi:cli/terminal-stderr@0.2.2",
+    "wasi:clocks/monotonic-clock@0.2.2",
+    "wasi:clocks/wall-clock@0.2.2",
+    "wasi:filesystem/preopens@0.2.2",
+    "wasi:filesystem/types@0.2.2",
+    "wasi:io/error@0.2.2",
+    "wasi:io/poll@0.2.2",
+    "wasi:io/streams@0.2.2",
+    "wasi:random/insecure@0.2.2",
+    "wasi:random/insecure-seed@0.2.2",
+    "wasi:random/random@0.2.2",
+    "wasi:sockets/instance-network@0.2.2",
+    "wasi:sockets/ip-name-lookup@0.2.2",
+    "wasi:sockets/network@0.2.2",
+    "wasi:sockets/tcp@0.2.2",
+    "wasi:sockets/tcp-create-socket@0.2.2",
+    "wasi:sockets/udp@0.2.2",
+    "wasi:sockets/udp-create-socket@0.2.2",
+    "wasi:http/outgoing-handler@0.2.2",
+    "wasi:http/types@0.2.2",
+    "wasi:keyvalue/atomics@0.2.0-draft",
+    "wasi:keyvalue/batch@0.2.0-draft",
+    "wasi:keyvalue/store@0.2.0-draft",
+    "wasi:config/store@0.2.0-draft",
+    "wasi:logging/logging@0.1.0-draft",
+    "wasi:messaging/consumer@0.2.0-draft",
+    "wasi:messaging/producer@0.2.0-draft",
+    "wasi:messaging/types@0.2.0-draft",
+    "wasi:blobstore/blobstore@0.2.0-draft",
+    "wasi:blobstore/container@0.2.0-draft",
+    "wasi:blobstore/types@0.2.0-draft",
+    #[cfg(feature = "wasi-nn")]
+    "wasi:nn/errors@0.2.0-rc-2024-10-28",
+    #[cfg(feature = "wasi-nn")]
+    "wasi:nn/graph@0.2.0-rc-2024-10-28",
+    #[cfg(feature = "wasi-nn")]
+    "wasi:nn/inference@0.2.0-rc-2024-10-28",
+    #[cfg(feature = "wasi-nn")]
+    "wasi:nn/tensor@0.2.0-rc-2024-10-28",
+    "cgwasm:instance/lifecycle@0.1.0",
+    "cgwasm:instance/metadata@0.1.0",
+    "cgwasm:cluster/pubsub@0.1.0",
+];
+
+#[derive(Debug, Serialize)]
+struct Proposal {
+    name: String,
+    /// Whether the proposal is enabled without `--wasm-features`
+    default: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Pooling {
+    /// Whether the pooling allocator is used on this host
+    default: bool,
+    slots_per_instance: u32,
+    max_memory_size: usize,
+    multi_memory_max_memories: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct Features {
+    version: &'static str,
+    wasmtime: &'static str,
+    proposals: Vec<Proposal>,
+    interfaces: Vec<&'static str>,
+    nn_backends: Vec<&'static str>,
+    pooling: Pooling,
+}
+
+/// Returns a module exercising `proposal` in text format
+fn probe(proposal: WasmProposal) -> &'static str {
+    match proposal {
+        WasmProposal::Memory64 => "(module (memory i64 1))",
+        WasmProposal::MultiMemory => "(module (memory 1) (memory 1))",
+        WasmProposal::Threads => "(module (memory 1 1 shared))",
+        WasmProposal::RelaxedSimd => {
+            "(module (func (param v128) (result v128) \
+            local.get 0 i32x4.relaxed_trunc_f32x4_s))"
+        }
+        WasmProposal::TailCall => "(module (func return_call 0))",
+        WasmProposal::Gc => "(module (type (struct)))",
+    }
+}
+
+/// Prints the version of cgwasm and, with `--features`, its capabilities as JSON
+pub fn run(Args { features }: Args) -> anyhow::Result<ExitCode> {
+    if !features {
+        println!(
+            "cgwasm {} (wasmtime {})",
+            env!("CARGO_PKG_VERSION"),
+            wasmtime_environ::VERSION
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // host interfaces are linked asynchronously, which does not affect enabled proposals
+    let mut config = wasmtime::Config::default();
+    config.async_support(true);
+    let engine = wasmtime::Engine::new(&config).context("failed to construct engine")?;
+    let proposals = WasmProposal::value_variants()
+        .iter()
+        .filter_map(|proposal| {
+            let name = proposal.to_possible_value()?.get_name().to_string();
+            let wasm = wat::parse_str(probe(*proposal)).ok()?;
+            Some(Proposal {
+                name,
+                default: wasmtime::Module::validate(&engine, &wasm).is_ok(),
+            })
+        })
+        .collect();
+    let linker = new_linker(&engine)?;
+    let interfaces = INTERFACES
+        .iter()
+        .copied()
+        .filter(|name| validate::is_defined(&linker, name))
+        .collect();
+    let features = Features {
+        version: env!("CARGO_PKG_VERSION"),
+        wasmtime: wasmtime_environ::VERSION,
+        proposals,
+        interfaces,
+        nn_backends: [
+            #[cfg(feature = "onnx")]
+            "onnx",
+            #[cfg(feature = "openvino")]
+            "openvino",
+        ]
+        .to_vec(),
+        pooling: Pooling {
+            default: matches!(use_pooling_allocator_by_default(), Ok(true)),
+            slots_per_instance: POOLING_SLOTS_PER_INSTANCE,
+            max_memory_size: DEFAULT_MAX_MEMORY_SIZE,
+            multi_memory_max_memories: MULTI_MEMORY_MAX_MEMORIES,
+        },
+    };
+    let json = serde_json::to_string_pretty(&features).context("failed to encode features")?;
+    println!("{json}");
+    Ok(ExitCode::SUCCESS)
+}